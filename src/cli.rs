@@ -0,0 +1,256 @@
+// Subcommand CLI. Tanpa argumen binary menjalankan server seperti biasa:
+//
+//   vector_tile_services                                  -> jalankan server
+//   vector_tile_services cache purge <layer> [--version v] -> hapus tile layer di S3
+//   vector_tile_services prepare [--verify] [opsi batch]  -> siapkan / cek geom_3857 (web::prep)
+//   vector_tile_services prepare --dry-run [--json]       -> tampilkan rencana tanpa DDL (web::plan)
+//   vector_tile_services migrate status|up|down [--to N]  -> migrasi objek server (web::db)
+use std::str::FromStr;
+use std::time::Duration;
+use sqlx::PgPool;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use crate::web::cache::{S3Config, purge_layer};
+use crate::web::{config, db, plan, prep, utils};
+use crate::web::backfill::BackfillConfig;
+
+pub const USAGE: &str = "\
+Usage:
+  vector_tile_services                                   Start the tile server
+  vector_tile_services cache purge <layer> [--version <v>]
+                                                         Delete a layer's S3 tile objects
+                                                         (all versions unless --version is given)
+  vector_tile_services prepare [--batch-size <n>] [--delay-ms <ms>] [--restart]
+                                                         Prepare geom_3857 for every layer, backfilling
+                                                         in primary key batches; resumes an interrupted
+                                                         run unless --restart is given
+  vector_tile_services prepare --verify                  Report rows where geom_3857 differs from
+                                                         the source geometry (exit code 1 if any)
+  vector_tile_services prepare --dry-run [--json]        Show the columns, indexes, triggers and function
+                                                         migrations that would be created, without
+                                                         executing anything
+  vector_tile_services migrate status                    List server migrations and whether they are
+                                                         applied (exit code 1 if any are pending or changed)
+  vector_tile_services migrate up [--to <version>]       Apply pending migrations (up to a version)
+  vector_tile_services migrate down [--to <version>]     Roll back the last migration, or every migration
+                                                         above a version (--to 0 rolls back all)";
+
+pub enum Command {
+    Serve,
+    CachePurge { layer: String, version: Option<String> },
+    Prepare { verify: bool, dry_run: bool, json: bool, backfill: BackfillConfig },
+    MigrateStatus,
+    MigrateUp { to: Option<i32> },
+    MigrateDown { to: Option<i32> },
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+    match args.as_slice() {
+        [] => Ok(Command::Serve),
+        ["cache", "purge", layer, rest @ ..] => {
+            let mut version = None;
+            let mut rest = rest.iter();
+            while let Some(flag) = rest.next() {
+                match *flag {
+                    "--version" => {
+                        version = Some(
+                            rest.next()
+                                .ok_or("--version requires a value")?
+                                .to_string(),
+                        )
+                    }
+                    other => return Err(format!("Unknown option: {}", other)),
+                }
+            }
+            Ok(Command::CachePurge { layer: layer.to_string(), version })
+        }
+        ["prepare", rest @ ..] => {
+            let mut verify = false;
+            let mut dry_run = false;
+            let mut json = false;
+            let mut backfill = BackfillConfig::default();
+            let mut rest = rest.iter();
+            while let Some(flag) = rest.next() {
+                match *flag {
+                    "--verify" => verify = true,
+                    "--dry-run" => dry_run = true,
+                    "--json" => json = true,
+                    "--restart" => backfill.restart = true,
+                    "--batch-size" => {
+                        backfill.batch_size = rest
+                            .next()
+                            .and_then(|v| v.parse().ok())
+                            .filter(|n: &i64| *n > 0)
+                            .ok_or("--batch-size requires a positive number")?
+                    }
+                    "--delay-ms" => {
+                        let ms = rest
+                            .next()
+                            .and_then(|v| v.parse().ok())
+                            .ok_or("--delay-ms requires a number")?;
+                        backfill.delay = Duration::from_millis(ms)
+                    }
+                    other => return Err(format!("Unknown option: {}", other)),
+                }
+            }
+            if verify && dry_run {
+                return Err("--verify and --dry-run cannot be combined".to_string());
+            }
+            if json && !dry_run {
+                return Err("--json requires --dry-run".to_string());
+            }
+            Ok(Command::Prepare { verify, dry_run, json, backfill })
+        }
+        ["migrate", "status"] => Ok(Command::MigrateStatus),
+        ["migrate", direction @ ("up" | "down"), rest @ ..] => {
+            let to = match rest {
+                [] => None,
+                ["--to", version] => Some(
+                    version
+                        .parse()
+                        .ok()
+                        .filter(|v: &i32| *v >= 0)
+                        .ok_or("--to requires a migration version")?,
+                ),
+                ["--to"] => return Err("--to requires a migration version".to_string()),
+                [other, ..] => return Err(format!("Unknown option: {}", other)),
+            };
+            Ok(if *direction == "up" { Command::MigrateUp { to } } else { Command::MigrateDown { to } })
+        }
+        _ => Err(format!("Unknown command: {}", args.join(" "))),
+    }
+}
+
+/// Jalankan subcommand non-server. Mengembalikan exit code.
+pub async fn run(command: Command) -> i32 {
+    match command {
+        Command::Serve => 0,
+        Command::CachePurge { layer, version } => {
+            let config = S3Config::default();
+            match purge_layer(&config, &layer, version.as_deref()).await {
+                Ok(deleted) => {
+                    println!("✓ Purged {} objects for layer {}", deleted, layer);
+                    0
+                }
+                Err(e) => {
+                    eprintln!("✗ Failed to purge layer {}: {}", layer, e);
+                    1
+                }
+            }
+        }
+        Command::Prepare { verify, dry_run, json, backfill } => {
+            if let Err(e) = config::validate() {
+                eprintln!("✗ {}", e);
+                return 1;
+            }
+            // Dry run memakai koneksi read-only: DDL yang tidak sengaja terjalankan akan ditolak server
+            let pool = match connect(dry_run).await {
+                Ok(pool) => pool,
+                Err(e) => {
+                    eprintln!("✗ Failed to connect to database: {}", e);
+                    return 1;
+                }
+            };
+            let result = if dry_run {
+                plan::build_plan(&pool, &backfill).await.map(|p| {
+                    plan::print_plan(&p, json);
+                    true
+                })
+            } else if verify {
+                prep::verify_layers(&pool).await
+            } else {
+                utils::check_and_create_geom_index(&pool, &backfill).await.map(|_| true)
+            };
+            match result {
+                Ok(true) => 0,
+                Ok(false) => 1,
+                Err(e) => {
+                    eprintln!("✗ Prepare failed: {}", e);
+                    1
+                }
+            }
+        }
+        Command::MigrateStatus => {
+            let pool = match connect(true).await {
+                Ok(pool) => pool,
+                Err(e) => {
+                    eprintln!("✗ Failed to connect to database: {}", e);
+                    return 1;
+                }
+            };
+            match db::migration_status(&pool).await {
+                Ok(status) => print_migration_status(&status),
+                Err(e) => {
+                    eprintln!("✗ Failed to read migrations: {}", e);
+                    1
+                }
+            }
+        }
+        Command::MigrateUp { to } | Command::MigrateDown { to } => {
+            let pool = match connect(false).await {
+                Ok(pool) => pool,
+                Err(e) => {
+                    eprintln!("✗ Failed to connect to database: {}", e);
+                    return 1;
+                }
+            };
+            let result = if matches!(command, Command::MigrateUp { .. }) {
+                db::migrate_up(&pool, to).await
+            } else {
+                db::migrate_down(&pool, to).await
+            };
+            match result {
+                Ok(done) if done.is_empty() => {
+                    println!("✓ Nothing to do");
+                    0
+                }
+                Ok(_) => 0,
+                Err(e) => {
+                    eprintln!("✗ Migration failed: {}", e);
+                    1
+                }
+            }
+        }
+    }
+}
+
+
+// Exit code 1 jika ada migrasi yang belum dipasang atau berubah, supaya bisa dipakai di CI
+fn print_migration_status(status: &[(i32, String, db::MigrationState)]) -> i32 {
+    println!("\nMigrations in schema {}\n", db::service_schema());
+    println!("{:<9} {:<26} Status", "Version", "Name");
+    println!("{}", "─".repeat(75));
+    let mut up_to_date = true;
+    for (version, name, state) in status {
+        let state = match state {
+            db::MigrationState::Applied { applied_at } => format!("✓ applied {}", applied_at),
+            db::MigrationState::Pending => {
+                up_to_date = false;
+                "pending".to_string()
+            }
+            db::MigrationState::Modified { applied_at } => {
+                up_to_date = false;
+                format!("✗ changed after it was applied {}", applied_at)
+            }
+            db::MigrationState::Unknown { applied_at } => format!("? applied {}, unknown to this build", applied_at),
+        };
+        println!("{:<9} {:<26} {}", format!("{:03}", version), name, state);
+    }
+    println!();
+    if up_to_date { 0 } else { 1 }
+}
+
+
+async fn connect(read_only: bool) -> Result<PgPool, String> {
+    let db_url = std::env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set".to_string())?;
+    let mut options = PgConnectOptions::from_str(&db_url).map_err(|e| e.to_string())?;
+    if read_only {
+        options = options.options([("default_transaction_read_only", "on")]);
+    }
+    PgPoolOptions::new()
+        .max_connections(2)
+        .connect_with(options)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -1 +1,2 @@
-pub mod web; 
\ No newline at end of file
+pub mod web;
+pub mod cli;
@@ -2,9 +2,11 @@
 use actix_web::{web, App, HttpServer};
 use actix_web::middleware::Logger;
 use sqlx::postgres::PgPoolOptions;
-use log::{info, error};
+use log::{info, warn, error};
 use dotenv::dotenv;
-use vector_tile_services::web::{web_handler, utils, db};
+use vector_tile_services::web::{web_handler, utils, db, versions, ogc, features, wmts, styles, assets, validity, privileges, config};
+use vector_tile_services::web::backfill::BackfillConfig;
+use vector_tile_services::cli;
 use std::time::Duration;
 
 
@@ -12,6 +14,23 @@ use std::time::Duration;
 async fn main() -> std::io::Result<()> {
 
     dotenv().ok();
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::parse(&args) {
+        Ok(cli::Command::Serve) => {}
+        Ok(command) => std::process::exit(cli::run(command).await),
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    // Config layer yang tidak valid bisa membuat layer "none" jatuh ke mode default; jangan jalan
+    if let Err(e) = config::validate() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
 
     let db_url = std::env::var("DATABASE_URL")
         .expect("DATABASE_URL must be set in .env");
@@ -27,9 +46,19 @@ async fn main() -> std::io::Result<()> {
     .expect("Failed to connect to database");
 
 
-    db::run_migrations(&pool)
+    // Role tanpa hak DDL: server jalan read-only, migrasi & persiapan dilewati
+    let capabilities = privileges::init(&pool)
         .await
-        .expect("Failed to run migrations");
+        .expect("Failed to check database privileges");
+    if capabilities.read_only() {
+        if let Err(e) = db::check_migrations(&pool).await {
+            warn!("Failed to read migration status: {}", e);
+        }
+    } else {
+        db::run_migrations(&pool)
+            .await
+            .expect("Failed to run migrations");
+    }
 
 
 
@@ -41,7 +70,6 @@ async fn main() -> std::io::Result<()> {
 
     let host = "0.0.0.0";
 
-    env_logger::init();
     info!("Loading layers... .");
     
 
@@ -55,27 +83,77 @@ async fn main() -> std::io::Result<()> {
     //     Err(e) => error!("Failed to load layers cache: {:?}", e),
     // };
 
-    match utils::check_and_create_geom_index(&pool).await {
-        Ok(()) => {
-            info!("Checking geom success!");
-        }
-        Err(e) => error!("Failed to check geom status: {:?}", e),
-    };
+    // Persiapan geometri (DDL, trigger, backfill) dijalankan lewat `vector_tile_services prepare`.
+    // PREPARE_ON_STARTUP=true: jalankan di background saat startup, tanpa menahan startup.
+    let prepare_on_startup = std::env::var("PREPARE_ON_STARTUP")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(false);
+    if prepare_on_startup {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            match utils::check_and_create_geom_index(&pool, &BackfillConfig::default()).await {
+                Ok(()) => {
+                    info!("Checking geom success!");
+                }
+                Err(e) => error!("Failed to check geom status: {:?}", e),
+            };
+            // Layer yang dimuat selama persiapan mungkin masih on the fly; muat ulang
+            *web_handler::LAYERS_CACHE.write().await = None;
+        });
+    }
 
     
 
 
     
 
+    versions::spawn_refresh_task(pool.clone());
+
     info!("starting server at port {}", port);
     HttpServer::new( move || {
         App::new()
             .wrap(Logger::default())
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::PathConfig::default().error_handler(web_handler::path_error_handler))
+            .app_data(web::QueryConfig::default().error_handler(web_handler::query_error_handler))
             .route("/", web::get().to(web_handler::index))
             .route("/layers", web::get().to(web_handler::get_layers))
+            .route("/layers/status", web::get().to(web_handler::layer_status))
+            .route("/layers/invalid", web::get().to(validity::invalid_summary))
+            .route("/layers/{table_name}/invalid", web::get().to(validity::invalid_features))
             .route("/layer_list", web::get().to(web_handler::layer_list))
+            .route("/health", web::get().to(web_handler::health))
+            .route("/metrics", web::get().to(web_handler::metrics))
+            .route("/layers/{table_name}/features/{feature_id}", web::get().to(features::layer_feature))
+            .route("/layers/{table_name}/identify", web::get().to(features::identify))
+            .route("/admin/cache", web::get().to(web_handler::cache_stats))
+            .route("/admin/cache", web::delete().to(web_handler::flush_cache))
+            .route("/admin/layers/{table_name}/version", web::post().to(web_handler::bump_layer_version))
+            .route("/preview/{table_name}", web::get().to(web_handler::preview))
+            .route("/tiles/{table_name}.json", web::get().to(styles::tilejson))
+            .route("/styles/{table_name}.json", web::get().to(styles::layer_style))
+            .route("/styles/groups/{group}.json", web::get().to(styles::group_style))
+            .route("/fonts.json", web::get().to(assets::font_list))
+            .route("/fonts/{fontstack}/{range}.pbf", web::get().to(assets::glyphs))
+            .route("/sprites/{file}", web::get().to(assets::sprite))
             .route("/tiles/{table_name}/{z}/{x}/{y}.pbf", web::get().to(web_handler::get_vector_tile))
+            .route("/tiles/{tms}/{table_name}/{z}/{x}/{y}.pbf", web::get().to(web_handler::get_tms_vector_tile))
+            // OGC API - Tiles
+            .route("/conformance", web::get().to(ogc::conformance))
+            .route("/collections", web::get().to(ogc::collections))
+            .route("/collections/{table_name}", web::get().to(ogc::collection))
+            .route("/collections/{table_name}/tiles", web::get().to(ogc::collection_tilesets))
+            .route("/collections/{table_name}/tiles/{tms}", web::get().to(ogc::collection_tileset))
+            .route("/collections/{table_name}/tiles/{tms}/{tile_matrix}/{tile_row}/{tile_col}", web::get().to(ogc::collection_tile))
+            // OGC API - Features
+            .route("/collections/{table_name}/items", web::get().to(features::collection_items))
+            .route("/collections/{table_name}/items/{feature_id}", web::get().to(features::collection_item))
+            .route("/tileMatrixSets", web::get().to(ogc::tile_matrix_sets))
+            .route("/tileMatrixSets/{tms}", web::get().to(ogc::tile_matrix_set))
+            // WMTS 1.0.0
+            .route("/wmts", web::get().to(wmts::kvp))
+            .route("/wmts/1.0.0/WMTSCapabilities.xml", web::get().to(wmts::capabilities))
             .default_service(web::route().to(web_handler::not_found))
     })
     .bind((host, port))?
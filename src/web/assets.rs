@@ -0,0 +1,261 @@
+// Font glyph & sprite untuk style yang di-host sendiri (tanpa URL pihak ketiga).
+//
+//   /fonts.json                        daftar font di FONTS_DIR
+//   /fonts/{fontstack}/{range}.pbf     glyph PBF, mis. /fonts/Open Sans Regular,Arial Unicode MS Regular/0-255.pbf
+//   /sprites/{name}[@2x].{json,png}    sprite sheet dari SPRITES_DIR
+//
+// Struktur FONTS_DIR (default "fonts") mengikuti hasil build glyph biasa:
+//   fonts/Open Sans Regular/0-255.pbf, fonts/Open Sans Regular/256-511.pbf, ...
+// Fontstack berisi beberapa font digabung per glyph: font pertama yang punya glyph menang.
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use actix_web::{web, HttpResponse};
+use actix_web::http::StatusCode;
+use log::warn;
+use serde::Deserialize;
+use super::web_handler::json_error;
+
+// Sama dengan header tile
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+
+fn fonts_dir() -> PathBuf {
+    PathBuf::from(std::env::var("FONTS_DIR").unwrap_or_else(|_| "fonts".to_string()))
+}
+
+fn sprites_dir() -> PathBuf {
+    PathBuf::from(std::env::var("SPRITES_DIR").unwrap_or_else(|_| "sprites".to_string()))
+}
+
+// Sprite yang dipasang di style hasil generate (STYLE_SPRITE=nama di SPRITES_DIR, tanpa ekstensi)
+pub(crate) fn style_sprite() -> Option<String> {
+    std::env::var("STYLE_SPRITE").ok().filter(|name| safe_name(name))
+}
+
+
+// Nama file/direktori dari URL: tolak path traversal & file tersembunyi
+fn safe_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\', '\0'])
+}
+
+
+fn asset_response(content_type: &str, body: Vec<u8>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .insert_header(("Cache-Control", CACHE_CONTROL))
+        .body(body)
+}
+
+
+// Baca file tanpa memblok worker actix; None jika file tidak ada
+async fn read_file(path: PathBuf) -> Option<Vec<u8>> {
+    match web::block(move || std::fs::read(&path)).await {
+        Ok(Ok(data)) => Some(data),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Ok(Err(e)) => {
+            warn!("Failed to read asset: {}", e);
+            None
+        }
+        Err(e) => {
+            warn!("Failed to read asset: {}", e);
+            None
+        }
+    }
+}
+
+
+// ---------- Protobuf glyph (glyphs.proto) ----------
+//
+// message glyphs    { repeated fontstack stacks = 1; }
+// message fontstack { string name = 1; string range = 2; repeated glyph glyphs = 3; }
+// message glyph     { uint32 id = 1; bytes bitmap = 2; ... }
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Other,
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut result = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.get(*pos)?;
+        *pos += 1;
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Some(result);
+        }
+    }
+    None
+}
+
+fn read_field<'a>(buf: &'a [u8], pos: &mut usize) -> Option<(u64, Field<'a>)> {
+    let key = read_varint(buf, pos)?;
+    let field = match key & 7 {
+        0 => Field::Varint(read_varint(buf, pos)?),
+        2 => {
+            let len = read_varint(buf, pos)? as usize;
+            let bytes = buf.get(*pos..pos.checked_add(len)?)?;
+            *pos += len;
+            Field::Bytes(bytes)
+        }
+        1 => {
+            *pos += 8;
+            Field::Other
+        }
+        5 => {
+            *pos += 4;
+            Field::Other
+        }
+        _ => return None,
+    };
+    Some((key >> 3, field))
+}
+
+fn fields(buf: &[u8]) -> impl Iterator<Item = (u64, Field<'_>)> {
+    let mut pos = 0;
+    std::iter::from_fn(move || if pos < buf.len() { read_field(buf, &mut pos) } else { None })
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(out, (field << 3) | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+
+// Tambahkan glyph dari satu file PBF yang belum ada di `glyphs` (key = codepoint)
+fn collect_glyphs<'a>(pbf: &'a [u8], glyphs: &mut BTreeMap<u64, &'a [u8]>) {
+    for (field, value) in fields(pbf) {
+        let (1, Field::Bytes(stack)) = (field, value) else { continue };
+        for (field, value) in fields(stack) {
+            let (3, Field::Bytes(glyph)) = (field, value) else { continue };
+            let id = fields(glyph).find_map(|(f, v)| match (f, v) {
+                (1, Field::Varint(id)) => Some(id),
+                _ => None,
+            });
+            if let Some(id) = id {
+                glyphs.entry(id).or_insert(glyph);
+            }
+        }
+    }
+}
+
+
+fn combine_glyphs(name: &str, range: &str, pbfs: &[Vec<u8>]) -> Vec<u8> {
+    let mut glyphs = BTreeMap::new();
+    for pbf in pbfs {
+        collect_glyphs(pbf, &mut glyphs);
+    }
+
+    let mut stack = Vec::new();
+    write_bytes(&mut stack, 1, name.as_bytes());
+    write_bytes(&mut stack, 2, range.as_bytes());
+    for glyph in glyphs.values() {
+        write_bytes(&mut stack, 3, glyph);
+    }
+
+    let mut out = Vec::new();
+    write_bytes(&mut out, 1, &stack);
+    out
+}
+
+
+// Range glyph harus blok 256 codepoint: "0-255", "256-511", ... sampai 65535
+fn valid_range(range: &str) -> bool {
+    let Some((start, end)) = range.split_once('-') else { return false };
+    match (start.parse::<u32>(), end.parse::<u32>()) {
+        (Ok(start), Ok(end)) => start % 256 == 0 && end == start + 255 && end <= 65535,
+        _ => false,
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct GlyphPath {
+    fontstack: String,
+    range: String,
+}
+
+
+pub async fn glyphs(path: web::Path<GlyphPath>) -> HttpResponse {
+    let params = path.into_inner();
+    if !valid_range(&params.range) {
+        return json_error(StatusCode::BAD_REQUEST, format!("Invalid glyph range: {}", params.range));
+    }
+
+    let fonts: Vec<&str> = params.fontstack.split(',').map(str::trim).filter(|f| !f.is_empty()).collect();
+    if fonts.is_empty() || !fonts.iter().all(|f| safe_name(f)) {
+        return json_error(StatusCode::BAD_REQUEST, format!("Invalid fontstack: {}", params.fontstack));
+    }
+
+    let dir = fonts_dir();
+    let mut pbfs = Vec::new();
+    for font in &fonts {
+        match read_file(dir.join(font).join(format!("{}.pbf", params.range))).await {
+            Some(data) => pbfs.push(data),
+            None => warn!("Font {} has no glyph range {}", font, params.range),
+        }
+    }
+
+    match pbfs.len() {
+        0 => json_error(StatusCode::NOT_FOUND, format!("No glyphs for fontstack {}", params.fontstack)),
+        // Satu font: kirim file apa adanya
+        1 if fonts.len() == 1 => asset_response("application/x-protobuf", pbfs.remove(0)),
+        _ => asset_response(
+            "application/x-protobuf",
+            combine_glyphs(&fonts.join(", "), &params.range, &pbfs),
+        ),
+    }
+}
+
+
+pub async fn font_list() -> HttpResponse {
+    let dir = fonts_dir();
+    let fonts = web::block(move || -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(&dir) else { return Vec::new() };
+        let mut names: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| safe_name(name))
+            .collect();
+        names.sort();
+        names
+    })
+    .await
+    .unwrap_or_default();
+
+    HttpResponse::Ok()
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .json(fonts)
+}
+
+
+pub async fn sprite(path: web::Path<String>) -> HttpResponse {
+    let file = path.into_inner();
+    let content_type = if file.ends_with(".json") {
+        "application/json"
+    } else if file.ends_with(".png") {
+        "image/png"
+    } else {
+        return json_error(StatusCode::NOT_FOUND, format!("Sprite not found: {}", file));
+    };
+    if !safe_name(&file) {
+        return json_error(StatusCode::BAD_REQUEST, format!("Invalid sprite name: {}", file));
+    }
+
+    match read_file(sprites_dir().join(&file)).await {
+        Some(data) => asset_response(content_type, data),
+        None => json_error(StatusCode::NOT_FOUND, format!("Sprite not found: {}", file)),
+    }
+}
@@ -0,0 +1,470 @@
+// Backfill geom_3857 bertahap per range primary key (lihat web::prep).
+//
+// Setiap batch adalah statement sendiri (commit sendiri), jadi tidak ada transaksi panjang
+// yang mengunci seluruh tabel. Key terakhir disimpan di {PREP_SCHEMA}.backfill_progress,
+// sehingga proses yang terputus dilanjutkan dari batch berikutnya pada run selanjutnya.
+// Tabel tanpa primary key di-batch per range block fisik (ctid, TID range scan).
+//
+//   PREPARE_BATCH_SIZE       baris per batch (default 10000)
+//   PREPARE_BATCH_DELAY_MS   jeda antar batch untuk mengurangi beban (default 0)
+use std::time::{Duration, Instant};
+use log::warn;
+use sqlx::{PgPool, Row};
+use super::privileges::can_select;
+use super::utils::{create_schema_sql, quote_ident};
+
+// Interval minimal antar baris progress di output
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+
+#[derive(Clone, Debug)]
+pub struct BackfillConfig {
+    pub batch_size: i64,
+    pub delay: Duration,
+    /// Abaikan progress tersimpan dan mulai dari awal
+    pub restart: bool,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: std::env::var("PREPARE_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|n: &i64| *n > 0)
+                .unwrap_or(10_000),
+            delay: Duration::from_millis(
+                std::env::var("PREPARE_BATCH_DELAY_MS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+            ),
+            restart: false,
+        }
+    }
+}
+
+
+pub(crate) fn progress_table_name(prep_schema: &str) -> String {
+    format!("{}.backfill_progress", quote_ident(prep_schema))
+}
+
+
+/// DDL tabel progress (juga ditampilkan oleh `prepare --dry-run`)
+pub(crate) fn progress_table_sql(prep_schema: &str) -> String {
+    format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS {} (
+            relation text NOT NULL,
+            mode text NOT NULL,
+            last_key text[] NOT NULL,
+            rows_done bigint NOT NULL DEFAULT 0,
+            updated_at timestamptz NOT NULL DEFAULT now(),
+            PRIMARY KEY (relation, mode)
+        )
+        "#,
+        progress_table_name(prep_schema)
+    )
+}
+
+
+// Range satu batch tabel tanpa primary key: block fisik [$1, $2)
+const BLOCK_RANGE: &str = "t.ctid >= format('(%s,0)', $1::bigint)::tid AND t.ctid < format('(%s,0)', $2::bigint)::tid";
+
+
+/// Satu pekerjaan backfill. `statement` berisi placeholder `{range}`: kondisi WHERE
+/// untuk range key saat ini (alias tabel sumber `t`).
+pub struct Backfill {
+    pub prep_schema: String,
+    pub mode: &'static str,
+    /// Kata kerja di output selesai, mis. "Backfilled"
+    pub verb: &'static str,
+    /// Id layer (lihat web::prep::LayerSource); key progress & lock
+    pub layer: String,
+    pub schema: String,
+    pub table: String,
+    pub statement: String,
+}
+
+
+struct KeyColumn {
+    name: String,
+    sql_type: String,
+}
+
+
+// ROW(t.a, t.b) <op> ROW($n::type, ...) memakai index primary key
+fn key_bound(keys: &[KeyColumn], op: &str, first_param: usize) -> String {
+    let key_list: Vec<String> = keys.iter().map(|k| format!("t.{}", quote_ident(&k.name))).collect();
+    let params: Vec<String> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, k)| format!("${}::{}", first_param + i, k.sql_type))
+        .collect();
+    format!("ROW({}) {} ROW({})", key_list.join(", "), op, params.join(", "))
+}
+
+
+// Range satu batch: key > key akhir batch sebelumnya ($1..) dan key <= key akhir batch ini
+fn key_range(keys: &[KeyColumn], lower: bool, upper: bool) -> String {
+    let mut conditions = Vec::new();
+    if lower {
+        conditions.push(key_bound(keys, ">", 1));
+    }
+    if upper {
+        let first = if lower { keys.len() + 1 } else { 1 };
+        conditions.push(key_bound(keys, "<=", first));
+    }
+    if conditions.is_empty() { "TRUE".to_string() } else { conditions.join(" AND ") }
+}
+
+
+// Kolom primary key beserta tipenya (untuk cast nilai key yang disimpan sebagai text)
+async fn key_columns(db_pool: &PgPool, schema: &str, table: &str) -> Result<Vec<KeyColumn>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT a.attname::text AS column_name, format_type(a.atttypid, a.atttypmod) AS sql_type
+        FROM pg_index i
+        JOIN pg_class c ON c.oid = i.indrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+        WHERE n.nspname = $1 AND c.relname = $2 AND i.indisprimary
+        ORDER BY array_position(i.indkey::int2[], a.attnum)
+        "#
+    )
+    .bind(schema)
+    .bind(table)
+    .fetch_all(db_pool)
+    .await?;
+
+    rows.iter()
+        .map(|r| Ok(KeyColumn { name: r.try_get("column_name")?, sql_type: r.try_get("sql_type")? }))
+        .collect()
+}
+
+
+impl Backfill {
+    fn label(&self) -> String {
+        format!("{}.{}", self.schema, self.layer)
+    }
+
+    fn progress_table(&self) -> String {
+        progress_table_name(&self.prep_schema)
+    }
+
+    /// Statement satu batch persis seperti yang dijalankan (batch di tengah tabel, dengan
+    /// batas bawah dan atas), untuk `prepare --dry-run`
+    pub async fn batch_sql(&self, db_pool: &PgPool) -> Result<String, sqlx::Error> {
+        let keys = key_columns(db_pool, &self.schema, &self.table).await?;
+        let range = if keys.is_empty() { BLOCK_RANGE.to_string() } else { key_range(&keys, true, true) };
+        Ok(self.statement.replace("{range}", &range))
+    }
+
+    /// Jalankan backfill. Mengembalikan jumlah baris yang ditulis.
+    pub async fn run(&self, db_pool: &PgPool, config: &BackfillConfig) -> Result<u64, sqlx::Error> {
+        let keys = key_columns(db_pool, &self.schema, &self.table).await?;
+
+        // Backfill yang sama dari proses lain (mis. replika server lain) dilewati
+        let mut lock_conn = db_pool.acquire().await?;
+        let lock_key = format!("tile_prep:{}:{}", self.mode, self.label());
+        let locked: bool = sqlx::query("SELECT pg_try_advisory_lock(hashtext($1)) AS locked")
+            .bind(&lock_key)
+            .fetch_one(&mut *lock_conn)
+            .await?
+            .try_get("locked")?;
+        if !locked {
+            println!("   ⏭️  {} is being backfilled by another process, skipped", self.label());
+            return Ok(0);
+        }
+
+        let result = if keys.is_empty() {
+            warn!("{} has no primary key, backfilling by block ranges", self.label());
+            self.run_block_batches(db_pool, config).await
+        } else {
+            self.run_batches(db_pool, config, &keys).await
+        };
+
+        sqlx::query("SELECT pg_advisory_unlock(hashtext($1))")
+            .bind(&lock_key)
+            .execute(&mut *lock_conn)
+            .await?;
+        result
+    }
+
+    async fn run_batches(&self, db_pool: &PgPool, config: &BackfillConfig, keys: &[KeyColumn]) -> Result<u64, sqlx::Error> {
+        self.ensure_progress_table(db_pool).await?;
+
+        let source = format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.table));
+        let key_list: Vec<String> = keys.iter().map(|k| format!("t.{}", quote_ident(&k.name))).collect();
+        let key_list = key_list.join(", ");
+        let key_text: Vec<String> = keys.iter().map(|k| format!("t.{}::text", quote_ident(&k.name))).collect();
+        let key_text = key_text.join(", ");
+
+        let (mut lower, mut done) = if config.restart {
+            (None, 0)
+        } else {
+            self.load_progress(db_pool).await?
+        };
+        if let Some(key) = &lower {
+            println!("   ↻ Resuming {} after key ({}), {} rows already done", self.label(), key.join(", "), done);
+        }
+
+        let estimate = self.estimated_rows(db_pool).await?;
+        let started = Instant::now();
+        let done_at_start = done;
+        let mut last_report = Instant::now();
+        let mut written = 0u64;
+
+        loop {
+            // Key akhir batch: baris ke-batch_size setelah key awal
+            let upper_sql = format!(
+                "SELECT {} FROM {} t WHERE {} ORDER BY {} OFFSET {} LIMIT 1",
+                key_text,
+                source,
+                key_range(keys, lower.is_some(), false),
+                key_list,
+                config.batch_size - 1
+            );
+            let mut query = sqlx::query(&upper_sql);
+            for value in lower.iter().flatten() {
+                query = query.bind(value);
+            }
+            let upper: Option<Vec<String>> = match query.fetch_optional(db_pool).await? {
+                Some(row) => Some((0..keys.len()).map(|i| row.try_get(i)).collect::<Result<_, _>>()?),
+                None => None,
+            };
+
+            let range = key_range(keys, lower.is_some(), upper.is_some());
+            let sql = self.statement.replace("{range}", &range);
+            let mut query = sqlx::query(&sql);
+            for value in lower.iter().chain(upper.iter()).flatten() {
+                query = query.bind(value);
+            }
+            written += query.execute(db_pool).await?.rows_affected();
+
+            let Some(upper) = upper else { break };
+            done += config.batch_size;
+            self.save_progress(db_pool, &upper, done).await?;
+            lower = Some(upper);
+
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                self.report(done, done_at_start, estimate, written, started.elapsed());
+                last_report = Instant::now();
+            }
+            if !config.delay.is_zero() {
+                tokio::time::sleep(config.delay).await;
+            }
+        }
+
+        self.clear_progress(db_pool).await?;
+        println!(
+            "   ✅ {} {}: {} rows written in {}",
+            self.verb, self.label(), written, format_duration(started.elapsed())
+        );
+        Ok(written)
+    }
+
+    // Batch per range block [lower, upper) untuk tabel tanpa key. Baris yang pindah ke block
+    // baru karena UPDATE sudah terisi, jadi ikut terbaca lagi tapi tidak ditulis ulang.
+    async fn run_block_batches(&self, db_pool: &PgPool, config: &BackfillConfig) -> Result<u64, sqlx::Error> {
+        self.ensure_progress_table(db_pool).await?;
+
+        let source = format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.table));
+        let estimate = self.estimated_rows(db_pool).await?;
+        let pages: i64 = sqlx::query("SELECT relpages::bigint AS pages FROM pg_class WHERE oid = to_regclass($1)")
+            .bind(&source)
+            .fetch_one(db_pool)
+            .await?
+            .try_get("pages")?;
+        // Tabel yang belum di-ANALYZE: anggap ~50 baris per block
+        let rows_per_block = if pages > 0 && estimate > 0 { (estimate / pages).max(1) } else { 50 };
+        let blocks_per_batch = (config.batch_size / rows_per_block).max(1);
+
+        let saved = if config.restart { None } else { self.load_progress(db_pool).await?.0 };
+        let mut lower: i64 = saved
+            .and_then(|key| key.first().and_then(|block| block.parse().ok()))
+            .unwrap_or(0);
+        if lower > 0 {
+            println!("   ↻ Resuming {} from block {}", self.label(), lower);
+        }
+
+        let sql = self.statement.replace("{range}", BLOCK_RANGE);
+        let started = Instant::now();
+        let done_at_start = lower * rows_per_block;
+        let mut last_report = Instant::now();
+        let mut written = 0u64;
+
+        loop {
+            // Jumlah block dibaca ulang tiap batch: tabel bisa bertambah selama backfill
+            let blocks: i64 = sqlx::query(
+                "SELECT pg_relation_size(to_regclass($1)) / current_setting('block_size')::bigint AS blocks"
+            )
+            .bind(&source)
+            .fetch_one(db_pool)
+            .await?
+            .try_get("blocks")?;
+            if lower >= blocks {
+                break;
+            }
+
+            let upper = lower + blocks_per_batch;
+            written += sqlx::query(&sql)
+                .bind(lower)
+                .bind(upper)
+                .execute(db_pool)
+                .await?
+                .rows_affected();
+            lower = upper;
+            self.save_progress(db_pool, &[lower.to_string()], lower * rows_per_block).await?;
+
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                self.report(lower * rows_per_block, done_at_start, estimate, written, started.elapsed());
+                last_report = Instant::now();
+            }
+            if !config.delay.is_zero() {
+                tokio::time::sleep(config.delay).await;
+            }
+        }
+
+        self.clear_progress(db_pool).await?;
+        println!(
+            "   ✅ {} {}: {} rows written in {}",
+            self.verb, self.label(), written, format_duration(started.elapsed())
+        );
+        Ok(written)
+    }
+
+    fn report(&self, done: i64, done_at_start: i64, estimate: i64, written: u64, elapsed: Duration) {
+        let rate = (done - done_at_start) as f64 / elapsed.as_secs_f64().max(0.001);
+        let mut line = format!("   ⏳ {}: {} / ~{} rows", self.label(), done, estimate.max(done));
+        if estimate > 0 {
+            line.push_str(&format!(" ({:.1}%)", (done as f64 / estimate as f64 * 100.0).min(100.0)));
+        }
+        line.push_str(&format!(", {} written, {:.0} rows/s", written, rate));
+        if rate > 0.0 && estimate > done {
+            let eta = Duration::from_secs_f64((estimate - done) as f64 / rate);
+            line.push_str(&format!(", ETA {}", format_duration(eta)));
+        }
+        println!("{}", line);
+    }
+
+    // Perkiraan jumlah baris dari statistik planner (tanpa count(*) yang memindai tabel)
+    async fn estimated_rows(&self, db_pool: &PgPool) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT GREATEST(c.reltuples, 0)::bigint AS estimate
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE n.nspname = $1 AND c.relname = $2
+            "#
+        )
+        .bind(&self.schema)
+        .bind(&self.table)
+        .fetch_optional(db_pool)
+        .await?;
+        match row {
+            Some(row) => row.try_get("estimate"),
+            None => Ok(0),
+        }
+    }
+
+    async fn ensure_progress_table(&self, db_pool: &PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query(&create_schema_sql(&self.prep_schema))
+            .execute(db_pool)
+            .await?;
+        sqlx::query(&progress_table_sql(&self.prep_schema))
+            .execute(db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Tandai backfill belum selesai sebelum kolom / tabel bayangan dibuat. Selama baris progress
+    /// masih ada, layer ditransform on the fly (lihat web::prep::resolve), jadi tile tidak pernah
+    /// dirender dari kolom yang baru terisi sebagian.
+    pub async fn mark_pending(&self, db_pool: &PgPool) -> Result<(), sqlx::Error> {
+        self.ensure_progress_table(db_pool).await?;
+        sqlx::query(&format!(
+            "INSERT INTO {} (relation, mode, last_key, rows_done) VALUES ($1, $2, '{{}}', 0) \
+             ON CONFLICT (relation, mode) DO NOTHING",
+            self.progress_table()
+        ))
+        .bind(self.label())
+        .bind(self.mode)
+        .execute(db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Backfill belum selesai: baris progress masih ada (dibersihkan setelah batch terakhir)
+    pub async fn is_pending(&self, db_pool: &PgPool) -> Result<bool, sqlx::Error> {
+        if !can_select(db_pool, &self.progress_table()).await? {
+            return Ok(false);
+        }
+        let row = sqlx::query(&format!(
+            "SELECT 1 FROM {} WHERE relation = $1 AND mode = $2",
+            self.progress_table()
+        ))
+        .bind(self.label())
+        .bind(self.mode)
+        .fetch_optional(db_pool)
+        .await?;
+        Ok(row.is_some())
+    }
+
+    async fn load_progress(&self, db_pool: &PgPool) -> Result<(Option<Vec<String>>, i64), sqlx::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT last_key, rows_done FROM {} WHERE relation = $1 AND mode = $2",
+            self.progress_table()
+        ))
+        .bind(self.label())
+        .bind(self.mode)
+        .fetch_optional(db_pool)
+        .await?;
+        match row {
+            // last_key kosong: ditandai mark_pending, belum ada batch yang selesai
+            Some(row) => {
+                let last_key: Vec<String> = row.try_get("last_key")?;
+                Ok((Some(last_key).filter(|k| !k.is_empty()), row.try_get("rows_done")?))
+            }
+            None => Ok((None, 0)),
+        }
+    }
+
+    async fn save_progress(&self, db_pool: &PgPool, last_key: &[String], done: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "INSERT INTO {} (relation, mode, last_key, rows_done, updated_at) VALUES ($1, $2, $3, $4, now()) \
+             ON CONFLICT (relation, mode) DO UPDATE \
+             SET last_key = EXCLUDED.last_key, rows_done = EXCLUDED.rows_done, updated_at = now()",
+            self.progress_table()
+        ))
+        .bind(self.label())
+        .bind(self.mode)
+        .bind(last_key)
+        .bind(done)
+        .execute(db_pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn clear_progress(&self, db_pool: &PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE relation = $1 AND mode = $2",
+            self.progress_table()
+        ))
+        .bind(self.label())
+        .bind(self.mode)
+        .execute(db_pool)
+        .await?;
+        Ok(())
+    }
+}
+
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
@@ -1,19 +1,46 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::collections::{BTreeMap, HashMap};
 use moka::future::Cache;
 use once_cell::sync::Lazy;
-use aws_sdk_s3::{Client as S3Client, primitives::ByteStream};
+use aws_sdk_s3::{Client as S3Client, primitives::{ByteStream, DateTime, DateTimeFormat}};
+use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::get_object::GetObjectError;
 use serde::{ Serialize};
-use log::{info};
+use log::{info, warn, debug};
 use tokio::sync::OnceCell;
 use std::sync::Arc;
-use aws_config::BehaviorVersion;
+use aws_config::{BehaviorVersion, Region};
+
+// Identitas satu tile di semua tier cache
+#[derive(Hash, Eq, PartialEq, Clone, Debug)]
+pub struct TileCacheKey {
+    pub table_name: String,
+    pub version: String,
+    pub tms: String,
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+}
 
-#[derive(Hash, Eq, PartialEq, Clone)]
-struct TileCacheKey {
-    table_name: String,
-    z: u32,
-    x: u32,
-    y: u32,
+impl TileCacheKey {
+    pub fn new(table_name: &str, version: &str, tms: &str, z: u32, x: u32, y: u32) -> Self {
+        Self {
+            table_name: table_name.to_string(),
+            version: version.to_string(),
+            tms: tms.to_string(),
+            z,
+            x,
+            y,
+        }
+    }
+}
+
+impl std::fmt::Display for TileCacheKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}/{}/{}", self.tms, self.table_name, self.z, self.x, self.y)
+    }
 }
 
 // Struktur untuk cache value dengan metadata ukuran
@@ -83,12 +110,56 @@ static TILE_CACHE: Lazy<Cache<TileCacheKey, CachedTile>> = Lazy::new(|| {
         .max_capacity(max_memory_bytes)
         // Set TTL
         .time_to_live(Duration::from_secs(config.ttl_seconds))
+        // Dibutuhkan untuk membuang entry per layer saat versi layer berubah
+        .support_invalidation_closures()
+        // Hitung entry yang dibuang karena kapasitas penuh atau TTL habis
+        .eviction_listener(|key: Arc<TileCacheKey>, _value, cause| {
+            if cause.was_evicted() {
+                MEMORY_COUNTERS.evictions.fetch_add(1, Ordering::Relaxed);
+                layer_counters(&key.table_name).evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        })
         // Optional: set initial capacity untuk performa
         .initial_capacity(1000)
         .build()
 });
 
 
+// Counter memory cache (global) dan counter per layer untuk /admin/cache
+#[derive(Default)]
+pub struct MemoryCounters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub evictions: AtomicU64,
+}
+
+pub static MEMORY_COUNTERS: Lazy<MemoryCounters> = Lazy::new(MemoryCounters::default);
+
+#[derive(Default)]
+pub struct LayerCounters {
+    pub memory_hits: AtomicU64,
+    pub memory_misses: AtomicU64,
+    pub s3_hits: AtomicU64,
+    pub s3_misses: AtomicU64,
+    pub evictions: AtomicU64,
+}
+
+static LAYER_COUNTERS: Lazy<std::sync::RwLock<HashMap<String, Arc<LayerCounters>>>> =
+    Lazy::new(|| std::sync::RwLock::new(HashMap::new()));
+
+fn layer_counters(table_name: &str) -> Arc<LayerCounters> {
+    if let Some(counters) = LAYER_COUNTERS.read().unwrap().get(table_name) {
+        return counters.clone();
+    }
+    LAYER_COUNTERS
+        .write()
+        .unwrap()
+        .entry(table_name.to_string())
+        .or_default()
+        .clone()
+}
+
+
 
 
 // Global singleton
@@ -97,16 +168,41 @@ static S3_CLIENT: OnceCell<Option<Arc<S3Client>>> = OnceCell::const_new();
 async fn get_s3_client() -> Option<Arc<S3Client>> {
     S3_CLIENT
         .get_or_init(|| async {
-            if std::env::var("AWS_ACCESS_KEY_ID").is_err()
-                || std::env::var("AWS_SECRET_ACCESS_KEY").is_err()
-            {
+            let config = S3Config::default();
+            let has_keys = std::env::var("AWS_ACCESS_KEY_ID").is_ok()
+                && std::env::var("AWS_SECRET_ACCESS_KEY").is_ok();
+
+            // S3 aktif jika ada credential, mode anonymous, atau endpoint custom (MinIO/Ceph/LocalStack)
+            if !has_keys && !config.anonymous && config.endpoint_url.is_none() {
                 info!("AWS env not set, skipping S3");
-                None
-            } else {
-                info!("Initializing S3 client...");
-                let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
-                Some(Arc::new(S3Client::new(&config)))
+                return None;
+            }
+
+            info!(
+                "Initializing S3 client: endpoint={}, region={}, path_style={}, anonymous={}",
+                config.endpoint_url.as_deref().unwrap_or("aws-default"),
+                config.region.as_deref().unwrap_or("aws-default"),
+                config.force_path_style,
+                config.anonymous
+            );
+
+            let mut loader = aws_config::defaults(BehaviorVersion::latest());
+            if let Some(region) = &config.region {
+                loader = loader.region(Region::new(region.clone()));
+            }
+            if let Some(endpoint_url) = &config.endpoint_url {
+                loader = loader.endpoint_url(endpoint_url);
+            }
+            if config.anonymous {
+                loader = loader.no_credentials();
             }
+            let shared_config = loader.load().await;
+
+            let s3_config = aws_sdk_s3::config::Builder::from(&shared_config)
+                .force_path_style(config.force_path_style)
+                .build();
+
+            Some(Arc::new(S3Client::from_conf(s3_config)))
         })
         .await
         .clone()
@@ -119,6 +215,11 @@ pub struct S3Config {
     pub bucket: String,
     pub prefix: String,
     pub ttl_seconds: u64,
+    pub endpoint_url: Option<String>, // Endpoint S3-compatible, contoh: http://localhost:9000
+    pub region: Option<String>,       // Override region (MinIO biasanya "us-east-1")
+    pub force_path_style: bool,       // http://host/bucket/key, bukan http://bucket.host/key
+    pub anonymous: bool,              // Tanpa signing, untuk bucket publik
+    pub timeout_ms: u64,              // Timeout per operasi S3 (termasuk baca body)
 }
 
 impl Default for S3Config {
@@ -130,15 +231,234 @@ impl Default for S3Config {
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(86400), // Default 24 jam
+            endpoint_url: std::env::var("S3_ENDPOINT_URL")
+                .ok()
+                .filter(|v| !v.is_empty()),
+            region: std::env::var("S3_REGION")
+                .ok()
+                .filter(|v| !v.is_empty()),
+            force_path_style: std::env::var("S3_FORCE_PATH_STYLE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            anonymous: std::env::var("S3_ANONYMOUS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            timeout_ms: std::env::var("S3_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(2000), // Default 2 detik
         }
     }
 }
 
-// Generate S3 key
-fn generate_s3_key(config: &S3Config, table_name: &str, z: u32, x: u32, y: u32) -> String {
-    format!("{}/{}/{}/{}/{}.pbf", config.prefix, table_name, z, x, y)
+// Generate S3 key: {prefix}/{layer}/{version}/{tms}/{z}/{x}/{y}.pbf
+// Prefix per layer supaya lifecycle rule dan purge bisa menargetkan satu layer/versi
+fn generate_s3_key(config: &S3Config, key: &TileCacheKey) -> String {
+    format!(
+        "{}{}/{}/{}/{}.pbf",
+        layer_s3_prefix(config, &key.table_name, Some(&key.version)),
+        key.tms, key.z, key.x, key.y
+    )
+}
+
+// Prefix S3 untuk satu layer (semua versi) atau satu versi layer, selalu diakhiri "/"
+fn layer_s3_prefix(config: &S3Config, table_name: &str, version: Option<&str>) -> String {
+    match version {
+        Some(version) => format!("{}/{}/{}/", config.prefix, table_name, version),
+        None => format!("{}/{}/", config.prefix, table_name),
+    }
+}
+
+
+
+// Circuit breaker untuk tier S3: setelah beberapa kegagalan berturut-turut,
+// S3 dilewati selama masa cool-down supaya request tile tidak ikut lambat.
+pub struct CircuitBreaker {
+    consecutive_failures: AtomicU32,
+    open_until_ms: AtomicU64,
+    trips: AtomicU64,
+    failure_threshold: u32,
+    cooldown: Duration,
 }
 
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            consecutive_failures: AtomicU32::new(0),
+            open_until_ms: AtomicU64::new(0),
+            trips: AtomicU64::new(0),
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+        }
+    }
+
+    /// Boleh memanggil S3? `false` selama breaker masih open.
+    pub fn allow(&self) -> bool {
+        now_ms() >= self.open_until_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        // Di state half-open counter masih >= threshold, jadi satu kegagalan langsung membuka lagi
+        if failures >= self.failure_threshold {
+            let until = now_ms() + self.cooldown.as_millis() as u64;
+            self.open_until_ms.store(until, Ordering::Relaxed);
+            self.trips.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "S3 circuit breaker open after {} consecutive failures, bypassing S3 for {}s",
+                failures,
+                self.cooldown.as_secs()
+            );
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        if !self.allow() {
+            CircuitState::Open
+        } else if self.consecutive_failures.load(Ordering::Relaxed) >= self.failure_threshold {
+            CircuitState::HalfOpen
+        } else {
+            CircuitState::Closed
+        }
+    }
+}
+
+static S3_BREAKER: Lazy<CircuitBreaker> = Lazy::new(|| {
+    let threshold = std::env::var("S3_BREAKER_FAILURES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5);
+    let cooldown = std::env::var("S3_BREAKER_COOLDOWN_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+    CircuitBreaker::new(threshold, Duration::from_secs(cooldown))
+});
+
+// Counter operasi S3 untuk health & metrics
+#[derive(Default)]
+pub struct S3Counters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub errors: AtomicU64,
+    pub timeouts: AtomicU64,
+    pub bypassed: AtomicU64,
+}
+
+pub static S3_COUNTERS: Lazy<S3Counters> = Lazy::new(S3Counters::default);
+
+#[derive(Debug, Serialize)]
+pub struct S3Status {
+    pub enabled: bool,
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+    pub circuit_trips: u64,
+    pub open_for_seconds: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    pub bypassed: u64,
+}
+
+pub async fn s3_status() -> S3Status {
+    let open_until = S3_BREAKER.open_until_ms.load(Ordering::Relaxed);
+    S3Status {
+        enabled: get_s3_client().await.is_some(),
+        circuit: S3_BREAKER.state(),
+        consecutive_failures: S3_BREAKER.consecutive_failures.load(Ordering::Relaxed),
+        circuit_trips: S3_BREAKER.trips.load(Ordering::Relaxed),
+        open_for_seconds: open_until.saturating_sub(now_ms()) / 1000,
+        hits: S3_COUNTERS.hits.load(Ordering::Relaxed),
+        misses: S3_COUNTERS.misses.load(Ordering::Relaxed),
+        errors: S3_COUNTERS.errors.load(Ordering::Relaxed),
+        timeouts: S3_COUNTERS.timeouts.load(Ordering::Relaxed),
+        bypassed: S3_COUNTERS.bypassed.load(Ordering::Relaxed),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[derive(Debug)]
+pub struct S3TimeoutError(pub Duration);
+
+impl std::fmt::Display for S3TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "S3 operation timed out after {}ms", self.0.as_millis())
+    }
+}
+
+impl std::error::Error for S3TimeoutError {}
+
+// Jalankan operasi S3 dengan timeout dan catat hasilnya ke circuit breaker
+async fn guarded<T, F>(config: &S3Config, fut: F) -> Result<T, Box<dyn std::error::Error>>
+where
+    F: std::future::Future<Output = Result<T, Box<dyn std::error::Error>>>,
+{
+    let timeout = Duration::from_millis(config.timeout_ms);
+    match tokio::time::timeout(timeout, fut).await {
+        Ok(Ok(value)) => {
+            S3_BREAKER.record_success();
+            Ok(value)
+        }
+        Ok(Err(e)) => {
+            S3_COUNTERS.errors.fetch_add(1, Ordering::Relaxed);
+            S3_BREAKER.record_failure();
+            Err(e)
+        }
+        Err(_) => {
+            S3_COUNTERS.timeouts.fetch_add(1, Ordering::Relaxed);
+            S3_BREAKER.record_failure();
+            Err(Box::new(S3TimeoutError(timeout)))
+        }
+    }
+}
+
+// Client S3 hanya jika tier aktif dan breaker tidak open
+async fn available_s3_client() -> Option<Arc<S3Client>> {
+    let client = get_s3_client().await?;
+    if S3_BREAKER.allow() {
+        Some(client)
+    } else {
+        S3_COUNTERS.bypassed.fetch_add(1, Ordering::Relaxed);
+        debug!("S3 circuit open, bypassing S3 tier");
+        None
+    }
+}
+
+// Hanya object yang tidak ada yang dihitung miss. 404 lain (mis. NoSuchBucket karena nama bucket
+// salah) adalah error, supaya tercatat di breaker dan /health, bukan cache yang selalu dingin.
+fn is_not_found(e: &SdkError<GetObjectError>) -> bool {
+    if let Some(service_err) = e.as_service_error()
+        && service_err.is_no_such_key()
+    {
+        return true;
+    }
+    // Beberapa server S3-compatible mengembalikan 404 tanpa body (tanpa kode error)
+    e.code().is_none()
+        && e.raw_response()
+            .map(|r| r.status().as_u16() == 404)
+            .unwrap_or(false)
+}
 
 
 #[derive(Debug, Serialize)]
@@ -146,49 +466,111 @@ pub struct CacheStats {
     pub entry_count: u64,
     pub memory_bytes: u64,
     pub memory_mb: f64,
+    pub max_memory_mb: u64,
+    pub ttl_seconds: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LayerCacheStats {
+    pub memory_hits: u64,
+    pub memory_misses: u64,
+    pub s3_hits: u64,
+    pub s3_misses: u64,
+    pub evictions: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheReport {
+    pub memory: CacheStats,
+    pub s3: S3Status,
+    pub layers: BTreeMap<String, LayerCacheStats>,
+}
+
+pub async fn get_cache_stats() -> CacheStats {
+    // Jalankan maintenance moka dulu supaya entry_count & weighted_size akurat
+    TILE_CACHE.run_pending_tasks().await;
+    let config = CacheConfig::default();
+    let memory_bytes = TILE_CACHE.weighted_size();
+
+    CacheStats {
+        entry_count: TILE_CACHE.entry_count(),
+        memory_bytes,
+        memory_mb: memory_bytes as f64 / (1024.0 * 1024.0),
+        max_memory_mb: config.max_memory_mb,
+        ttl_seconds: config.ttl_seconds,
+        hits: MEMORY_COUNTERS.hits.load(Ordering::Relaxed),
+        misses: MEMORY_COUNTERS.misses.load(Ordering::Relaxed),
+        evictions: MEMORY_COUNTERS.evictions.load(Ordering::Relaxed),
+    }
+}
+
+pub async fn get_cache_report() -> CacheReport {
+    let memory = get_cache_stats().await;
+    let s3 = s3_status().await;
+    let layers = LAYER_COUNTERS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(name, c)| {
+            (
+                name.clone(),
+                LayerCacheStats {
+                    memory_hits: c.memory_hits.load(Ordering::Relaxed),
+                    memory_misses: c.memory_misses.load(Ordering::Relaxed),
+                    s3_hits: c.s3_hits.load(Ordering::Relaxed),
+                    s3_misses: c.s3_misses.load(Ordering::Relaxed),
+                    evictions: c.evictions.load(Ordering::Relaxed),
+                },
+            )
+        })
+        .collect();
+
+    CacheReport { memory, s3, layers }
+}
+
+// Flush memory cache: semua layer, atau satu layer saja. Tier S3 tidak disentuh.
+pub async fn flush_memory_cache(table_name: Option<&str>) {
+    match table_name {
+        Some(table_name) => invalidate_layer_memory(table_name, None),
+        None => TILE_CACHE.invalidate_all(),
+    }
+    TILE_CACHE.run_pending_tasks().await;
 }
 
 // Function untuk get data dari memory cache
 pub async fn get_from_memory_cache(
-    table_name: &str,
-    z: u32,
-    x: u32,
-    y: u32,
+    key: &TileCacheKey,
 ) -> Option<Vec<u8>> {
-    let key = TileCacheKey {
-        table_name: table_name.to_string(),
-        z,
-        x,
-        y,
-    };
-    
-    TILE_CACHE.get(&key).await.map(|cached| {
-        info!(
-            "Memory cache hit: {}/{}/{}/{} ({} bytes)",
-            table_name, z, x, y, cached.size
-        );
-        cached.data
-    })
+    let counters = layer_counters(&key.table_name);
+    match TILE_CACHE.get(key).await {
+        Some(cached) => {
+            MEMORY_COUNTERS.hits.fetch_add(1, Ordering::Relaxed);
+            counters.memory_hits.fetch_add(1, Ordering::Relaxed);
+            info!(
+                "Memory cache hit: {} ({} bytes)",
+                key, cached.size
+            );
+            Some(cached.data)
+        }
+        None => {
+            MEMORY_COUNTERS.misses.fetch_add(1, Ordering::Relaxed);
+            counters.memory_misses.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
 }
 
 // Function untuk put data ke memory cache
 pub async fn put_to_memory_cache(
-    table_name: &str,
-    z: u32,
-    x: u32,
-    y: u32,
+    key: &TileCacheKey,
     data: Vec<u8>,
 ) {
-    let key = TileCacheKey {
-        table_name: table_name.to_string(),
-        z,
-        x,
-        y,
-    };
-    
     let cached = CachedTile::new(data);
     // let size = cached.size;
-    TILE_CACHE.insert(key, cached).await;
+    TILE_CACHE.insert(key.clone(), cached).await;
     
     // Log cache stats setelah insert
     // let stats = get_cache_stats().await;
@@ -201,16 +583,16 @@ pub async fn put_to_memory_cache(
 // Function untuk get data dari S3
 pub async fn get_from_s3(
     config: &S3Config,
-    table_name: &str,
-    z: u32,
-    x: u32,
-    y: u32,
+    key: &TileCacheKey,
 ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
-    let key = generate_s3_key(config, table_name, z, x, y);
-
-    if let Some(client) = get_s3_client().await {
+    let counters = layer_counters(&key.table_name);
+    let key = generate_s3_key(config, key);
 
+    let Some(client) = available_s3_client().await else {
+        return Ok(None);
+    };
 
+    let result = guarded(config, async {
         match client
             .get_object()
             .bucket(&config.bucket)
@@ -219,64 +601,46 @@ pub async fn get_from_s3(
             .await
         {
             Ok(output) => {
-                // Cek metadata untuk expires
-                if let Some(metadata) = output.metadata() {
-                    if let Some(expires_str) = metadata.get("expires_at") {
-                        if let Ok(expires_at) = expires_str.parse::<u64>() {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs();
-                            
-                            if now > expires_at {
-                                info!("S3 tile expired, deleting: {}", key);
-                                let _ = client
-                                    .delete_object()
-                                    .bucket(&config.bucket)
-                                    .key(&key)
-                                    .send()
-                                    .await;
-                                return Ok(None);
-                            }
-                        }
-                    }
+                // Object yang sudah lewat Expires dianggap miss; penghapusan diserahkan ke lifecycle rule bucket
+                if let Some(expires) = output.expires_string()
+                    && let Ok(expires) = DateTime::from_str(expires, DateTimeFormat::HttpDate)
+                    && expires.secs() < now_ms() as i64 / 1000
+                {
+                    debug!("S3 tile expired: {}", key);
+                    return Ok(None);
                 }
-                
+
                 let bytes = output.body.collect().await?.into_bytes();
                 Ok(Some(bytes.to_vec()))
             }
-            Err(e) => {
-                if e.to_string().contains("NoSuchKey") {
-                    info!("S3 tile not found: {}", key);
-                    Ok(None)
-                } else {
-                    Err(Box::new(e))
-                }
-            }
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e.into()),
         }
-           
-
-
-
-    } else {
-        info!("S3 not initialized, skipping S3 fetch");
-        Ok(None)
-    }
+    })
+    .await?;
 
-    
-    
+    match &result {
+        Some(_) => {
+            S3_COUNTERS.hits.fetch_add(1, Ordering::Relaxed);
+            counters.s3_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        None => {
+            debug!("S3 tile not found: {}", key);
+            S3_COUNTERS.misses.fetch_add(1, Ordering::Relaxed);
+            counters.s3_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    };
+    Ok(result)
 }
 
 // Function untuk put data ke S3 dengan expires
 pub async fn put_to_s3(
     config: &S3Config,
-    table_name: &str,
-    z: u32,
-    x: u32,
-    y: u32,
+    key: &TileCacheKey,
     data: Vec<u8>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let key = generate_s3_key(config, table_name, z, x, y);
+    let table_name = key.table_name.as_str();
+    let key = generate_s3_key(config, key);
     
     let expires_at = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -284,61 +648,244 @@ pub async fn put_to_s3(
         .as_secs()
         + config.ttl_seconds;
 
-     if let Some(client) = get_s3_client().await {
-        client
-        .put_object()
-        .bucket(&config.bucket)
-        .key(&key)
-        .body(ByteStream::from(data))
-        .content_type("application/x-protobuf")
-        .metadata("expires_at", expires_at.to_string())
-        .metadata("table_name", table_name)
-        .send()
-        .await?;
-    
-        info!("✓ Uploaded to S3: {} (expires in {} seconds)", key, config.ttl_seconds);
-        Ok(())
+    let Some(client) = available_s3_client().await else {
+        return Ok(());
+    };
 
-     } else {
-        info!("S3 not initialized, skipping S3 fetch");
+    guarded(config, async {
+        client
+            .put_object()
+            .bucket(&config.bucket)
+            .key(&key)
+            .body(ByteStream::from(data))
+            .content_type("application/x-protobuf")
+            .cache_control(format!("public, max-age={}", config.ttl_seconds))
+            .expires(DateTime::from_secs(expires_at as i64))
+            .metadata("table_name", table_name)
+            .send()
+            .await?;
         Ok(())
-     }
+    })
+    .await?;
 
-    
-    
+    info!("✓ Uploaded to S3: {} (expires in {} seconds)", key, config.ttl_seconds);
+    Ok(())
 }
 
 // Function untuk invalidate cache (memory + S3)
 pub async fn invalidate_tile(
     config: &S3Config,
-    table_name: &str,
-    z: u32,
-    x: u32,
-    y: u32,
+    key: &TileCacheKey,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let key = TileCacheKey {
-        table_name: table_name.to_string(),
-        z,
-        x,
-        y,
+    TILE_CACHE.invalidate(key).await;
+
+    if let Some(client) = available_s3_client().await {
+        let s3_key = generate_s3_key(config, key);
+        guarded(config, async {
+            client
+                .delete_object()
+                .bucket(&config.bucket)
+                .key(&s3_key)
+                .send()
+                .await?;
+            Ok(())
+        })
+        .await?;
+        
+        info!("✓ Invalidated tile: {}", key);
+    }
+    Ok(())
+}
+
+
+// Hapus semua object tile milik layer (atau satu versi layer) dengan DeleteObjects per batch 1000 key.
+// Mengembalikan jumlah object yang terhapus.
+pub async fn purge_layer(
+    config: &S3Config,
+    table_name: &str,
+    version: Option<&str>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let Some(client) = get_s3_client().await else {
+        return Err("S3 is not configured".into());
     };
-    TILE_CACHE.invalidate(&key).await;
 
-    if let Some(client) = get_s3_client().await { 
-        let s3_key = generate_s3_key(config, table_name, z, x, y);
-        client
-            .delete_object()
+    let prefix = layer_s3_prefix(config, table_name, version);
+    info!("Purging s3://{}/{}", config.bucket, prefix);
+
+    let mut deleted: u64 = 0;
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(&config.bucket)
+        .prefix(&prefix)
+        .into_paginator()
+        .send();
+
+    // ListObjectsV2 mengembalikan maks 1000 key per halaman, sama dengan batas DeleteObjects
+    while let Some(page) = pages.next().await {
+        let page = page?;
+        let objects = page
+            .contents()
+            .iter()
+            .filter_map(|o| o.key())
+            .map(|k| ObjectIdentifier::builder().key(k).build())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if objects.is_empty() {
+            continue;
+        }
+
+        let batch_len = objects.len() as u64;
+        let output = client
+            .delete_objects()
             .bucket(&config.bucket)
-            .key(&s3_key)
+            .delete(Delete::builder().set_objects(Some(objects)).quiet(true).build()?)
             .send()
             .await?;
-        
-        info!("✓ Invalidated tile: {}/{}/{}/{}", table_name, z, x, y);
-        Ok(())
 
-    } else {
-        info!("S3 not initialized, skipping S3 fetch");
-        Ok(())
+        let failed = output.errors().len() as u64;
+        for err in output.errors().iter().take(5) {
+            warn!(
+                "Failed to delete {}: {}",
+                err.key().unwrap_or("?"),
+                err.message().unwrap_or("unknown error")
+            );
+        }
+
+        deleted += batch_len - failed;
+        info!("  deleted {} objects so far", deleted);
+    }
+
+    Ok(deleted)
+}
+
+
+// Daftar versi layer yang ada di S3 (segmen di bawah {prefix}/{layer}/)
+pub async fn list_layer_versions(
+    config: &S3Config,
+    table_name: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let Some(client) = available_s3_client().await else {
+        return Ok(Vec::new());
+    };
+
+    let prefix = layer_s3_prefix(config, table_name, None);
+    let mut versions = Vec::new();
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(&config.bucket)
+        .prefix(&prefix)
+        .delimiter("/")
+        .into_paginator()
+        .send();
+
+    while let Some(page) = pages.next().await {
+        for common in page?.common_prefixes() {
+            if let Some(version) = common
+                .prefix()
+                .and_then(|p| p.strip_prefix(&prefix))
+                .map(|p| p.trim_end_matches('/'))
+                .filter(|p| !p.is_empty())
+            {
+                versions.push(version.to_string());
+            }
+        }
+    }
+
+    Ok(versions)
+}
+
+// Buang semua entry memory cache milik layer kecuali versi `keep_version`
+pub fn invalidate_layer_memory(table_name: &str, keep_version: Option<&str>) {
+    let table_name = table_name.to_string();
+    let keep_version = keep_version.map(|v| v.to_string());
+    if let Err(e) = TILE_CACHE.invalidate_entries_if(move |key, _| {
+        key.table_name == table_name && Some(&key.version) != keep_version.as_ref()
+    }) {
+        warn!("Failed to invalidate memory cache: {}", e);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::http::HttpResponse;
+    use aws_sdk_s3::error::ErrorMetadata;
+    use aws_sdk_s3::primitives::SdkBody;
+    use aws_sdk_s3::types::error::NoSuchKey;
+
+    fn get_error(error: GetObjectError, status: u16) -> SdkError<GetObjectError> {
+        SdkError::service_error(error, HttpResponse::new(status.try_into().unwrap(), SdkBody::empty()))
+    }
+
+    fn generic(code: Option<&str>) -> GetObjectError {
+        let mut meta = ErrorMetadata::builder();
+        if let Some(code) = code {
+            meta = meta.code(code);
+        }
+        GetObjectError::generic(meta.build())
+    }
+
+    #[test]
+    fn breaker_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+        assert_eq!(breaker.trips.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn half_open_breaker_reopens_on_failure_and_closes_on_success() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // Cool-down selesai
+        breaker.open_until_ms.store(0, Ordering::Relaxed);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.allow());
+
+        // Satu kegagalan di half-open langsung membuka lagi
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.trips.load(Ordering::Relaxed), 2);
+
+        breaker.open_until_ms.store(0, Ordering::Relaxed);
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn breaker_threshold_is_at_least_one() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn missing_key_is_a_miss() {
+        let error = GetObjectError::NoSuchKey(NoSuchKey::builder().build());
+        assert!(is_not_found(&get_error(error, 404)));
+        assert!(is_not_found(&get_error(generic(None), 404)));
+    }
+
+    #[test]
+    fn missing_bucket_is_an_error() {
+        assert!(!is_not_found(&get_error(generic(Some("NoSuchBucket")), 404)));
+        assert!(!is_not_found(&get_error(generic(Some("AccessDenied")), 403)));
+        assert!(!is_not_found(&get_error(generic(None), 500)));
     }
-    
 }
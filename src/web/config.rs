@@ -0,0 +1,119 @@
+// Konfigurasi per layer dari file JSON (LAYERS_CONFIG, default "layers.json").
+//
+// Contoh:
+// {
+//   "roads":   { "minzoom": 5, "maxzoom": 18 },
+//   "parcels": { "minzoom": 12, "datetime_column": "updated_at" },
+//   "zones":   { "key_columns": ["region", "code"], "properties": ["name", "population"], "group": "admin" },
+//   "buildings": { "prep": "shadow", "sync_trigger": true },
+//   "parcels.centroid": { "minzoom": 14 },
+//   "survey_points": { "assume_srid": 32748 },
+//   "landuse": { "make_valid": true }
+// }
+//
+// Layer yang tidak ada di file memakai default dari env DEFAULT_MINZOOM / DEFAULT_MAXZOOM.
+// File yang tidak valid menggagalkan startup server dan `prepare` (lihat validate); default
+// mode persiapan tidak boleh menimpa layer yang dikonfigurasi `"prep": "none"`.
+use std::collections::HashMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use log::info;
+use super::prep::PrepMode;
+
+// Zoom tertinggi yang didukung; 2^z harus muat di u32 untuk x/y
+pub const MAX_SUPPORTED_ZOOM: u32 = 30;
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayerConfig {
+    pub minzoom: Option<u32>,
+    pub maxzoom: Option<u32>,
+    // Kolom waktu untuk filter `datetime` di OGC API - Features (default: kolom date/timestamp pertama).
+    // Tidak memengaruhi isi tile, jadi tidak ikut fingerprint jika kosong.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datetime_column: Option<String>,
+    // Kolom id feature MVT (lihat web::feature_id); default: gid/id, lalu primary key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_columns: Option<Vec<String>>,
+    // Kolom atribut yang ikut ditulis ke tile MVT (default: hanya id + geometri)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<Vec<String>>,
+    // Grup untuk style gabungan /styles/groups/{group}.json (default: nama schema)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    // Cara menyiapkan geom_3857: column / shadow / expression / none (lihat web::prep)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prep: Option<PrepMode>,
+    // Trigger yang menjaga geom_3857 tetap sinkron dengan kolom asli (mode column / shadow)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_trigger: Option<bool>,
+    // SRID data untuk kolom tanpa SRID (SRID 0); default dari env ASSUME_SRID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assume_srid: Option<i32>,
+    // Tulis hasil ST_MakeValid untuk geometri invalid (lihat web::validity); default env GEOM_MAKE_VALID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub make_valid: Option<bool>,
+}
+
+impl LayerConfig {
+    pub fn minzoom(&self) -> u32 {
+        self.minzoom.unwrap_or_else(|| env_zoom("DEFAULT_MINZOOM", 0))
+    }
+
+    pub fn maxzoom(&self) -> u32 {
+        self.maxzoom
+            .unwrap_or_else(|| env_zoom("DEFAULT_MAXZOOM", 22))
+            .min(MAX_SUPPORTED_ZOOM)
+    }
+
+    /// Representasi stabil untuk fingerprint versi layer
+    pub fn fingerprint(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+fn env_zoom(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+static LAYERS_CONFIG: Lazy<Result<HashMap<String, LayerConfig>, String>> = Lazy::new(|| {
+    let path = std::env::var("LAYERS_CONFIG").unwrap_or_else(|_| "layers.json".to_string());
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(_) => {
+            info!("No layer config at {}, using defaults", path);
+            return Ok(HashMap::new());
+        }
+    };
+
+    match serde_json::from_str::<HashMap<String, LayerConfig>>(&content) {
+        Ok(config) => {
+            info!("Loaded config for {} layers from {}", config.len(), path);
+            Ok(config)
+        }
+        Err(e) => Err(format!("Invalid layer config {}: {}", path, e)),
+    }
+});
+
+/// Baca LAYERS_CONFIG dan kembalikan error jika file ada tapi tidak valid
+pub fn validate() -> Result<(), String> {
+    LAYERS_CONFIG.as_ref().map(|_| ()).map_err(|e| e.clone())
+}
+
+/// Konfigurasi per id layer. Layer `tabel.kolom` (tabel dengan beberapa geometri) memakai
+/// entri nama tabel jika tidak punya entri sendiri. Jika file tidak valid semua layer
+/// dianggap `"prep": "none"` supaya tidak ada yang ditulis ke database.
+pub fn layer_config(layer_id: &str) -> LayerConfig {
+    let Ok(layers) = LAYERS_CONFIG.as_ref() else {
+        return LayerConfig { prep: Some(PrepMode::ReadOnly), ..LayerConfig::default() };
+    };
+    layers
+        .get(layer_id)
+        .or_else(|| layer_id.split_once('.').and_then(|(table, _)| layers.get(table)))
+        .cloned()
+        .unwrap_or_default()
+}
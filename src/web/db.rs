@@ -1,113 +1,414 @@
-// src/db/migrations.rs
-use sqlx::PgPool;
-
-pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        r#"
-        CREATE OR REPLACE FUNCTION public.get_tile(
-            p_table text,
-            p_z integer,
-            p_x integer,
-            p_y integer,
-            p_minx double precision,
-            p_miny double precision,
-            p_maxx double precision,
-            p_maxy double precision
-        )
-        RETURNS bytea AS
-        $$
-        DECLARE
-            mvt bytea;
-            bbox geometry;
-            pk_col text;
-            sql_query text;
+// Migrasi objek database milik server (tabel versi layer). Function get_tile lama di public
+// dibuang; query tile sekarang dirender oleh web::tile_query.
+//
+// Migrasi berurutan per versi dan dicatat di {SERVICE_SCHEMA}.schema_migrations beserta
+// checksum SQL-nya. Migrasi yang sudah terpasang tidak boleh diubah: checksum yang berbeda
+// menghentikan startup dan `migrate up`. Perubahan objek = migrasi baru di akhir MIGRATIONS.
+//
+//   SERVICE_SCHEMA   schema untuk objek server dan tabel migrasi (default "tile_service")
+//
+// Subcommand: `migrate status`, `migrate up [--to N]`, `migrate down [--to N]` (lihat cli).
+use std::fmt;
+use std::time::Instant;
+use log::warn;
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, PgConnection, PgPool, Row};
+use super::utils::{create_schema_sql, quote_ident, quote_literal};
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    /// SQL dengan placeholder `{schema}` (identifier) dan `{schema_literal}` (string)
+    up: &'static str,
+    /// None = tidak bisa di-rollback
+    down: Option<&'static str>,
+}
+
+// Urutan tetap; jangan ubah migrasi yang sudah dirilis, tambahkan yang baru di akhir
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "tile_layer_versions",
+        // Token versi manual per layer (lihat web::versions). Instalasi lama menyimpannya di public.
+        up: r#"
+        CREATE TABLE IF NOT EXISTS {schema}.tile_layer_versions (
+            layer text PRIMARY KEY,
+            epoch bigint NOT NULL DEFAULT 0,
+            updated_at timestamptz NOT NULL DEFAULT now()
+        );
+        DO $m$
         BEGIN
-            -- 1️⃣ Buat bounding box dalam SRID 3857
-            bbox := ST_MakeEnvelope(p_minx, p_miny, p_maxx, p_maxy, 3857);
-
-            -- 2️⃣ Cek kolom PK yang ada: id, gid, atau generate row_number
-            SELECT column_name
-            INTO pk_col
-            FROM information_schema.columns
-            WHERE table_name = p_table
-            AND column_name IN ('id','gid')
-            ORDER BY column_name ASC
-            LIMIT 1;
-
-            -- 3️⃣ Buat query dynamic menggunakan geom_3857 (sudah dalam SRID 3857)
-            IF pk_col IS NULL THEN
-                -- tidak ada id/gid, generate row_number
-                sql_query := format($f$
-                    SELECT ST_AsMVT(tile, %L, 4096, 'geom', 'gid')
-                    FROM (
-                        SELECT
-                            row_number() OVER () AS gid,
-                            ST_AsMVTGeom(
-                                CASE
-                                    WHEN %s >= 17 THEN geom_3857
-                                    WHEN %s <= 5 THEN ST_SimplifyVW(geom_3857, %s)
-                                    WHEN %s <= 8 THEN ST_SimplifyVW(geom_3857, %s)
-                                    ELSE ST_SimplifyVW(geom_3857, %s)
-                                END,
-                                $1,
-                                4096, 256, true
-                            ) AS geom
-                        FROM %I
-                        WHERE geom_3857 && $1
-                    ) tile
-                $f$,
-                    p_table,
-                    p_z,
-                    p_z, 1e-6 * POWER(2, 17 - p_z),
-                    p_z, 1e-7 * POWER(2, 17 - p_z),
-                    1e-8 * POWER(2, 17 - p_z),
-                    p_table
-                );
-            ELSE
-                -- pakai kolom PK yang ada
-                sql_query := format($f$
-                    SELECT ST_AsMVT(tile, %L, 4096, 'geom', 'gid')
-                    FROM (
-                        SELECT
-                            %I AS gid,
-                            ST_AsMVTGeom(
-                                CASE
-                                    WHEN %s >= 17 THEN geom_3857
-                                    WHEN %s <= 5 THEN ST_SimplifyVW(geom_3857, %s)
-                                    WHEN %s <= 8 THEN ST_SimplifyVW(geom_3857, %s)
-                                    ELSE ST_SimplifyVW(geom_3857, %s)
-                                END,
-                                $1,
-                                4096, 256, true
-                            ) AS geom
-                        FROM %I
-                        WHERE geom_3857 && $1
-                    ) tile
-                $f$,
-                    p_table,
-                    pk_col,
-                    p_z,
-                    p_z, 1e-6 * POWER(2, 17 - p_z),
-                    p_z, 1e-7 * POWER(2, 17 - p_z),
-                    1e-8 * POWER(2, 17 - p_z),
-                    p_table
-                );
+            IF {schema_literal} <> 'public' AND to_regclass('public.tile_layer_versions') IS NOT NULL THEN
+                INSERT INTO {schema}.tile_layer_versions (layer, epoch, updated_at)
+                SELECT layer, epoch, updated_at FROM public.tile_layer_versions
+                ON CONFLICT (layer) DO NOTHING;
+                DROP TABLE public.tile_layer_versions;
             END IF;
+        END
+        $m$;
+        "#,
+        down: Some("DROP TABLE IF EXISTS {schema}.tile_layer_versions;"),
+    },
+    Migration {
+        version: 2,
+        name: "drop_public_get_tile",
+        // Semua overload get_tile yang dulu dibuat di public (tanpa p_srid / p_id_expr /
+        // p_properties / p_relation, dan versi terakhir sebelum SERVICE_SCHEMA)
+        up: r#"
+        DROP FUNCTION IF EXISTS public.get_tile(text, integer, integer, integer, double precision, double precision, double precision, double precision);
+        DROP FUNCTION IF EXISTS public.get_tile(text, integer, integer, integer, double precision, double precision, double precision, double precision, integer);
+        DROP FUNCTION IF EXISTS public.get_tile(text, integer, integer, integer, double precision, double precision, double precision, double precision, integer, text);
+        DROP FUNCTION IF EXISTS public.get_tile(text, integer, integer, integer, double precision, double precision, double precision, double precision, integer, text, text[]);
+        DROP FUNCTION IF EXISTS public.get_tile(text, integer, integer, integer, double precision, double precision, double precision, double precision, integer, text, text[], text);
+        "#,
+        down: None,
+    },
+];
+
+
+pub fn service_schema() -> String {
+    std::env::var("SERVICE_SCHEMA").unwrap_or_else(|_| "tile_service".to_string())
+}
+
+
+/// Nama tabel/function di SERVICE_SCHEMA, sudah di-quote
+pub fn service_object(name: &str) -> String {
+    format!("{}.{}", quote_ident(&service_schema()), quote_ident(name))
+}
+
+
+impl Migration {
+    /// Checksum dari SQL up sebelum placeholder diisi, jadi tidak bergantung pada SERVICE_SCHEMA
+    pub fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.up.as_bytes()))
+    }
+
+    fn render(sql: &str, schema: &str) -> String {
+        sql.replace("{schema_literal}", &quote_literal(schema))
+            .replace("{schema}", &quote_ident(schema))
+    }
+
+    pub fn up_sql(&self, schema: &str) -> String {
+        Self::render(self.up, schema)
+    }
+
+    pub fn down_sql(&self, schema: &str) -> Option<String> {
+        self.down.map(|sql| Self::render(sql, schema))
+    }
+}
+
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(sqlx::Error),
+    ChecksumMismatch { version: i32, name: String },
+    Irreversible { version: i32, name: &'static str },
+    UnknownVersion(i32),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(e) => write!(f, "{}", e),
+            MigrationError::ChecksumMismatch { version, name } => write!(
+                f,
+                "migration {:03} {} was changed after it was applied (checksum mismatch); add a new migration instead",
+                version, name
+            ),
+            MigrationError::Irreversible { version, name } => {
+                write!(f, "migration {:03} {} cannot be rolled back", version, name)
+            }
+            MigrationError::UnknownVersion(version) => write!(f, "unknown migration version {}", version),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(e: sqlx::Error) -> Self {
+        MigrationError::Database(e)
+    }
+}
+
+
+/// Migrasi yang tercatat di database
+pub struct AppliedMigration {
+    pub version: i32,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: String,
+}
+
+
+pub enum MigrationState {
+    Applied { applied_at: String },
+    Pending,
+    /// SQL berubah setelah dipasang
+    Modified { applied_at: String },
+    /// Tercatat di database tapi tidak dikenal binary ini (binary lebih lama)
+    Unknown { applied_at: String },
+}
+
+
+fn migrations_table(schema: &str) -> String {
+    format!("{}.schema_migrations", quote_ident(schema))
+}
 
-            -- 4️⃣ Execute query
-            EXECUTE sql_query INTO mvt USING bbox;
 
-            RETURN COALESCE(mvt, ''::bytea);
+/// Isi schema_migrations; kosong jika tabel belum ada. Hanya membaca (aman untuk dry run).
+pub async fn applied_migrations(pool: &PgPool) -> Result<Vec<AppliedMigration>, sqlx::Error> {
+    let table = migrations_table(&service_schema());
+    let exists: bool = sqlx::query("SELECT to_regclass($1) IS NOT NULL AS found")
+        .bind(&table)
+        .fetch_one(pool)
+        .await?
+        .try_get("found")?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+    sqlx::query(&format!(
+        "SELECT version, name, checksum, applied_at::text AS applied_at FROM {} ORDER BY version",
+        table
+    ))
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|row| {
+        Ok(AppliedMigration {
+            version: row.try_get("version")?,
+            name: row.try_get("name")?,
+            checksum: row.try_get("checksum")?,
+            applied_at: row.try_get("applied_at")?,
+        })
+    })
+    .collect()
+}
 
-        END;
-        $$ LANGUAGE plpgsql STABLE STRICT;
 
-        "#
-    )
-    .execute(pool)
-    .await?;
+/// Status semua migrasi (kode + database), urut versi
+pub async fn migration_status(pool: &PgPool) -> Result<Vec<(i32, String, MigrationState)>, sqlx::Error> {
+    let applied = applied_migrations(pool).await?;
+    let mut status: Vec<(i32, String, MigrationState)> = MIGRATIONS
+        .iter()
+        .map(|m| {
+            let state = match applied.iter().find(|a| a.version == m.version) {
+                None => MigrationState::Pending,
+                Some(a) if a.checksum != m.checksum() => MigrationState::Modified { applied_at: a.applied_at.clone() },
+                Some(a) => MigrationState::Applied { applied_at: a.applied_at.clone() },
+            };
+            (m.version, m.name.to_string(), state)
+        })
+        .collect();
+    for a in applied.iter().filter(|a| MIGRATIONS.iter().all(|m| m.version != a.version)) {
+        status.push((a.version, a.name.clone(), MigrationState::Unknown { applied_at: a.applied_at.clone() }));
+    }
+    status.sort_by_key(|(version, _, _)| *version);
+    Ok(status)
+}
 
-    println!("✓ Function get_tile created/updated successfully");
-    
+
+// Satu proses saja yang menjalankan migrasi; instance lain menunggu lalu melihat semuanya sudah terpasang
+async fn with_migration_lock<T>(
+    pool: &PgPool,
+    run: impl AsyncFnOnce(&mut PgConnection, &str) -> Result<T, MigrationError>,
+) -> Result<T, MigrationError> {
+    let schema = service_schema();
+    let lock_key = format!("{}.schema_migrations", schema);
+    let mut conn = pool.acquire().await?;
+    sqlx::query("SELECT pg_advisory_lock(hashtext($1))")
+        .bind(&lock_key)
+        .execute(&mut *conn)
+        .await?;
+
+    let result = async {
+        sqlx::query(&create_schema_sql(&schema))
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {} (
+                version integer PRIMARY KEY,
+                name text NOT NULL,
+                checksum text NOT NULL,
+                applied_at timestamptz NOT NULL DEFAULT now(),
+                execution_ms bigint NOT NULL DEFAULT 0
+            )
+            "#,
+            migrations_table(&schema)
+        ))
+        .execute(&mut *conn)
+        .await?;
+        run(&mut conn, &schema).await
+    }
+    .await;
+
+    // Lock session harus dilepas juga saat gagal, sebelum koneksi kembali ke pool
+    sqlx::query("SELECT pg_advisory_unlock(hashtext($1))")
+        .bind(&lock_key)
+        .execute(&mut *conn)
+        .await?;
+    result
+}
+
+
+async fn applied_versions(conn: &mut PgConnection, schema: &str) -> Result<Vec<(i32, String)>, sqlx::Error> {
+    sqlx::query(&format!("SELECT version, checksum FROM {} ORDER BY version", migrations_table(schema)))
+        .fetch_all(&mut *conn)
+        .await?
+        .iter()
+        .map(|row| Ok((row.try_get("version")?, row.try_get("checksum")?)))
+        .collect()
+}
+
+
+/// Pasang migrasi yang belum ada sampai versi `to` (semua jika None), masing-masing dalam
+/// transaksi sendiri. Mengembalikan migrasi yang dipasang.
+pub async fn migrate_up(pool: &PgPool, to: Option<i32>) -> Result<Vec<&'static Migration>, MigrationError> {
+    if let Some(to) = to
+        && !MIGRATIONS.iter().any(|m| m.version == to)
+    {
+        return Err(MigrationError::UnknownVersion(to));
+    }
+
+    with_migration_lock(pool, async |conn, schema| {
+        let applied = applied_versions(conn, schema).await?;
+        for (version, checksum) in &applied {
+            if let Some(m) = MIGRATIONS.iter().find(|m| m.version == *version)
+                && m.checksum() != *checksum
+            {
+                return Err(MigrationError::ChecksumMismatch { version: m.version, name: m.name.to_string() });
+            }
+        }
+
+        let mut done = Vec::new();
+        for m in MIGRATIONS
+            .iter()
+            .filter(|m| to.is_none_or(|to| m.version <= to))
+            .filter(|m| applied.iter().all(|(v, _)| *v != m.version))
+        {
+            let started = Instant::now();
+            let mut tx = conn.begin().await?;
+            sqlx::raw_sql(&m.up_sql(schema)).execute(&mut *tx).await?;
+            sqlx::query(&format!(
+                "INSERT INTO {} (version, name, checksum, execution_ms) VALUES ($1, $2, $3, $4)",
+                migrations_table(schema)
+            ))
+            .bind(m.version)
+            .bind(m.name)
+            .bind(m.checksum())
+            .bind(started.elapsed().as_millis() as i64)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            println!("✓ Migration {:03} {} applied", m.version, m.name);
+            done.push(m);
+        }
+        Ok(done)
+    })
+    .await
+}
+
+
+/// Rollback migrasi terpasang dengan versi > `to`; default hanya migrasi terakhir.
+/// Semua migrasi yang akan di-rollback dicek dulu, jadi migrasi tanpa down tidak setengah jalan.
+pub async fn migrate_down(pool: &PgPool, to: Option<i32>) -> Result<Vec<&'static Migration>, MigrationError> {
+    if let Some(to) = to
+        && to != 0
+        && !MIGRATIONS.iter().any(|m| m.version == to)
+    {
+        return Err(MigrationError::UnknownVersion(to));
+    }
+
+    with_migration_lock(pool, async |conn, schema| {
+        let applied = applied_versions(conn, schema).await?;
+        let to = match to {
+            Some(to) => to,
+            None => match applied.iter().rev().nth(1) {
+                Some((version, _)) => *version,
+                None => 0,
+            },
+        };
+
+        let mut targets = Vec::new();
+        for (version, _) in applied.iter().rev().filter(|(v, _)| *v > to) {
+            let Some(m) = MIGRATIONS.iter().find(|m| m.version == *version) else {
+                return Err(MigrationError::UnknownVersion(*version));
+            };
+            if m.down.is_none() {
+                return Err(MigrationError::Irreversible { version: m.version, name: m.name });
+            }
+            targets.push(m);
+        }
+
+        for m in &targets {
+            let mut tx = conn.begin().await?;
+            if let Some(sql) = m.down_sql(schema) {
+                sqlx::raw_sql(&sql).execute(&mut *tx).await?;
+            }
+            sqlx::query(&format!("DELETE FROM {} WHERE version = $1", migrations_table(schema)))
+                .bind(m.version)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            println!("✓ Migration {:03} {} rolled back", m.version, m.name);
+        }
+        Ok(targets)
+    })
+    .await
+}
+
+
+/// Dipanggil saat server start: pasang semua migrasi yang belum ada
+pub async fn run_migrations(pool: &PgPool) -> Result<(), MigrationError> {
+    let applied = migrate_up(pool, None).await?;
+    if applied.is_empty() {
+        println!("✓ Database objects are up to date ({})", service_schema());
+    }
+    for (version, name, state) in migration_status(pool).await? {
+        if let MigrationState::Unknown { .. } = state {
+            warn!("Migration {:03} {} is applied but unknown to this build", version, name);
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+
+/// Mode read-only (lihat web::privileges): migrasi tidak dipasang, hanya dilaporkan.
+/// Server tetap jalan; objek yang dibutuhkan dipasang dengan `migrate up` oleh role lain.
+pub async fn check_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
+    for (version, name, state) in migration_status(pool).await? {
+        match state {
+            MigrationState::Pending => warn!(
+                "Migration {:03} {} is not applied; run `vector_tile_services migrate up` with a role that has DDL privileges",
+                version, name
+            ),
+            MigrationState::Modified { .. } => warn!("Migration {:03} {} changed after it was applied", version, name),
+            MigrationState::Unknown { .. } => warn!("Migration {:03} {} is applied but unknown to this build", version, name),
+            MigrationState::Applied { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+
+/// Migrasi yang akan dipasang run_migrations (untuk `prepare --dry-run`), sebagai
+/// (jenis, keterangan). Hanya membaca katalog.
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<(&'static str, String)>, sqlx::Error> {
+    let schema = service_schema();
+    let mut pending = Vec::new();
+    for (version, name, state) in migration_status(pool).await? {
+        match state {
+            MigrationState::Pending => pending.push((
+                "migration",
+                format!("{:03} {} (schema {})", version, name, quote_ident(&schema)),
+            )),
+            MigrationState::Modified { .. } => pending.push((
+                "modified",
+                format!("{:03} {} changed after it was applied; startup will fail", version, name),
+            )),
+            _ => {}
+        }
+    }
+    Ok(pending)
+}
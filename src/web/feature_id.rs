@@ -0,0 +1,246 @@
+// ID feature MVT yang stabil antar tile dan antar query (untuk feature-state & identify).
+//
+// Urutan penentuan per layer:
+//   1. override `key_columns` di layers.json
+//   2. kolom gid / id (perilaku lama, supaya id yang sudah dipakai client tidak berubah)
+//   3. primary key dari pg_index, termasuk nama lain & composite key
+//   4. hash geometri (geom_3857) jika tabel tidak punya key sama sekali
+//
+// Key integer tunggal dipakai langsung; key lain (text, uuid, composite) di-hash ke
+// bilangan bulat non-negatif karena id feature MVT harus unsigned integer.
+use log::warn;
+use sqlx::{PgPool, Row};
+use super::config::layer_config;
+use super::features::Column;
+use super::utils::quote_ident;
+use super::web_handler::Layer;
+
+const INTEGER_TYPES: &[&str] = &["int2", "int4", "int8"];
+
+// hashtextextended menghasilkan bigint bertanda; dipotong ke 53 bit supaya id >= 0
+// dan tetap presisi sebagai Number di JavaScript (feature-state MapLibre)
+const HASH_MASK: &str = "9007199254740991";
+
+
+#[derive(Clone, Debug, Default)]
+pub enum FeatureId {
+    /// Kolom integer, dipakai apa adanya
+    Column(String),
+    /// Kolom non-integer atau composite key, di-hash
+    Hashed(Vec<String>),
+    /// Tidak ada key: hash WKB geometri
+    #[default]
+    Geometry,
+}
+
+impl FeatureId {
+    /// Ekspresi SQL id feature, tanpa alias tabel (nama kolom di-quote)
+    pub fn sql(&self, geom_column: &str) -> String {
+        match self {
+            FeatureId::Column(name) => quote_ident(name),
+            FeatureId::Hashed(columns) => {
+                let values: Vec<String> = columns
+                    .iter()
+                    .map(|c| format!("{}::text", quote_ident(c)))
+                    .collect();
+                format!(
+                    "(hashtextextended(concat_ws(E'\\x1f', {}), 0) & {})",
+                    values.join(", "),
+                    HASH_MASK
+                )
+            }
+            FeatureId::Geometry => format!(
+                "(hashtextextended(md5(ST_AsBinary({})), 0) & {})",
+                quote_ident(geom_column),
+                HASH_MASK
+            ),
+        }
+    }
+
+    /// Urutan stabil untuk paging (alias `t`): kolom key apa adanya supaya index primary key
+    /// bisa dipakai, atau ctid untuk tabel tanpa key. View dan foreign table tidak punya ctid
+    /// (`has_ctid` false), jadi diurutkan menurut id feature-nya.
+    pub fn order_sql(&self, geom_column: &str, has_ctid: bool) -> String {
+        match self {
+            FeatureId::Column(name) => format!("t.{}", quote_ident(name)),
+            FeatureId::Hashed(columns) => {
+                let columns: Vec<String> = columns.iter().map(|c| format!("t.{}", quote_ident(c))).collect();
+                columns.join(", ")
+            }
+            FeatureId::Geometry if has_ctid => "t.ctid".to_string(),
+            FeatureId::Geometry => self.sql(geom_column),
+        }
+    }
+
+    /// Kondisi WHERE untuk mencari satu feature berdasarkan id ($1, di-bind sebagai text).
+    /// Key integer dibandingkan langsung dengan tipe kolomnya, jadi index key terpakai.
+    /// Id hash tidak bisa dibalik ke nilai key: pencarian menghitung hash untuk setiap baris
+    /// (sequential scan), kecuali ada expression index untuk ekspresi id tersebut.
+    pub(crate) fn lookup_sql(&self, geom_column: &str, columns: &[Column]) -> String {
+        match self {
+            FeatureId::Column(name) => {
+                let udt_name = columns
+                    .iter()
+                    .find(|c| &c.name == name)
+                    .map_or("int8", |c| c.udt_name.as_str());
+                format!("t.{} = $1::{}", quote_ident(name), udt_name)
+            }
+            _ => format!("{} = $1::int8", self.sql(geom_column)),
+        }
+    }
+
+    fn from_columns(names: &[String], columns: &[Column]) -> Option<FeatureId> {
+        let found: Vec<&Column> = names
+            .iter()
+            .filter_map(|n| columns.iter().find(|c| &c.name == n))
+            .collect();
+        if names.is_empty() || found.len() != names.len() {
+            return None;
+        }
+        match found.as_slice() {
+            [single] if INTEGER_TYPES.contains(&single.udt_name.as_str()) => {
+                Some(FeatureId::Column(single.name.clone()))
+            }
+            _ => Some(FeatureId::Hashed(names.to_vec())),
+        }
+    }
+}
+
+
+/// Kolom primary key tabel sesuai urutan di index
+pub(crate) async fn primary_key(db_pool: &PgPool, schema: &str, table: &str) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT a.attname::text AS column_name
+        FROM pg_index i
+        JOIN pg_class c ON c.oid = i.indrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+        WHERE n.nspname = $1 AND c.relname = $2 AND i.indisprimary
+        ORDER BY array_position(i.indkey::int2[], a.attnum)
+        "#
+    )
+    .bind(schema)
+    .bind(table)
+    .fetch_all(db_pool)
+    .await?;
+
+    rows.iter().map(|r| r.try_get("column_name")).collect()
+}
+
+
+/// Langkah 1-2 (tanpa katalog): `key_columns` jika semua kolomnya ada, lalu gid / id
+fn configured_or_conventional(key_columns: Option<&[String]>, columns: &[Column]) -> Option<FeatureId> {
+    key_columns
+        .and_then(|names| FeatureId::from_columns(names, columns))
+        .or_else(|| {
+            ["gid", "id"]
+                .iter()
+                .find_map(|name| FeatureId::from_columns(&[name.to_string()], columns))
+        })
+}
+
+
+pub(crate) async fn resolve(db_pool: &PgPool, layer: &Layer, columns: &[Column]) -> Result<FeatureId, sqlx::Error> {
+    let key_columns = layer_config(&layer.table_name).key_columns;
+    if let Some(names) = &key_columns
+        && FeatureId::from_columns(names, columns).is_none()
+    {
+        warn!(
+            "key_columns {:?} of layer {} do not exist, detecting key instead",
+            names, layer.table_name
+        );
+    }
+    if let Some(id) = configured_or_conventional(key_columns.as_deref(), columns) {
+        return Ok(id);
+    }
+
+    let pk = primary_key(db_pool, &layer.schema, &layer.source_table).await?;
+    if let Some(id) = FeatureId::from_columns(&pk, columns) {
+        return Ok(id);
+    }
+
+    warn!("Layer {} has no key column, feature ids are hashed from geometry", layer.table_name);
+    Ok(FeatureId::Geometry)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(spec: &[(&str, &str)]) -> Vec<Column> {
+        spec.iter()
+            .map(|(name, udt_name)| Column { name: name.to_string(), udt_name: udt_name.to_string() })
+            .collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn resolved(key_columns: Option<&[&str]>, spec: &[(&str, &str)]) -> Option<FeatureId> {
+        configured_or_conventional(key_columns.map(names).as_deref(), &columns(spec))
+    }
+
+    #[test]
+    fn integer_keys_are_used_as_is() {
+        for udt_name in INTEGER_TYPES {
+            let id = FeatureId::from_columns(&names(&["code"]), &columns(&[("code", udt_name)]));
+            assert!(matches!(id, Some(FeatureId::Column(name)) if name == "code"));
+        }
+    }
+
+    #[test]
+    fn other_keys_are_hashed() {
+        let cols = columns(&[("code", "numeric"), ("region", "text"), ("n", "int4")]);
+        assert!(matches!(FeatureId::from_columns(&names(&["code"]), &cols), Some(FeatureId::Hashed(_))));
+        let id = FeatureId::from_columns(&names(&["region", "n"]), &cols);
+        assert!(matches!(id, Some(FeatureId::Hashed(c)) if c == ["region", "n"]));
+        assert!(FeatureId::from_columns(&names(&["region", "missing"]), &cols).is_none());
+        assert!(FeatureId::from_columns(&[], &cols).is_none());
+    }
+
+    #[test]
+    fn key_columns_win_over_gid_and_id() {
+        let spec = [("gid", "int4"), ("id", "int4"), ("region", "text"), ("code", "int8")];
+        let id = resolved(Some(&["region", "code"]), &spec);
+        assert!(matches!(id, Some(FeatureId::Hashed(c)) if c == ["region", "code"]));
+        let id = resolved(Some(&["code"]), &spec);
+        assert!(matches!(id, Some(FeatureId::Column(c)) if c == "code"));
+    }
+
+    #[test]
+    fn missing_key_columns_fall_back_to_gid_then_id() {
+        let id = resolved(Some(&["nope"]), &[("id", "int4"), ("gid", "int8")]);
+        assert!(matches!(id, Some(FeatureId::Column(c)) if c == "gid"));
+        let id = resolved(None, &[("id", "uuid"), ("name", "text")]);
+        assert!(matches!(id, Some(FeatureId::Hashed(c)) if c == ["id"]));
+        // Tanpa gid / id: lanjut ke primary key (butuh katalog), lalu hash geometri
+        assert!(resolved(None, &[("name", "text")]).is_none());
+    }
+
+    #[test]
+    fn hash_mask_keeps_53_bits() {
+        assert_eq!(HASH_MASK.parse::<u64>().unwrap(), (1u64 << 53) - 1);
+        let hashed = FeatureId::Hashed(names(&["a", "b"])).sql("geom_3857");
+        assert_eq!(
+            hashed,
+            "(hashtextextended(concat_ws(E'\\x1f', \"a\"::text, \"b\"::text), 0) & 9007199254740991)"
+        );
+        let geometry = FeatureId::Geometry.sql("geom_3857");
+        assert!(geometry.ends_with(&format!("& {})", HASH_MASK)));
+        assert_eq!(FeatureId::Column("gid".to_string()).sql("geom_3857"), "\"gid\"");
+    }
+
+    #[test]
+    fn order_and_lookup() {
+        let id = FeatureId::Column("gid".to_string());
+        assert_eq!(id.order_sql("geom_3857", true), "t.\"gid\"");
+        assert_eq!(id.lookup_sql("geom_3857", &columns(&[("gid", "int4")])), "t.\"gid\" = $1::int4");
+        assert_eq!(FeatureId::Hashed(names(&["a", "b"])).order_sql("geom_3857", false), "t.\"a\", t.\"b\"");
+        assert_eq!(FeatureId::Geometry.order_sql("geom_3857", true), "t.ctid");
+        assert_eq!(FeatureId::Geometry.order_sql("geom_3857", false), FeatureId::Geometry.sql("geom_3857"));
+        assert!(FeatureId::Geometry.lookup_sql("geom_3857", &[]).ends_with(" = $1::int8"));
+    }
+}
@@ -0,0 +1,548 @@
+// OGC API - Features (Part 1: Core) di atas layer yang sama dengan tile.
+//
+//   /collections/{layer}/items        FeatureCollection GeoJSON: bbox, limit/offset, datetime, filter properti
+//   /collections/{layer}/items/{id}   satu Feature berdasarkan id feature MVT (web::feature_id)
+//
+// Geometri diambil dari geom_3857 dan dikirim dalam EPSG:4326 (CRS84). Hasil query
+// di-stream baris per baris dari Postgres, tidak dikumpulkan dulu di memory.
+use actix_web::{web, HttpResponse, HttpRequest};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use futures_util::{StreamExt, TryStreamExt};
+use log::{error, warn};
+use serde_json::{json, Value};
+use sqlx::{PgPool, Row};
+use tokio::sync::mpsc;
+use super::config::{self, MAX_SUPPORTED_ZOOM};
+use super::utils::{quote_ident, quote_literal};
+use super::web_handler::{Layer, base_url, get_layer_detail, ensure_layers_loaded, json_error, layer_error};
+
+const GEOJSON_MEDIA_TYPE: &str = "application/geo+json";
+const DEFAULT_LIMIT: u64 = 10;
+const MAX_LIMIT: u64 = 10_000;
+// Batas lintang Web Mercator; bbox di luar ini tidak bisa ditransform ke 3857
+const MAX_MERCATOR_LAT: f64 = 85.0511287798;
+
+// Tipe kolom yang bisa dipakai filter datetime
+const TEMPORAL_TYPES: &[&str] = &["timestamptz", "timestamp", "date"];
+
+
+#[derive(Clone, Debug)]
+pub(crate) struct Column {
+    pub(crate) name: String,
+    pub(crate) udt_name: String,
+}
+
+impl Column {
+    pub(crate) fn is_geometry(&self) -> bool {
+        self.udt_name == "geometry" || self.udt_name == "geography"
+    }
+}
+
+
+/// Kolom tabel layer sesuai urutan di tabel. Dibaca dari pg_attribute karena materialized view
+/// tidak muncul di information_schema.columns; tipe domain dilaporkan dengan tipe dasarnya
+/// (sama seperti udt_name).
+pub(crate) async fn layer_columns(db_pool: &PgPool, layer: &Layer) -> Result<Vec<Column>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            a.attname::text AS column_name,
+            (CASE WHEN ty.typtype = 'd' THEN base.typname ELSE ty.typname END)::text AS udt_name
+        FROM pg_attribute a
+        JOIN pg_type ty ON ty.oid = a.atttypid
+        LEFT JOIN pg_type base ON base.oid = ty.typbasetype
+        WHERE a.attrelid = to_regclass(format('%I.%I', $1::text, $2::text))
+        AND a.attnum > 0
+        AND NOT a.attisdropped
+        ORDER BY a.attnum
+        "#
+    )
+    .bind(&layer.schema)
+    .bind(&layer.source_table)
+    .fetch_all(db_pool)
+    .await?;
+
+    rows.iter()
+        .map(|r| Ok(Column { name: r.try_get("column_name")?, udt_name: r.try_get("udt_name")? }))
+        .collect()
+}
+
+
+// SELECT satu Feature GeoJSON (text) per baris dari relasi layer (web::prep), alias `t`
+fn feature_select(layer: &Layer, columns: &[Column]) -> String {
+    let mut hidden: Vec<String> = columns
+        .iter()
+        .filter(|c| c.is_geometry() && c.name != layer.geom_column)
+        .map(|c| quote_literal(&c.name))
+        .collect();
+    // geom_3857 dan ctid selalu ada di relasi, walau bukan kolom tabel sumber
+    hidden.push(quote_literal(&layer.geom_column));
+    hidden.push(quote_literal("ctid"));
+    let properties = format!("to_jsonb(t) - ARRAY[{}]::text[]", hidden.join(", "));
+    format!(
+        "SELECT json_build_object('type', 'Feature', 'id', {id}, \
+            'geometry', ST_AsGeoJSON(ST_Transform(t.{geom}, 4326), 7)::json, \
+            'properties', {properties})::text AS feature \
+         FROM {relation} t",
+        id = layer.feature_id.sql(&layer.geom_column),
+        geom = quote_ident(&layer.geom_column),
+        properties = properties,
+        relation = layer.relation,
+    )
+}
+
+
+// Klausa WHERE beserta argumennya; semua argumen di-bind sebagai text lalu di-cast di SQL
+#[derive(Default)]
+struct Filters {
+    clauses: Vec<String>,
+    args: Vec<String>,
+}
+
+impl Filters {
+    fn arg(&mut self, value: impl Into<String>) -> String {
+        self.args.push(value.into());
+        format!("${}", self.args.len())
+    }
+
+    fn sql(&self) -> String {
+        if self.clauses.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.clauses.join(" AND "))
+        }
+    }
+}
+
+
+// bbox=minx,miny,maxx,maxy dalam CRS84 (bbox 6 angka: z diabaikan)
+fn parse_bbox(value: &str) -> Result<[f64; 4], String> {
+    let numbers: Vec<f64> = value
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Invalid bbox: {}", value))?;
+
+    let bbox = match numbers.as_slice() {
+        [minx, miny, maxx, maxy] => [*minx, *miny, *maxx, *maxy],
+        [minx, miny, _, maxx, maxy, _] => [*minx, *miny, *maxx, *maxy],
+        _ => return Err(format!("Invalid bbox: {} (expected 4 or 6 numbers)", value)),
+    };
+    if bbox[0] > bbox[2] || bbox[1] > bbox[3] {
+        return Err(format!("Invalid bbox: {} (min must not exceed max)", value));
+    }
+    Ok(bbox)
+}
+
+
+// datetime: instant "2020-01-01T00:00:00Z" atau interval "start/end" dengan ".." / kosong untuk ujung terbuka
+fn datetime_filter(filters: &mut Filters, column: &Column, value: &str) -> Result<(), String> {
+    let col = format!("t.{}", quote_ident(&column.name));
+    let cast = &column.udt_name;
+
+    match value.split_once('/') {
+        None => {
+            if value.is_empty() || value == ".." {
+                return Err(format!("Invalid datetime: {}", value));
+            }
+            let p = filters.arg(value);
+            filters.clauses.push(format!("{} = {}::{}", col, p, cast));
+        }
+        Some((start, end)) => {
+            let open = |v: &str| v.is_empty() || v == "..";
+            if open(start) && open(end) {
+                return Ok(());
+            }
+            if !open(start) {
+                let p = filters.arg(start);
+                filters.clauses.push(format!("{} >= {}::{}", col, p, cast));
+            }
+            if !open(end) {
+                let p = filters.arg(end);
+                filters.clauses.push(format!("{} <= {}::{}", col, p, cast));
+            }
+        }
+    }
+    Ok(())
+}
+
+
+fn datetime_column<'a>(layer: &Layer, columns: &'a [Column]) -> Result<&'a Column, String> {
+    match config::layer_config(&layer.table_name).datetime_column {
+        Some(name) => columns
+            .iter()
+            .find(|c| c.name == name)
+            .ok_or_else(|| format!("Configured datetime_column {} does not exist", name)),
+        None => columns
+            .iter()
+            .find(|c| TEMPORAL_TYPES.contains(&c.udt_name.as_str()))
+            .ok_or_else(|| format!("Collection {} has no temporal column for datetime", layer.table_name)),
+    }
+}
+
+
+fn parse_number(name: &str, value: &str) -> Result<u64, HttpResponse> {
+    value
+        .parse::<u64>()
+        .map_err(|_| json_error(StatusCode::BAD_REQUEST, format!("Invalid {}: {}", name, value)))
+}
+
+
+// Error Postgres kelas 22 (data exception, mis. datetime tidak valid) = kesalahan input
+fn query_error(e: sqlx::Error) -> HttpResponse {
+    if let sqlx::Error::Database(db) = &e
+        && db.code().is_some_and(|c| c.starts_with("22"))
+    {
+        return json_error(StatusCode::BAD_REQUEST, db.message().to_string());
+    }
+    error!("Features query failed: {:?}", e);
+    json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query features")
+}
+
+
+async fn find_layer(db_pool: &PgPool, req: &HttpRequest, table_name: &str) -> Result<(Layer, Vec<Column>), HttpResponse> {
+    ensure_layers_loaded(db_pool, req).await;
+    let layer = match get_layer_detail(table_name.to_string()).await {
+        Some(layer) => layer,
+        None => {
+            let message = layer_error(table_name)
+                .await
+                .unwrap_or_else(|| format!("Collection not found: {}", table_name));
+            return Err(json_error(StatusCode::NOT_FOUND, message));
+        }
+    };
+
+    let columns = layer_columns(db_pool, &layer).await.map_err(|e| {
+        error!("Failed to read columns of {}: {:?}", table_name, e);
+        json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read collection schema")
+    })?;
+    Ok((layer, columns))
+}
+
+
+// Query string asli tanpa limit/offset, untuk link self/next/prev
+fn page_href(base: &str, table_name: &str, query: &str, limit: u64, offset: u64) -> String {
+    let mut params: Vec<&str> = query
+        .split('&')
+        .filter(|p| !p.is_empty() && !p.starts_with("limit=") && !p.starts_with("offset="))
+        .collect();
+    let paging = format!("limit={}&offset={}", limit, offset);
+    params.push(&paging);
+    format!("{}/collections/{}/items?{}", base, table_name, params.join("&"))
+}
+
+
+pub async fn collection_items(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    query: web::Query<Vec<(String, String)>>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (layer, columns) = match find_layer(db_pool.get_ref(), &req, &path.into_inner()).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    let mut filters = Filters::default();
+    let mut limit = DEFAULT_LIMIT;
+    let mut offset = 0;
+
+    for (name, value) in query.into_inner() {
+        match name.as_str() {
+            "limit" => match parse_number("limit", &value) {
+                Ok(n) => limit = n.clamp(1, MAX_LIMIT),
+                Err(resp) => return resp,
+            },
+            "offset" => match parse_number("offset", &value) {
+                Ok(n) => offset = n,
+                Err(resp) => return resp,
+            },
+            "bbox" => {
+                let bbox = match parse_bbox(&value) {
+                    Ok(b) => b,
+                    Err(msg) => return json_error(StatusCode::BAD_REQUEST, msg),
+                };
+                let [minx, miny, maxx, maxy] = bbox;
+                let (p1, p2, p3, p4) = (
+                    filters.arg(minx.to_string()),
+                    filters.arg(miny.max(-MAX_MERCATOR_LAT).to_string()),
+                    filters.arg(maxx.to_string()),
+                    filters.arg(maxy.min(MAX_MERCATOR_LAT).to_string()),
+                );
+                filters.clauses.push(format!(
+                    "t.{} && ST_Transform(ST_MakeEnvelope({}::float8, {}::float8, {}::float8, {}::float8, 4326), 3857)",
+                    quote_ident(&layer.geom_column), p1, p2, p3, p4
+                ));
+            }
+            "datetime" => {
+                let result = datetime_column(&layer, &columns)
+                    .and_then(|column| datetime_filter(&mut filters, column, &value));
+                if let Err(msg) = result {
+                    return json_error(StatusCode::BAD_REQUEST, msg);
+                }
+            }
+            "f" => {}
+            // Parameter lain = filter properti ?kolom=nilai (dibandingkan sebagai text)
+            _ => {
+                let Some(column) = columns.iter().find(|c| c.name == name && !c.is_geometry()) else {
+                    return json_error(StatusCode::BAD_REQUEST, format!("Unknown query parameter: {}", name));
+                };
+                let p = filters.arg(value);
+                filters.clauses.push(format!("t.{}::text = {}", quote_ident(&column.name), p));
+            }
+        }
+    }
+
+    // Urutan stabil untuk paging: key tabel, atau ctid jika tabel tidak punya key
+    let sql = format!(
+        "{}{} ORDER BY {} LIMIT {} OFFSET {}",
+        feature_select(&layer, &columns),
+        filters.sql(),
+        layer.feature_id.order_sql(&layer.geom_column, layer.has_ctid),
+        limit,
+        offset
+    );
+
+    // Query jalan di task terpisah; baris dikirim lewat channel kecil (backpressure ke Postgres)
+    let (tx, mut rx) = mpsc::channel::<Result<String, sqlx::Error>>(64);
+    let pool = db_pool.get_ref().clone();
+    let args = filters.args;
+    tokio::spawn(async move {
+        let mut q = sqlx::query(&sql);
+        for arg in &args {
+            q = q.bind(arg);
+        }
+        let mut rows = q.fetch(&pool);
+        loop {
+            let item = match rows.try_next().await {
+                Ok(Some(row)) => row.try_get::<String, _>("feature"),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            let failed = item.is_err();
+            // Client sudah putus -> hentikan query
+            if tx.send(item).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    // Tunggu baris pertama: error SQL (mis. datetime tidak valid) masih bisa jadi response 4xx/5xx
+    let first = match rx.recv().await {
+        Some(Ok(feature)) => Some(feature),
+        Some(Err(e)) => return query_error(e),
+        None => None,
+    };
+
+    let base = base_url(&req);
+    let query_string = req.query_string().to_string();
+    let table_name = layer.table_name.clone();
+    let footer = move |returned: u64| {
+        let mut links = vec![
+            json!({ "href": page_href(&base, &table_name, &query_string, limit, offset), "rel": "self", "type": GEOJSON_MEDIA_TYPE, "title": "This document" }),
+            json!({ "href": format!("{}/collections/{}", base, table_name), "rel": "collection", "type": "application/json", "title": table_name }),
+        ];
+        if returned == limit {
+            links.push(json!({ "href": page_href(&base, &table_name, &query_string, limit, offset + limit), "rel": "next", "type": GEOJSON_MEDIA_TYPE, "title": "Next page" }));
+        }
+        if offset > 0 {
+            links.push(json!({ "href": page_href(&base, &table_name, &query_string, limit, offset.saturating_sub(limit)), "rel": "prev", "type": GEOJSON_MEDIA_TYPE, "title": "Previous page" }));
+        }
+        format!("],\"numberReturned\":{},\"links\":{}}}", returned, Value::Array(links))
+    };
+
+    let header = Bytes::from_static(b"{\"type\":\"FeatureCollection\",\"features\":[");
+    let body = futures_util::stream::once(async move { Ok::<_, actix_web::Error>(header) }).chain(
+        // State: (channel, feature yang sudah diambil, jumlah feature terkirim, selesai)
+        futures_util::stream::unfold((rx, first, 0u64, false), move |(mut rx, pending, count, done)| {
+            let footer = footer.clone();
+            async move {
+                if done {
+                    return None;
+                }
+                let next = match pending {
+                    Some(feature) => Some(Ok(feature)),
+                    None => rx.recv().await,
+                };
+                match next {
+                    Some(Ok(feature)) => {
+                        let chunk = if count == 0 { feature } else { format!(",{}", feature) };
+                        Some((Ok(Bytes::from(chunk)), (rx, None, count + 1, false)))
+                    }
+                    None => Some((Ok(Bytes::from(footer(count))), (rx, None, count, true))),
+                    Some(Err(e)) => {
+                        // Header sudah terkirim; putuskan stream supaya client tahu response tidak lengkap
+                        warn!("Features stream aborted: {:?}", e);
+                        Some((Err(actix_web::error::ErrorInternalServerError("features stream aborted")), (rx, None, count, true)))
+                    }
+                }
+            }
+        }),
+    );
+
+    HttpResponse::Ok()
+        .content_type(GEOJSON_MEDIA_TYPE)
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .streaming(body)
+}
+
+
+#[derive(serde::Deserialize)]
+pub struct ItemPath {
+    table_name: String,
+    feature_id: String,
+}
+
+
+fn geojson_response(body: &Value) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(GEOJSON_MEDIA_TYPE)
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .body(body.to_string())
+}
+
+
+fn parse_feature(row: &sqlx::postgres::PgRow) -> Result<Value, HttpResponse> {
+    row.try_get::<String, _>("feature")
+        .ok()
+        .and_then(|f| serde_json::from_str(&f).ok())
+        .ok_or_else(|| json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read feature"))
+}
+
+
+// Satu feature berdasarkan id MVT (lihat web::feature_id). Id hash dicari dengan scan.
+async fn fetch_feature(db_pool: &PgPool, layer: &Layer, columns: &[Column], feature_id: &str) -> Result<Value, HttpResponse> {
+    let not_found = || json_error(StatusCode::NOT_FOUND, format!("Feature not found: {}", feature_id));
+    // Id feature MVT selalu bilangan bulat; nilai lain (atau di luar jangkauan tipe key) tidak ada
+    if feature_id.parse::<i64>().is_err() {
+        return Err(not_found());
+    }
+    let sql = format!(
+        "{} WHERE {} LIMIT 1",
+        feature_select(layer, columns),
+        layer.feature_id.lookup_sql(&layer.geom_column, columns)
+    );
+    match sqlx::query(&sql).bind(feature_id).fetch_optional(db_pool).await {
+        Ok(Some(row)) => parse_feature(&row),
+        Ok(None) => Err(not_found()),
+        Err(sqlx::Error::Database(db)) if db.code().as_deref() == Some("22003") => Err(not_found()),
+        Err(e) => Err(query_error(e)),
+    }
+}
+
+
+pub async fn collection_item(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<ItemPath>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let params = path.into_inner();
+    let (layer, columns) = match find_layer(db_pool.get_ref(), &req, &params.table_name).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    let mut feature = match fetch_feature(db_pool.get_ref(), &layer, &columns, &params.feature_id).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+
+    let base = base_url(&req);
+    feature["links"] = json!([
+        { "href": format!("{}/collections/{}/items/{}", base, layer.table_name, params.feature_id), "rel": "self", "type": GEOJSON_MEDIA_TYPE, "title": "This feature" },
+        { "href": format!("{}/collections/{}", base, layer.table_name), "rel": "collection", "type": "application/json", "title": layer.table_name },
+    ]);
+    geojson_response(&feature)
+}
+
+
+/// GET /layers/{layer}/features/{id}: id sama dengan id feature di tile MVT
+pub async fn layer_feature(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<ItemPath>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let params = path.into_inner();
+    let (layer, columns) = match find_layer(db_pool.get_ref(), &req, &params.table_name).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+    match fetch_feature(db_pool.get_ref(), &layer, &columns, &params.feature_id).await {
+        Ok(feature) => geojson_response(&feature),
+        Err(resp) => resp,
+    }
+}
+
+
+#[derive(serde::Deserialize)]
+pub struct IdentifyQuery {
+    lon: f64,
+    lat: f64,
+    z: u32,
+    tolerance_px: Option<f64>,
+    limit: Option<u64>,
+}
+
+
+const DEFAULT_TOLERANCE_PX: f64 = 3.0;
+const MAX_IDENTIFY_LIMIT: u64 = 100;
+
+
+/// GET /layers/{layer}/identify?lon=&lat=&z=&tolerance_px=
+/// Feature dalam radius tolerance_px (pixel tile 256 pada zoom z) dari titik klik, terdekat dulu.
+pub async fn identify(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    query: web::Query<IdentifyQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let params = query.into_inner();
+    if !(-180.0..=180.0).contains(&params.lon) || !(-MAX_MERCATOR_LAT..=MAX_MERCATOR_LAT).contains(&params.lat) {
+        return json_error(StatusCode::BAD_REQUEST, format!("Point {},{} is outside the Web Mercator extent", params.lon, params.lat));
+    }
+    if params.z > MAX_SUPPORTED_ZOOM {
+        return json_error(StatusCode::BAD_REQUEST, format!("Zoom level {} is not supported (max {})", params.z, MAX_SUPPORTED_ZOOM));
+    }
+    let tolerance_px = params.tolerance_px.unwrap_or(DEFAULT_TOLERANCE_PX);
+    if !(0.0..=256.0).contains(&tolerance_px) {
+        return json_error(StatusCode::BAD_REQUEST, "tolerance_px must be between 0 and 256");
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_IDENTIFY_LIMIT);
+
+    let (layer, columns) = match find_layer(db_pool.get_ref(), &req, &path.into_inner()).await {
+        Ok(found) => found,
+        Err(resp) => return resp,
+    };
+
+    // Ukuran 1 pixel (tile 256px) dalam meter Web Mercator pada zoom z
+    let pixel_size = 2.0 * std::f64::consts::PI * 6378137.0 / (256.0 * 2f64.powi(params.z as i32));
+    let geom = format!("t.{}", quote_ident(&layer.geom_column));
+    let sql = format!(
+        "{select} \
+         WHERE ST_DWithin({geom}, ST_Transform(ST_SetSRID(ST_MakePoint($1, $2), 4326), 3857), $3) \
+         ORDER BY {geom} <-> ST_Transform(ST_SetSRID(ST_MakePoint($1, $2), 4326), 3857) \
+         LIMIT $4",
+        select = feature_select(&layer, &columns),
+        geom = geom,
+    );
+
+    let rows = sqlx::query(&sql)
+        .bind(params.lon)
+        .bind(params.lat)
+        .bind(tolerance_px * pixel_size)
+        .bind(limit as i64)
+        .fetch_all(db_pool.get_ref())
+        .await;
+    let features = match rows {
+        Ok(rows) => match rows.iter().map(parse_feature).collect::<Result<Vec<_>, _>>() {
+            Ok(f) => f,
+            Err(resp) => return resp,
+        },
+        Err(e) => return query_error(e),
+    };
+
+    geojson_response(&json!({
+        "type": "FeatureCollection",
+        "features": features,
+        "numberReturned": features.len(),
+    }))
+}
@@ -1,4 +1,19 @@
 pub mod web_handler;
 pub mod utils;
 pub mod db;
-pub mod cache;
\ No newline at end of file
+pub mod cache;
+pub mod versions;
+pub mod config;
+pub mod tms;
+pub mod ogc;
+pub mod features;
+pub mod feature_id;
+pub mod wmts;
+pub mod styles;
+pub mod prep;
+pub mod backfill;
+pub mod plan;
+pub mod validity;
+pub mod tile_query;
+pub mod assets;
+pub mod privileges;
@@ -0,0 +1,376 @@
+// OGC API - Tiles (Part 1: Core) di atas katalog Layer yang sama dengan /layers.
+//
+//   /?f=json                                         landing page
+//   /conformance                                     conformance classes
+//   /collections, /collections/{layer}               katalog collection
+//   /collections/{layer}/tiles                       daftar tileset per tile matrix set
+//   /collections/{layer}/tiles/{tms}                 metadata tileset
+//   /collections/{layer}/tiles/{tms}/{z}/{row}/{col} tile MVT
+//   /tileMatrixSets, /tileMatrixSets/{tms}           definisi tile matrix set (TMS 2.0)
+//
+// Endpoint items (OGC API - Features) ada di web::features.
+use actix_web::{web, HttpResponse, HttpRequest};
+use actix_web::http::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use super::tms::{self, TileMatrixSet};
+use super::utils;
+use super::web_handler::{Layer, all_layers, base_url, get_layer_detail, ensure_layers_loaded, json_error, layer_error, serve_tile};
+
+const MVT_MEDIA_TYPE: &str = "application/vnd.mapbox-vector-tile";
+const CRS84: &str = "http://www.opengis.net/def/crs/OGC/1.3/CRS84";
+
+const CONFORMANCE: &[&str] = &[
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/json",
+    "http://www.opengis.net/spec/ogcapi-common-2/1.0/conf/collections",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/tileset",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/tilesets-list",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/geodata-tilesets",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/mvt",
+    "http://www.opengis.net/spec/tms/2.0/conf/tilematrixset",
+    "http://www.opengis.net/spec/tms/2.0/conf/json-tilematrixset",
+    "http://www.opengis.net/spec/ogcapi-features-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-features-1/1.0/conf/geojson",
+];
+
+
+#[derive(Deserialize)]
+pub struct TilesetPath {
+    table_name: String,
+    tms: String,
+}
+
+
+#[derive(Deserialize)]
+pub struct OgcTilePath {
+    table_name: String,
+    tms: String,
+    tile_matrix: String,
+    tile_row: u32,
+    tile_col: u32,
+}
+
+
+/// Request minta JSON? (?f=json, atau Accept JSON tanpa text/html seperti dari browser)
+pub fn wants_json(req: &HttpRequest) -> bool {
+    let query = req.query_string();
+    if query.split('&').any(|p| p == "f=json") {
+        return true;
+    }
+    if query.split('&').any(|p| p == "f=html") {
+        return false;
+    }
+
+    req.headers()
+        .get("Accept")
+        .and_then(|v| v.to_str().ok())
+        .map(|accept| accept.contains("application/json") && !accept.contains("text/html"))
+        .unwrap_or(false)
+}
+
+
+fn link(href: String, rel: &str, media_type: &str, title: &str) -> Value {
+    json!({ "href": href, "rel": rel, "type": media_type, "title": title })
+}
+
+
+fn ogc_json(body: Value) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .json(body)
+}
+
+
+// Bounding box layer dalam CRS84 untuk extent collection
+pub(crate) fn layer_bbox_crs84(layer: &Layer) -> [f64; 4] {
+    layer
+        .tms_bounds
+        .get(tms::WORLD_CRS84_QUAD)
+        .copied()
+        .unwrap_or_else(|| utils::bbox_3857_to_4326(&layer.bbox))
+}
+
+
+// Nilai dataType layer MVT sesuai OGC API - Tiles: points / lines / polygons / geometry
+fn layer_data_type(geom_type: &str) -> &'static str {
+    let geom_type = geom_type.to_uppercase();
+    if geom_type.contains("POINT") {
+        "points"
+    } else if geom_type.contains("LINE") {
+        "lines"
+    } else if geom_type.contains("POLYGON") {
+        "polygons"
+    } else {
+        "geometry"
+    }
+}
+
+
+pub async fn landing_page(req: HttpRequest) -> HttpResponse {
+    let base = base_url(&req);
+    ogc_json(json!({
+        "title": "Vector Tiles Service",
+        "description": "Mapbox Vector Tiles served from PostGIS",
+        "links": [
+            link(format!("{}/?f=json", base), "self", "application/json", "This document"),
+            link(format!("{}/conformance", base), "http://www.opengis.net/def/rel/ogc/1.0/conformance", "application/json", "Conformance classes"),
+            link(format!("{}/collections", base), "http://www.opengis.net/def/rel/ogc/1.0/data", "application/json", "Collections"),
+            link(format!("{}/tileMatrixSets", base), "http://www.opengis.net/def/rel/ogc/1.0/tiling-schemes", "application/json", "Tile matrix sets"),
+        ],
+    }))
+}
+
+
+pub async fn conformance() -> HttpResponse {
+    ogc_json(json!({ "conformsTo": CONFORMANCE }))
+}
+
+
+fn collection_json(layer: &Layer, base: &str) -> Value {
+    let id = &layer.table_name;
+    json!({
+        "id": id,
+        "title": id,
+        "itemType": "feature",
+        "extent": {
+            "spatial": {
+                "bbox": [layer_bbox_crs84(layer)],
+                "crs": CRS84,
+            }
+        },
+        "crs": [CRS84],
+        "links": [
+            link(format!("{}/collections/{}", base, id), "self", "application/json", id),
+            link(format!("{}/collections/{}/tiles", base, id), "http://www.opengis.net/def/rel/ogc/1.0/tilesets-vector", "application/json", "Vector tilesets"),
+            link(format!("{}/collections/{}/items", base, id), "items", "application/geo+json", "Features"),
+        ],
+    })
+}
+
+
+pub async fn collections(db_pool: web::Data<PgPool>, req: HttpRequest) -> HttpResponse {
+    let base = base_url(&req);
+    let layers = all_layers(db_pool.get_ref(), &req).await;
+
+    ogc_json(json!({
+        "links": [
+            link(format!("{}/collections", base), "self", "application/json", "Collections"),
+        ],
+        "collections": layers.iter().map(|l| collection_json(l, &base)).collect::<Vec<_>>(),
+    }))
+}
+
+
+async fn find_layer(db_pool: &PgPool, req: &HttpRequest, table_name: &str) -> Result<Layer, HttpResponse> {
+    ensure_layers_loaded(db_pool, req).await;
+    match get_layer_detail(table_name.to_string()).await {
+        Some(layer) => Ok(layer),
+        None => {
+            let message = layer_error(table_name)
+                .await
+                .unwrap_or_else(|| format!("Collection not found: {}", table_name));
+            Err(json_error(StatusCode::NOT_FOUND, message))
+        }
+    }
+}
+
+
+fn find_tms(id: &str) -> Result<&'static TileMatrixSet, HttpResponse> {
+    tms::get_tile_matrix_set(id)
+        .ok_or_else(|| json_error(StatusCode::NOT_FOUND, format!("Tile matrix set not found: {}", id)))
+}
+
+
+pub async fn collection(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> HttpResponse {
+    match find_layer(db_pool.get_ref(), &req, &path.into_inner()).await {
+        Ok(layer) => ogc_json(collection_json(&layer, &base_url(&req))),
+        Err(resp) => resp,
+    }
+}
+
+
+fn tileset_summary(layer: &Layer, tms: &TileMatrixSet, base: &str) -> Value {
+    let tileset_url = format!("{}/collections/{}/tiles/{}", base, layer.table_name, tms.id);
+    json!({
+        "title": format!("{} ({})", layer.table_name, tms.id),
+        "dataType": "vector",
+        "crs": tms.crs_uri(),
+        "tileMatrixSetURI": tms.uri,
+        "links": [
+            link(tileset_url, "self", "application/json", "Tileset metadata"),
+            link(format!("{}/tileMatrixSets/{}", base, tms.id), "http://www.opengis.net/def/rel/ogc/1.0/tiling-scheme", "application/json", &tms.id),
+        ],
+    })
+}
+
+
+pub async fn collection_tilesets(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let layer = match find_layer(db_pool.get_ref(), &req, &path.into_inner()).await {
+        Ok(layer) => layer,
+        Err(resp) => return resp,
+    };
+    let base = base_url(&req);
+
+    ogc_json(json!({
+        "links": [
+            link(format!("{}/collections/{}/tiles", base, layer.table_name), "self", "application/json", "Tilesets"),
+        ],
+        "tilesets": tms::tile_matrix_sets()
+            .map(|tms| tileset_summary(&layer, tms, &base))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+
+pub(crate) struct TileMatrixLimits {
+    pub(crate) tile_matrix: String,
+    pub(crate) min_row: u64,
+    pub(crate) max_row: u64,
+    pub(crate) min_col: u64,
+    pub(crate) max_col: u64,
+}
+
+
+/// Batas tile layer per zoom (minzoom..=maxzoom); tanpa bounds di CRS ini seluruh matrix dianggap valid
+pub(crate) fn tile_matrix_limits(layer: &Layer, tms: &TileMatrixSet) -> Vec<TileMatrixLimits> {
+    (layer.minzoom..=layer.maxzoom.min(tms.max_zoom()))
+        .filter_map(|z| {
+            let matrix = tms.matrix(z)?;
+            let (min_col, max_col, min_row, max_row) = match layer.tms_bounds.get(&tms.id) {
+                Some(bounds) => tms.tile_range(z, bounds)?,
+                None => (0, matrix.matrix_width - 1, 0, matrix.matrix_height - 1),
+            };
+            Some(TileMatrixLimits { tile_matrix: matrix.id.clone(), min_row, max_row, min_col, max_col })
+        })
+        .collect()
+}
+
+
+pub async fn collection_tileset(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<TilesetPath>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let params = path.into_inner();
+    let layer = match find_layer(db_pool.get_ref(), &req, &params.table_name).await {
+        Ok(layer) => layer,
+        Err(resp) => return resp,
+    };
+    let tms = match find_tms(&params.tms) {
+        Ok(tms) => tms,
+        Err(resp) => return resp,
+    };
+    let base = base_url(&req);
+
+    let minzoom = layer.minzoom;
+    let maxzoom = layer.maxzoom.min(tms.max_zoom());
+
+    let limits: Vec<Value> = tile_matrix_limits(&layer, tms)
+        .into_iter()
+        .map(|l| json!({
+            "tileMatrix": l.tile_matrix,
+            "minTileRow": l.min_row,
+            "maxTileRow": l.max_row,
+            "minTileCol": l.min_col,
+            "maxTileCol": l.max_col,
+        }))
+        .collect();
+
+    let bbox = layer_bbox_crs84(&layer);
+    let bounding_box = json!({
+        "lowerLeft": [bbox[0], bbox[1]],
+        "upperRight": [bbox[2], bbox[3]],
+        "crs": CRS84,
+    });
+
+    let mut tileset = tileset_summary(&layer, tms, &base);
+    tileset["tileMatrixSetLimits"] = json!(limits);
+    tileset["boundingBox"] = bounding_box.clone();
+    tileset["layers"] = json!([{
+        "id": layer.table_name,
+        "dataType": layer_data_type(&layer.geom_type),
+        "geometryDimension": match layer_data_type(&layer.geom_type) {
+            "points" => json!(0),
+            "lines" => json!(1),
+            "polygons" => json!(2),
+            _ => Value::Null,
+        },
+        "minTileMatrix": minzoom.to_string(),
+        "maxTileMatrix": maxzoom.to_string(),
+        "boundingBox": bounding_box,
+    }]);
+    if let Some(links) = tileset["links"].as_array_mut() {
+        links.push(json!({
+            "href": format!(
+                "{}/collections/{}/tiles/{}/{{tileMatrix}}/{{tileRow}}/{{tileCol}}",
+                base, layer.table_name, tms.id
+            ),
+            "rel": "item",
+            "type": MVT_MEDIA_TYPE,
+            "title": "Vector tiles",
+            "templated": true,
+        }));
+    }
+
+    ogc_json(tileset)
+}
+
+
+pub async fn collection_tile(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<OgcTilePath>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let params = path.into_inner();
+    let tms = match find_tms(&params.tms) {
+        Ok(tms) => tms,
+        Err(resp) => return resp,
+    };
+    let Some(z) = tms.matrix_index(&params.tile_matrix) else {
+        return json_error(
+            StatusCode::BAD_REQUEST,
+            format!("Tile matrix {} does not exist in {}", params.tile_matrix, tms.id),
+        );
+    };
+
+    // Urutan OGC: {tileMatrix}/{tileRow}/{tileCol} = z/y/x
+    serve_tile(db_pool.get_ref(), &req, &tms.id, &params.table_name, z, params.tile_col, params.tile_row).await
+}
+
+
+pub async fn tile_matrix_sets(req: HttpRequest) -> HttpResponse {
+    let base = base_url(&req);
+    let sets: Vec<Value> = tms::tile_matrix_sets()
+        .map(|tms| json!({
+            "id": tms.id,
+            "title": tms.title,
+            "uri": tms.uri,
+            "crs": tms.crs_uri(),
+            "links": [
+                link(format!("{}/tileMatrixSets/{}", base, tms.id), "self", "application/json", &tms.id),
+            ],
+        }))
+        .collect();
+
+    ogc_json(json!({ "tileMatrixSets": sets }))
+}
+
+
+pub async fn tile_matrix_set(path: web::Path<String>) -> HttpResponse {
+    match find_tms(&path.into_inner()) {
+        Ok(tms) => ogc_json(json!(tms)),
+        Err(resp) => resp,
+    }
+}
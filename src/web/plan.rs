@@ -0,0 +1,419 @@
+// Rencana persiapan database (`prepare --dry-run`): apa yang akan dibuat oleh
+// run_migrations dan web::prep untuk setiap layer, tanpa menjalankan DDL apa pun.
+// SQL yang dicetak berasal dari fungsi yang sama dengan yang dipakai saat persiapan
+// (web::prep, web::backfill, web::validity), jadi sama persis dengan yang dijalankan.
+//
+// Semua informasi diambil dari katalog (geometry_columns, pg_class, pg_stats, pg_trigger);
+// jumlah baris adalah perkiraan planner (reltuples), bukan count(*).
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+use super::backfill::{progress_table_name, progress_table_sql, Backfill, BackfillConfig};
+use super::db::pending_migrations;
+use super::feature_id::primary_key;
+use super::prep::{self, LayerSource, PrepMode, GEOM_3857};
+use super::privileges::{self, Capabilities};
+use super::utils::create_schema_sql;
+use super::validity;
+
+
+#[derive(Serialize)]
+pub struct Action {
+    /// schema / table / column / index / function / trigger / backfill / cleanup / validate / repair
+    pub kind: &'static str,
+    pub sql: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batches: Option<i64>,
+}
+
+impl Action {
+    fn new(kind: &'static str, sql: String) -> Self {
+        Self { kind, sql, rows: None, batches: None }
+    }
+}
+
+
+#[derive(Serialize)]
+pub struct LayerPlan {
+    pub table: String,
+    pub geom_column: String,
+    pub geom_type: String,
+    pub srid: i32,
+    pub mode: PrepMode,
+    pub estimated_rows: i64,
+    pub table_size_bytes: i64,
+    pub table_size: String,
+    /// Alasan layer tidak disiapkan (view, tanpa primary key, hak akses role, ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+    pub actions: Vec<Action>,
+}
+
+
+#[derive(Serialize)]
+pub struct Plan {
+    pub migrations: Vec<Action>,
+    pub layers: Vec<LayerPlan>,
+}
+
+
+struct TableStats {
+    rows: i64,
+    size_bytes: i64,
+    size: String,
+}
+
+async fn table_stats(db_pool: &PgPool, qualified: &str) -> Result<TableStats, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT GREATEST(c.reltuples, 0)::bigint AS rows,
+               pg_total_relation_size(c.oid) AS size_bytes,
+               pg_size_pretty(pg_total_relation_size(c.oid)) AS size
+        FROM pg_class c
+        WHERE c.oid = to_regclass($1)
+        "#
+    )
+    .bind(qualified)
+    .fetch_optional(db_pool)
+    .await?;
+    match row {
+        Some(row) => Ok(TableStats {
+            rows: row.try_get("rows")?,
+            size_bytes: row.try_get("size_bytes")?,
+            size: row.try_get("size")?,
+        }),
+        None => Ok(TableStats { rows: 0, size_bytes: 0, size: "-".to_string() }),
+    }
+}
+
+
+async fn has_prepared_column(db_pool: &PgPool, source: &LayerSource) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query(
+        "SELECT 1 FROM information_schema.columns WHERE table_schema = $1 AND table_name = $2 AND column_name = $3"
+    )
+    .bind(&source.schema)
+    .bind(&source.table)
+    .bind(&source.prepared_column)
+    .fetch_optional(db_pool)
+    .await?
+    .is_some())
+}
+
+
+// Perkiraan baris kolom hasil persiapan yang masih NULL dari pg_stats; tanpa statistik dianggap semua baris
+async fn estimated_nulls(db_pool: &PgPool, source: &LayerSource, rows: i64) -> Result<i64, sqlx::Error> {
+    let null_frac: Option<f32> = sqlx::query(
+        "SELECT null_frac FROM pg_stats WHERE schemaname = $1 AND tablename = $2 AND attname = $3"
+    )
+    .bind(&source.schema)
+    .bind(&source.table)
+    .bind(&source.prepared_column)
+    .fetch_optional(db_pool)
+    .await?
+    .map(|row| row.try_get("null_frac"))
+    .transpose()?;
+    Ok(match null_frac {
+        Some(frac) => (rows as f64 * frac as f64).round() as i64,
+        None => rows,
+    })
+}
+
+
+// Statement yang dijalankan per batch (lihat web::backfill)
+async fn batched_action(
+    db_pool: &PgPool,
+    kind: &'static str,
+    backfill: &Backfill,
+    rows: i64,
+    config: &BackfillConfig,
+) -> Result<Action, sqlx::Error> {
+    Ok(Action {
+        kind,
+        sql: backfill.batch_sql(db_pool).await?,
+        rows: Some(rows),
+        batches: Some(batch_count(rows, config.batch_size)),
+    })
+}
+
+
+// Jumlah batch dibulatkan ke atas; estimasi negatif (tabel belum di-ANALYZE) dianggap 0 baris
+fn batch_count(rows: i64, batch_size: i64) -> i64 {
+    (rows.max(0) + batch_size - 1) / batch_size
+}
+
+
+// Trigger sinkronisasi: dipasang jika diaktifkan dan belum ada, dibuang jika dimatikan
+async fn trigger_actions(
+    db_pool: &PgPool,
+    source: &LayerSource,
+    definition: impl FnOnce() -> prep::SyncDefinition,
+) -> Result<Vec<Action>, sqlx::Error> {
+    let installed = prep::has_sync_trigger(db_pool, source).await?;
+    let enabled = prep::layer_sync_trigger(&source.id);
+    let mut actions = Vec::new();
+
+    if enabled && !installed {
+        let (body, triggers) = definition();
+        let postgis = prep::postgis_schema(db_pool).await?;
+        for statement in prep::sync_function_sql(source, &body, &postgis) {
+            actions.push(Action::new("function", statement));
+        }
+        for statement in prep::sync_trigger_sql(source, &triggers) {
+            actions.push(Action::new("trigger", statement));
+        }
+    } else if !enabled && installed {
+        for statement in prep::drop_sync_trigger_sql(source) {
+            let kind = if statement.starts_with("DROP FUNCTION") { "function" } else { "trigger" };
+            actions.push(Action::new(kind, statement));
+        }
+    }
+    Ok(actions)
+}
+
+
+async fn layer_plan(
+    db_pool: &PgPool,
+    capabilities: &Capabilities,
+    source: &LayerSource,
+    config: &BackfillConfig,
+) -> Result<LayerPlan, sqlx::Error> {
+    let mode = prep::layer_prep_mode(&source.id);
+    let stats = table_stats(db_pool, &source.qualified()).await?;
+    let mut plan = LayerPlan {
+        table: format!("{}.{}", source.schema, source.id),
+        geom_column: source.geom_column.clone(),
+        geom_type: source.geom_type.clone(),
+        srid: source.srid,
+        mode,
+        estimated_rows: stats.rows,
+        table_size_bytes: stats.size_bytes,
+        table_size: stats.size,
+        skipped: None,
+        actions: Vec::new(),
+    };
+
+    // Sama dengan aturan check_and_create_geom_index / prep::prepare
+    if source.geom_column == GEOM_3857 {
+        plan.skipped = Some("already stored as geom_3857".to_string());
+        return Ok(plan);
+    }
+    if !matches!(source.relkind.as_deref(), Some("r") | Some("m")) {
+        plan.skipped = Some("not a table or materialized view".to_string());
+        return Ok(plan);
+    }
+    if let Some(problem) = source.problem() {
+        plan.skipped = Some(problem);
+        return Ok(plan);
+    }
+    if let Some(reason) = privileges::prepare_denied(db_pool, capabilities, source, mode).await? {
+        plan.skipped = Some(reason);
+        return Ok(plan);
+    }
+    if let Some(reason) = validity::make_valid_blocked(db_pool, source, mode).await? {
+        plan.skipped = Some(reason);
+        return Ok(plan);
+    }
+
+    let actions = &mut plan.actions;
+    match mode {
+        PrepMode::ReadOnly => {}
+        PrepMode::Expression => {
+            if !prep::relation_exists(db_pool, &prep::expression_index(source)).await? {
+                actions.push(Action::new("index", prep::expression_index_sql(source)));
+            }
+        }
+        PrepMode::Column => {
+            let has_column = has_prepared_column(db_pool, source).await?;
+            if !has_column {
+                actions.push(Action::new("column", prep::add_column_sql(source)));
+            }
+            actions.extend(trigger_actions(db_pool, source, || prep::column_sync(source)).await?);
+            let pending = if has_column { estimated_nulls(db_pool, source, stats.rows).await? } else { stats.rows };
+            // Tabel yang belum pernah di-ANALYZE: reltuples belum diketahui, tetap tampilkan backfill
+            if pending > 0 || !has_column {
+                actions.push(batched_action(db_pool, "backfill", &prep::column_backfill(source), pending, config).await?);
+            }
+            if !prep::relation_exists(db_pool, &prep::column_index(source)).await? {
+                actions.push(Action::new("index", prep::column_index_sql(source)));
+            }
+        }
+        PrepMode::Shadow => {
+            let pk = primary_key(db_pool, &source.schema, &source.table).await?;
+            if pk.is_empty() {
+                plan.skipped = Some("no primary key for a shadow table".to_string());
+                return Ok(plan);
+            }
+            let shadow = source.shadow_table();
+            let has_shadow = prep::relation_exists(db_pool, &shadow).await?;
+            let shadow_rows = if has_shadow {
+                table_stats(db_pool, &shadow).await?.rows
+            } else {
+                actions.extend(prep::shadow_create_sql(source, &pk).map(|sql| Action::new("table", sql)));
+                0
+            };
+            actions.extend(trigger_actions(db_pool, source, || prep::shadow_sync(source, &pk)).await?);
+            let pending = (stats.rows - shadow_rows).max(0);
+            if pending > 0 || !has_shadow {
+                actions.push(batched_action(db_pool, "backfill", &prep::shadow_backfill(source, &pk), pending, config).await?);
+            }
+            actions.push(Action::new("cleanup", prep::shadow_cleanup_sql(source, &pk)));
+            if !prep::relation_exists(db_pool, &prep::shadow_index(source)).await? {
+                actions.push(Action::new("index", prep::shadow_index_sql(source)));
+            }
+        }
+    }
+
+    // Pemeriksaan validitas setelah persiapan; make_valid menulis ulang baris invalid yang tersimpan
+    if mode != PrepMode::ReadOnly && validity::validate_enabled() {
+        let pk = primary_key(db_pool, &source.schema, &source.table).await?;
+        actions.push(Action::new("validate", validity::clear_report_sql()));
+        actions.push(batched_action(db_pool, "validate", &validity::detect_backfill(source, &pk), stats.rows, config).await?);
+        if source.make_valid()
+            && let Some(repair) = validity::repair_backfill(source, mode, &pk)
+        {
+            actions.push(batched_action(db_pool, "repair", &repair, stats.rows, config).await?);
+        }
+    }
+    Ok(plan)
+}
+
+
+/// Susun rencana lengkap. Hanya membaca katalog; aman dijalankan di koneksi read-only.
+pub async fn build_plan(db_pool: &PgPool, config: &BackfillConfig) -> Result<Plan, sqlx::Error> {
+    let mut migrations: Vec<Action> = pending_migrations(db_pool)
+        .await?
+        .into_iter()
+        .map(|(kind, sql)| Action::new(kind, sql))
+        .collect();
+
+    // Koneksi dry run sendiri read-only; yang dicek adalah hak akses role-nya
+    let capabilities = Capabilities { read_only_database: false, ..privileges::detect(db_pool).await? };
+    let mut layers = Vec::new();
+    for source in prep::layer_sources(db_pool).await? {
+        layers.push(layer_plan(db_pool, &capabilities, &source, config).await?);
+    }
+
+    // Schema PREP_SCHEMA & tabel progress backfill dibuat saat pertama dibutuhkan
+    let schema = prep::prep_schema();
+    let needs_schema = layers
+        .iter()
+        .flat_map(|l| &l.actions)
+        .any(|a| matches!(a.kind, "table" | "function" | "backfill" | "validate"));
+    let schema_exists: bool = sqlx::query("SELECT to_regnamespace($1) IS NOT NULL AS found")
+        .bind(&schema)
+        .fetch_one(db_pool)
+        .await?
+        .try_get("found")?;
+    if needs_schema && !schema_exists {
+        migrations.push(Action::new("schema", create_schema_sql(&schema)));
+    }
+    let needs_progress = layers
+        .iter()
+        .flat_map(|l| &l.actions)
+        .any(|a| matches!(a.kind, "backfill" | "validate" | "repair"));
+    if needs_progress && !prep::relation_exists(db_pool, &progress_table_name(&schema)).await? {
+        migrations.push(Action::new("table", progress_table_sql(&schema)));
+    }
+
+    let needs_report = layers.iter().flat_map(|l| &l.actions).any(|a| a.kind == "validate");
+    if needs_report && !prep::relation_exists(db_pool, &validity::report_table_name(&schema)).await? {
+        migrations.push(Action::new("table", validity::report_table_sql(&schema)));
+    }
+
+    Ok(Plan { migrations, layers })
+}
+
+
+// SQL multi-baris dari raw string: buang baris kosong di awal/akhir dan indentasi bersama
+fn dedent(sql: &str) -> Vec<&str> {
+    let lines: Vec<&str> = sql.trim_start_matches('\n').trim_end().lines().collect();
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines.iter().map(|l| l.get(indent..).unwrap_or(l.trim_start())).collect()
+}
+
+
+fn print_action(action: &Action) {
+    let mut lines = dedent(&action.sql).into_iter();
+    let first = lines.next().unwrap_or_default();
+    match (action.rows, action.batches) {
+        (Some(rows), Some(batches)) => {
+            println!("   + {:<9} ~{} rows in {} batches", action.kind, rows, batches);
+            println!("     {:<9} {}", "", first);
+        }
+        _ => println!("   + {:<9} {}", action.kind, first),
+    }
+    for line in lines {
+        println!("     {:<9} {}", "", line);
+    }
+}
+
+
+/// Cetak rencana sebagai teks (default) atau JSON (`--json`)
+pub fn print_plan(plan: &Plan, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(plan).unwrap_or_default());
+        return;
+    }
+
+    println!("\n📋 Preparation plan (dry run, nothing is executed)\n");
+    println!("Migrations:");
+    if plan.migrations.is_empty() {
+        println!("   up to date");
+    }
+    for action in &plan.migrations {
+        print_action(action);
+    }
+
+    for layer in &plan.layers {
+        println!(
+            "\n{} ({} {}, SRID {})  mode {}  ~{} rows, {}",
+            layer.table, layer.geom_column, layer.geom_type, layer.srid,
+            layer.mode, layer.estimated_rows, layer.table_size
+        );
+        if let Some(reason) = &layer.skipped {
+            println!("   skipped: {}", reason);
+        } else if layer.actions.is_empty() {
+            println!("   nothing to do");
+        }
+        for action in &layer.actions {
+            print_action(action);
+        }
+    }
+    println!();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedent_strips_common_indent_and_blank_edges() {
+        let sql = "\n        CREATE TABLE t (\n            id int\n        )\n    ";
+        assert_eq!(dedent(sql), ["CREATE TABLE t (", "    id int", ")"]);
+    }
+
+    #[test]
+    fn dedent_ignores_blank_lines_for_indent() {
+        let sql = "\n    SELECT 1;\n\n      SELECT 2;\n";
+        assert_eq!(dedent(sql), ["SELECT 1;", "", "  SELECT 2;"]);
+        assert_eq!(dedent("SELECT 1"), ["SELECT 1"]);
+        assert!(dedent("").is_empty());
+    }
+
+    #[test]
+    fn batch_count_rounds_up() {
+        assert_eq!(batch_count(0, 10_000), 0);
+        assert_eq!(batch_count(1, 10_000), 1);
+        assert_eq!(batch_count(10_000, 10_000), 1);
+        assert_eq!(batch_count(10_001, 10_000), 2);
+        assert_eq!(batch_count(25_000, 1), 25_000);
+        assert_eq!(batch_count(-1, 1), 0);
+    }
+}
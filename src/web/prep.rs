@@ -0,0 +1,1246 @@
+// Persiapan geometri EPSG:3857 per layer, dipilih lewat `prep` di layers.json
+// (default dari env GEOM_PREP_MODE, atau "shadow"):
+//
+//   column      kolom geom_3857 di tabel sumber (perilaku lama: ALTER TABLE + UPDATE + GiST index)
+//   shadow      tabel bayangan {PREP_SCHEMA}."{schema}__{table}" berisi primary key + geom_3857,
+//               tabel sumber tidak diubah sama sekali (default: startup tidak pernah ALTER tabel)
+//   expression  transform on the fly, dipercepat GiST expression index di tabel sumber
+//   none        transform on the fly tanpa menulis apa pun ke tabel layer
+//
+// Semua query (tile, features, extent) membaca layer lewat `Layer.relation`: relasi SQL
+// dengan kolom tabel sumber ditambah geom_3857. Jika data hasil persiapan belum ada
+// (mis. kolom atau tabel bayangan belum dibuat), layer otomatis dibaca on the fly.
+//
+// Tabel dengan beberapa kolom geometri menghasilkan satu layer per kolom: kolom pertama tetap
+// layer `tabel`, kolom tambahan menjadi `tabel.kolom` dengan kolom `{kolom}_3857`, tabel
+// bayangan `{schema}__{table}__{kolom}`, index dan trigger sendiri (lihat table_sources).
+use std::fmt;
+use std::str::FromStr;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use super::backfill::{Backfill, BackfillConfig};
+use super::config::layer_config;
+use super::feature_id::primary_key;
+use super::features::Column;
+use super::utils::{create_schema_sql, pg_identifier, quote_ident, quote_literal};
+use super::privileges::can_select;
+use super::web_handler::Layer;
+
+// Nama kolom geometri 3857 di setiap relasi layer
+pub const GEOM_3857: &str = "geom_3857";
+
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrepMode {
+    Column,
+    #[default]
+    Shadow,
+    Expression,
+    #[serde(rename = "none")]
+    ReadOnly,
+}
+
+impl PrepMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrepMode::Column => "column",
+            PrepMode::Shadow => "shadow",
+            PrepMode::Expression => "expression",
+            PrepMode::ReadOnly => "none",
+        }
+    }
+
+    /// Apakah geom_3857 tersimpan (bukan hasil transform saat query)
+    pub fn is_stored(&self) -> bool {
+        matches!(self, PrepMode::Column | PrepMode::Shadow)
+    }
+}
+
+impl fmt::Display for PrepMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for PrepMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "column" => Ok(PrepMode::Column),
+            "shadow" => Ok(PrepMode::Shadow),
+            "expression" => Ok(PrepMode::Expression),
+            "none" => Ok(PrepMode::ReadOnly),
+            other => Err(format!("Unknown geometry prep mode: {}", other)),
+        }
+    }
+}
+
+
+/// Mode persiapan layer: layers.json, lalu GEOM_PREP_MODE, lalu "shadow"
+pub fn layer_prep_mode(layer_id: &str) -> PrepMode {
+    layer_config(layer_id).prep.unwrap_or_else(|| {
+        match std::env::var("GEOM_PREP_MODE") {
+            Ok(value) => value.parse().unwrap_or_else(|e| {
+                warn!("{}, using shadow", e);
+                PrepMode::Shadow
+            }),
+            Err(_) => PrepMode::Shadow,
+        }
+    })
+}
+
+
+pub fn prep_schema() -> String {
+    std::env::var("PREP_SCHEMA").unwrap_or_else(|_| "tile_prep".to_string())
+}
+
+
+/// Daftar kolom dengan alias, mis. `t."a", t."b"`
+fn prefixed(columns: &[String], alias: &str) -> String {
+    let list: Vec<String> = columns.iter().map(|c| format!("{}.{}", alias, quote_ident(c))).collect();
+    list.join(", ")
+}
+
+/// Kondisi kesamaan key, mis. `s."a" = t."a" AND s."b" = t."b"`
+pub(crate) fn key_match(columns: &[String], left: &str, right: &str) -> String {
+    let list: Vec<String> = columns
+        .iter()
+        .map(|c| format!("{left}.{col} = {right}.{col}", col = quote_ident(c)))
+        .collect();
+    list.join(" AND ")
+}
+
+
+/// Key baris (alias `t`) sebagai teks untuk laporan; tanpa primary key pakai ctid
+pub(crate) fn row_key(pk: &[String]) -> String {
+    if pk.is_empty() {
+        "t.ctid::text".to_string()
+    } else {
+        let cols: Vec<String> = pk.iter().map(|c| format!("t.{}::text", quote_ident(c))).collect();
+        format!("concat_ws(',', {})", cols.join(", "))
+    }
+}
+
+
+/// Ekspresi transform ke 3857; harus sama persis dengan expression index supaya index terpakai
+fn transform_sql(geom: &str) -> String {
+    format!("ST_Transform(ST_Force2D({}), 3857)", geom)
+}
+
+
+/// Geometri invalid diganti hasil ST_MakeValid; ST_CollectionExtract membuang potongan
+/// berdimensi lebih rendah (mis. garis sisa dari polygon bowtie)
+fn make_valid_sql(geom: &str, tile_type: &str) -> String {
+    let repaired = match tile_type {
+        "POLYGON" | "MULTIPOLYGON" => format!("ST_CollectionExtract(ST_MakeValid({}), 3)", geom),
+        "LINESTRING" | "MULTILINESTRING" => format!("ST_CollectionExtract(ST_MakeValid({}), 2)", geom),
+        _ => format!("ST_MakeValid({})", geom),
+    };
+    format!("CASE WHEN ST_IsValid({g}) THEN {g} ELSE {r} END", g = geom, r = repaired)
+}
+
+
+// Tipe lengkung dilinearisasi dengan ST_CurveToLine sebelum ditransform (tipe asal, tipe hasil)
+const CURVE_TYPES: [(&str, &str); 5] = [
+    ("CIRCULARSTRING", "LINESTRING"),
+    ("COMPOUNDCURVE", "LINESTRING"),
+    ("CURVEPOLYGON", "POLYGON"),
+    ("MULTICURVE", "MULTILINESTRING"),
+    ("MULTISURFACE", "MULTIPOLYGON"),
+];
+
+// Tipe permukaan 3D yang tidak punya padanan di MVT
+const UNSUPPORTED_TYPES: [&str; 3] = ["POLYHEDRALSURFACE", "TIN", "TRIANGLE"];
+
+const BASE_TYPES: [&str; 8] = [
+    "POINT", "LINESTRING", "POLYGON", "MULTIPOINT", "MULTILINESTRING", "MULTIPOLYGON",
+    "GEOMETRYCOLLECTION", "GEOMETRY",
+];
+
+
+/// SRID untuk kolom dengan SRID 0 (tidak diketahui): `assume_srid` di layers.json, lalu env ASSUME_SRID
+pub fn layer_assumed_srid(layer_id: &str) -> Option<i32> {
+    layer_config(layer_id).assume_srid.or_else(|| {
+        std::env::var("ASSUME_SRID")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|srid: &i32| *srid > 0)
+    })
+}
+
+
+/// Perbaiki geometri invalid dengan ST_MakeValid: layers.json `make_valid`, lalu GEOM_MAKE_VALID
+pub fn layer_make_valid(layer_id: &str) -> bool {
+    layer_config(layer_id).make_valid.unwrap_or_else(|| {
+        std::env::var("GEOM_MAKE_VALID")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false)
+    })
+}
+
+
+// Semua kolom geometri (geometry_columns) dan geografi (geography_columns);
+// tabel di PREP_SCHEMA (tabel bayangan) tidak ikut
+fn layer_sources_sql() -> String {
+    format!(
+        r#"
+        SELECT
+            gc.f_table_schema,
+            gc.f_table_name,
+            gc.f_geometry_column,
+            upper(gc.type) AS type,
+            gc.srid,
+            gc.geography,
+            (
+                SELECT c.relkind::text
+                FROM pg_class c
+                JOIN pg_namespace n ON n.oid = c.relnamespace
+                WHERE n.nspname = gc.f_table_schema AND c.relname = gc.f_table_name
+            ) AS relkind,
+            has_table_privilege(format('%I.%I', gc.f_table_schema, gc.f_table_name), 'SELECT') AS selectable,
+            (
+                SELECT a.attnum
+                FROM pg_attribute a
+                WHERE a.attrelid = to_regclass(format('%I.%I', gc.f_table_schema, gc.f_table_name))
+                AND a.attname = gc.f_geometry_column
+            ) AS attnum
+        FROM (
+            SELECT f_table_schema::text, f_table_name::text, f_geometry_column::text,
+                type::text, srid, false AS geography
+            FROM public.geometry_columns
+            UNION ALL
+            SELECT f_table_schema::text, f_table_name::text, f_geography_column::text,
+                type::text, srid, true AS geography
+            FROM public.geography_columns
+        ) gc
+        WHERE gc.f_table_schema <> {}
+        ORDER BY gc.f_table_schema, gc.f_table_name, attnum, gc.f_geometry_column
+        "#,
+        quote_literal(&prep_schema()),
+    )
+}
+
+
+/// Satu layer = satu (tabel, kolom geometri). Kolom geometri pertama tabel memakai nama tabel
+/// sebagai id layer dan kolom geom_3857; kolom geometri tambahan menjadi layer `tabel.kolom`
+/// dengan kolom hasil persiapan `{kolom}_3857` masing-masing (lihat table_sources).
+#[derive(Clone)]
+pub struct LayerSource {
+    pub id: String,
+    pub schema: String,
+    pub table: String,
+    pub geom_column: String,
+    pub geom_type: String,
+    pub srid: i32,
+    pub relkind: Option<String>,
+    /// Kolom bertipe geography (di-cast ke geometry sebelum ditransform)
+    pub geography: bool,
+    /// Kolom geometri 3857 di tabel sumber (mode column)
+    pub prepared_column: String,
+    /// Role koneksi punya hak SELECT di tabel sumber
+    pub selectable: bool,
+}
+
+impl LayerSource {
+    pub fn from_row(row: &sqlx::postgres::PgRow) -> Result<Self, sqlx::Error> {
+        let table: String = row.try_get("f_table_name")?;
+        Ok(Self {
+            id: table.clone(),
+            schema: row.try_get("f_table_schema")?,
+            table,
+            geom_column: row.try_get("f_geometry_column")?,
+            geom_type: row.try_get("type")?,
+            srid: row.try_get("srid")?,
+            relkind: row.try_get("relkind")?,
+            geography: row.try_get("geography")?,
+            prepared_column: GEOM_3857.to_string(),
+            selectable: row.try_get("selectable")?,
+        })
+    }
+
+    /// Relasi punya kolom sistem ctid (tabel, materialized view, tabel partisi); view dan
+    /// foreign table tidak
+    pub fn has_ctid(&self) -> bool {
+        matches!(self.relkind.as_deref(), Some("r") | Some("m") | Some("p"))
+    }
+
+    /// Tipe tanpa akhiran dimensi Z/M, mis. POLYGONM → POLYGON, CURVEPOLYGONZ → CURVEPOLYGON
+    fn base_type(&self) -> &str {
+        let known = |t: &str| {
+            BASE_TYPES.contains(&t) || UNSUPPORTED_TYPES.contains(&t) || CURVE_TYPES.iter().any(|(c, _)| *c == t)
+        };
+        ["ZM", "Z", "M"]
+            .iter()
+            .filter_map(|suffix| self.geom_type.strip_suffix(suffix))
+            .find(|base| known(base))
+            .unwrap_or(&self.geom_type)
+    }
+
+    fn is_curve(&self) -> bool {
+        CURVE_TYPES.iter().any(|(curve, _)| *curve == self.base_type())
+    }
+
+    /// Tipe geometri di tile / geom_3857: 2D, tipe lengkung sudah dilinearisasi
+    pub fn tile_type(&self) -> String {
+        let base = self.base_type();
+        CURVE_TYPES
+            .iter()
+            .find(|(curve, _)| *curve == base)
+            .map(|(_, linear)| *linear)
+            .unwrap_or(base)
+            .to_string()
+    }
+
+    /// SRID data sumber; SRID 0 memakai SRID yang diasumsikan (None jika tidak di-set)
+    pub fn source_srid(&self) -> Option<i32> {
+        if self.srid > 0 {
+            Some(self.srid)
+        } else {
+            layer_assumed_srid(&self.id)
+        }
+    }
+
+    /// Alasan layer tidak bisa dipublikasikan atau disiapkan
+    pub fn problem(&self) -> Option<String> {
+        if !self.selectable {
+            return Some(format!("the database role has no SELECT privilege on {}.{}", self.schema, self.table));
+        }
+        if UNSUPPORTED_TYPES.contains(&self.base_type()) {
+            return Some(format!("geometry type {} cannot be rendered as vector tiles", self.geom_type));
+        }
+        if self.source_srid().is_none() {
+            return Some("SRID is 0 (unknown); set \"assume_srid\" in the layer config or ASSUME_SRID".to_string());
+        }
+        None
+    }
+
+    /// Kolom sumber sebagai geometry dengan SRID yang benar. `alias` kosong = tanpa alias tabel.
+    pub(crate) fn source_geom(&self, alias: &str) -> String {
+        let mut geom = if alias.is_empty() {
+            quote_ident(&self.geom_column)
+        } else {
+            format!("{}.{}", alias, quote_ident(&self.geom_column))
+        };
+        if self.geography {
+            geom = format!("{}::geometry", geom);
+        }
+        if self.srid <= 0
+            && let Some(srid) = self.source_srid()
+        {
+            geom = format!("ST_SetSRID({}, {})", geom, srid);
+        }
+        geom
+    }
+
+    /// Kolom sumber yang sudah dilinearisasi; input ST_IsValid / ST_MakeValid
+    pub(crate) fn linear_geom(&self, alias: &str) -> String {
+        let geom = self.source_geom(alias);
+        if self.is_curve() {
+            format!("ST_CurveToLine({})", geom)
+        } else {
+            geom
+        }
+    }
+
+    /// Geometri invalid diperbaiki saat persiapan / query; titik tidak pernah perlu diperbaiki
+    pub fn make_valid(&self) -> bool {
+        !matches!(self.tile_type().as_str(), "POINT" | "MULTIPOINT") && layer_make_valid(&self.id)
+    }
+
+    /// Tipe kolom geom_3857 tersimpan. ST_MakeValid bisa mengubah POLYGON menjadi
+    /// MULTIPOLYGON, jadi layer dengan make_valid memakai tipe generik.
+    pub fn column_type(&self) -> String {
+        if self.make_valid() {
+            "GEOMETRY".to_string()
+        } else {
+            self.tile_type()
+        }
+    }
+
+    /// Ekspresi geom_3857 dari kolom sumber (lihat transform_sql)
+    pub(crate) fn transform(&self, alias: &str) -> String {
+        let geom = self.linear_geom(alias);
+        if self.make_valid() {
+            transform_sql(&make_valid_sql(&geom, &self.tile_type()))
+        } else {
+            transform_sql(&geom)
+        }
+    }
+
+    pub(crate) fn qualified(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.table))
+    }
+
+    /// Layer dari tabel dengan beberapa kolom geometri
+    fn is_split(&self) -> bool {
+        self.id != self.table
+    }
+
+    /// Nama dasar objek di PREP_SCHEMA: `{schema}__{table}`, ditambah `__{kolom}` untuk tabel
+    /// dengan beberapa geometri (di-hash jika melebihi NAMEDATALEN, lihat pg_identifier)
+    pub fn prep_name(&self) -> String {
+        if self.is_split() {
+            pg_identifier(&format!("{}__{}__{}", self.schema, self.table, self.geom_column))
+        } else {
+            pg_identifier(&format!("{}__{}", self.schema, self.table))
+        }
+    }
+
+    /// Tabel bayangan (sudah di-quote) untuk mode shadow
+    pub fn shadow_table(&self) -> String {
+        format!("{}.{}", quote_ident(&prep_schema()), quote_ident(&self.prep_name()))
+    }
+}
+
+
+/// Kolom hasil persiapan untuk kolom geometri tambahan: `{kolom}_3857`, atau
+/// `{tabel}_geom_3857` untuk kolom `geom` supaya tidak bentrok dengan geom_3857 kolom utama
+fn split_prepared_column(table: &str, geom_column: &str) -> String {
+    let name = format!("{}_3857", geom_column);
+    if name == GEOM_3857 {
+        pg_identifier(&format!("{}_{}", table, name))
+    } else {
+        pg_identifier(&name)
+    }
+}
+
+
+// Kolom geometri satu tabel (urut attnum) → layer. Kolom utama adalah kolom geometri pertama
+// di tabel: id layer tetap nama tabel dan kolom hasil persiapannya geom_3857, sama seperti
+// sebelum kolom lain ditambahkan, jadi URL, cache dan konfigurasi tidak berubah. Kolom
+// tambahan menjadi layer `tabel.kolom`. Salinan hasil persiapan tidak ikut: geom_3857 yang
+// dibuat setelah kolom geometri lain (salinan kolom utama) dan `{kolom}_3857` kolom tambahan.
+// Tabel lama yang hanya punya geom_3857 tetap dipublikasikan dari kolom itu, juga setelah
+// kolom geometri baru ditambahkan.
+fn table_sources(columns: Vec<LayerSource>) -> Vec<LayerSource> {
+    let names: Vec<String> = columns.iter().map(|c| c.geom_column.clone()).collect();
+    let is_copy = |position: usize, source: &LayerSource| {
+        let name = source.geom_column.as_str();
+        (name == GEOM_3857 && position > 0)
+            || names.iter().any(|n| n != name && split_prepared_column(&source.table, n) == name)
+    };
+    let mut columns: Vec<LayerSource> = columns
+        .into_iter()
+        .enumerate()
+        .filter(|(position, source)| !is_copy(*position, source))
+        .map(|(_, source)| source)
+        .collect();
+
+    for source in columns.iter_mut().skip(1) {
+        source.id = format!("{}.{}", source.table, source.geom_column);
+        source.prepared_column = split_prepared_column(&source.table, &source.geom_column);
+    }
+    columns
+}
+
+
+pub async fn layer_sources(db_pool: &PgPool) -> Result<Vec<LayerSource>, sqlx::Error> {
+    let rows = sqlx::query(&layer_sources_sql()).fetch_all(db_pool).await?;
+    let mut sources = Vec::new();
+    let mut table: Vec<LayerSource> = Vec::new();
+    for row in &rows {
+        let source = LayerSource::from_row(row)?;
+        if let Some(first) = table.first()
+            && (first.schema != source.schema || first.table != source.table)
+        {
+            sources.extend(table_sources(std::mem::take(&mut table)));
+        }
+        table.push(source);
+    }
+    sources.extend(table_sources(table));
+    Ok(sources)
+}
+
+
+pub(crate) async fn relation_exists(db_pool: &PgPool, qualified: &str) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("SELECT to_regclass($1) IS NOT NULL AS found")
+        .bind(qualified)
+        .fetch_one(db_pool)
+        .await?;
+    row.try_get("found")
+}
+
+
+/// Relasi SQL layer (kolom sumber + geom_3857) beserta mode yang benar-benar dipakai
+pub(crate) async fn resolve(
+    db_pool: &PgPool,
+    source: &LayerSource,
+    mode: PrepMode,
+    columns: &[Column],
+) -> Result<(PrepMode, String), sqlx::Error> {
+    let table = source.qualified();
+    let has_prepared = columns.iter().any(|c| c.name == source.prepared_column);
+
+    // Tabel lama yang hanya punya geom_3857: tidak ada yang perlu disiapkan
+    if source.geom_column == GEOM_3857 {
+        return Ok((PrepMode::Column, table));
+    }
+
+    // ctid tabel sumber ikut supaya paging layer tanpa key tetap punya urutan stabil
+    // (view dan foreign table tidak punya ctid, lihat FeatureId::order_sql)
+    let select_list: Vec<String> = source
+        .has_ctid()
+        .then(|| "t.ctid".to_string())
+        .into_iter()
+        .chain(
+            columns
+                .iter()
+                .filter(|c| c.name != GEOM_3857)
+                .map(|c| format!("t.{}", quote_ident(&c.name))),
+        )
+        .collect();
+    let select_list = select_list.join(", ");
+    let on_the_fly = format!(
+        "(SELECT {}, {} AS {} FROM {} t)",
+        select_list,
+        source.transform("t"),
+        GEOM_3857,
+        table
+    );
+
+    // Kolom / tabel bayangan yang baru terisi sebagian tidak dipakai sampai backfill selesai
+    let backfilling = |pending: bool| {
+        if pending {
+            warn!("Backfill of layer {} is not finished, transforming on the fly", source.id);
+        }
+        pending
+    };
+
+    match mode {
+        PrepMode::Column if has_prepared && backfilling(column_backfill(source).is_pending(db_pool).await?) => {
+            Ok((PrepMode::ReadOnly, on_the_fly))
+        }
+        PrepMode::Column if has_prepared && source.prepared_column == GEOM_3857 => Ok((PrepMode::Column, table)),
+        // Kolom {kolom}_3857 dibaca sebagai geom_3857 supaya query tile tetap sama
+        PrepMode::Column if has_prepared => Ok((
+            PrepMode::Column,
+            format!(
+                "(SELECT {}, t.{} AS {} FROM {} t)",
+                select_list, quote_ident(&source.prepared_column), GEOM_3857, table
+            ),
+        )),
+        PrepMode::Column => {
+            warn!("Layer {} has no {} column yet, transforming on the fly", source.id, source.prepared_column);
+            Ok((PrepMode::ReadOnly, on_the_fly))
+        }
+        PrepMode::Shadow => {
+            let shadow = source.shadow_table();
+            let pk = primary_key(db_pool, &source.schema, &source.table).await?;
+            if pk.is_empty() || !can_select(db_pool, &shadow).await? {
+                warn!("Shadow table of layer {} is not prepared or not readable, transforming on the fly", source.id);
+                return Ok((PrepMode::ReadOnly, on_the_fly));
+            }
+            if backfilling(shadow_backfill(source, &pk).is_pending(db_pool).await?) {
+                return Ok((PrepMode::ReadOnly, on_the_fly));
+            }
+            // Baris yang ditambah setelah prepare (tanpa sync trigger) belum punya baris bayangan;
+            // baris itu tetap tampil, ditransform on the fly
+            Ok((
+                PrepMode::Shadow,
+                format!(
+                    "(SELECT {}, COALESCE(s.{}, {}) AS {} FROM {} t LEFT JOIN {} s ON {})",
+                    select_list,
+                    GEOM_3857,
+                    source.transform("t"),
+                    GEOM_3857,
+                    table,
+                    shadow,
+                    key_match(&pk, "s", "t")
+                ),
+            ))
+        }
+        mode => Ok((mode, on_the_fly)),
+    }
+}
+
+
+/// SELECT minx/miny/maxx/maxy (3857) layer. Mode on the fly memakai extent kolom sumber
+/// yang ditransform, supaya tidak perlu transform setiap baris.
+pub(crate) fn extent_sql(source: &LayerSource, layer: &Layer) -> String {
+    let extent = if layer.prep.is_stored() {
+        format!("SELECT ST_Extent(t.{}) AS extent FROM {} t", GEOM_3857, layer.relation)
+    } else {
+        format!(
+            "SELECT ST_Transform(ST_SetSRID(ST_Extent({})::geometry, {}), 3857) AS extent FROM {} t",
+            source.source_geom("t"),
+            source.source_srid().unwrap_or(layer.srid),
+            source.qualified()
+        )
+    };
+    format!(
+        "SELECT ST_XMin(extent) AS minx, ST_YMin(extent) AS miny, \
+                ST_XMax(extent) AS maxx, ST_YMax(extent) AS maxy \
+         FROM ({}) e",
+        extent
+    )
+}
+
+
+// ---------- DDL per mode ----------
+//
+// Dipakai saat persiapan dan oleh `prepare --dry-run` (web::plan), supaya rencana yang
+// ditampilkan sama persis dengan statement yang dijalankan.
+
+pub(crate) fn add_column_sql(source: &LayerSource) -> String {
+    format!(
+        "ALTER TABLE {} ADD COLUMN {} geometry({}, 3857)",
+        source.qualified(), quote_ident(&source.prepared_column), source.column_type()
+    )
+}
+
+fn column_index_name(source: &LayerSource) -> String {
+    pg_identifier(&format!("idx_{}_{}_gist", source.table, source.prepared_column))
+}
+
+/// Nama index beserta schema-nya (untuk dicek lewat to_regclass)
+pub(crate) fn column_index(source: &LayerSource) -> String {
+    format!("{}.{}", quote_ident(&source.schema), quote_ident(&column_index_name(source)))
+}
+
+pub(crate) fn column_index_sql(source: &LayerSource) -> String {
+    format!(
+        "CREATE INDEX CONCURRENTLY IF NOT EXISTS {} ON {} USING GIST({})",
+        quote_ident(&column_index_name(source)),
+        source.qualified(),
+        quote_ident(&source.prepared_column)
+    )
+}
+
+pub(crate) fn column_backfill(source: &LayerSource) -> Backfill {
+    Backfill {
+        prep_schema: prep_schema(),
+        mode: PrepMode::Column.as_str(),
+        verb: "Backfilled",
+        layer: source.id.clone(),
+        schema: source.schema.clone(),
+        table: source.table.clone(),
+        statement: format!(
+            "UPDATE {} AS t SET {col} = {} WHERE t.{col} IS NULL AND {{range}}",
+            source.qualified(),
+            source.transform("t"),
+            col = quote_ident(&source.prepared_column),
+        ),
+    }
+}
+
+pub(crate) fn shadow_create_sql(source: &LayerSource, pk: &[String]) -> [String; 2] {
+    let shadow = source.shadow_table();
+    let pk_list: Vec<String> = pk.iter().map(|c| quote_ident(c)).collect();
+    [
+        format!(
+            "CREATE TABLE {} AS SELECT {}, {}::geometry({}, 3857) AS {} FROM {} t WITH NO DATA",
+            shadow,
+            prefixed(pk, "t"),
+            source.transform("t"),
+            source.column_type(),
+            GEOM_3857,
+            source.qualified()
+        ),
+        format!("ALTER TABLE {} ADD PRIMARY KEY ({})", shadow, pk_list.join(", ")),
+    ]
+}
+
+/// Buang baris bayangan yang sudah dihapus di tabel sumber
+pub(crate) fn shadow_cleanup_sql(source: &LayerSource, pk: &[String]) -> String {
+    format!(
+        "DELETE FROM {} s WHERE NOT EXISTS (SELECT 1 FROM {} t WHERE {})",
+        source.shadow_table(), source.qualified(), key_match(pk, "s", "t")
+    )
+}
+
+fn shadow_index_name(source: &LayerSource) -> String {
+    pg_identifier(&format!("{}_geom_3857_gist", source.prep_name()))
+}
+
+pub(crate) fn shadow_index(source: &LayerSource) -> String {
+    format!("{}.{}", quote_ident(&prep_schema()), quote_ident(&shadow_index_name(source)))
+}
+
+pub(crate) fn shadow_index_sql(source: &LayerSource) -> String {
+    format!(
+        "CREATE INDEX IF NOT EXISTS {} ON {} USING GIST({})",
+        quote_ident(&shadow_index_name(source)),
+        source.shadow_table(),
+        GEOM_3857
+    )
+}
+
+pub(crate) fn shadow_backfill(source: &LayerSource, pk: &[String]) -> Backfill {
+    let shadow = source.shadow_table();
+    let pk_list: Vec<String> = pk.iter().map(|c| quote_ident(c)).collect();
+    Backfill {
+        prep_schema: prep_schema(),
+        mode: PrepMode::Shadow.as_str(),
+        verb: "Backfilled",
+        layer: source.id.clone(),
+        schema: source.schema.clone(),
+        table: source.table.clone(),
+        statement: format!(
+            "INSERT INTO {shadow} ({pk_list}, {g}) \
+             SELECT {t_pk}, {geom} FROM {table} t \
+             WHERE {{range}} AND NOT EXISTS (SELECT 1 FROM {shadow} s WHERE {pk_match}) \
+             ON CONFLICT DO NOTHING",
+            pk_list = pk_list.join(", "),
+            g = GEOM_3857,
+            t_pk = prefixed(pk, "t"),
+            geom = source.transform("t"),
+            table = source.qualified(),
+            pk_match = key_match(pk, "s", "t"),
+        ),
+    }
+}
+
+// Ekspresi dengan ST_MakeValid berbeda, jadi index-nya juga bernama lain
+fn expression_index_name(source: &LayerSource) -> String {
+    let suffix = if source.make_valid() { "valid_expr" } else { "expr" };
+    pg_identifier(&format!("idx_{}_{}_{}", source.table, source.prepared_column, suffix))
+}
+
+pub(crate) fn expression_index(source: &LayerSource) -> String {
+    format!("{}.{}", quote_ident(&source.schema), quote_ident(&expression_index_name(source)))
+}
+
+pub(crate) fn expression_index_sql(source: &LayerSource) -> String {
+    format!(
+        "CREATE INDEX CONCURRENTLY IF NOT EXISTS {} ON {} USING GIST(({}))",
+        quote_ident(&expression_index_name(source)),
+        source.qualified(),
+        source.transform("")
+    )
+}
+
+
+// ---------- Persiapan (menulis ke database) ----------
+
+/// Siapkan geometri 3857 satu layer sesuai mode. Mode none tidak menulis apa pun.
+pub async fn prepare(db_pool: &PgPool, source: &LayerSource, mode: PrepMode, config: &BackfillConfig) -> Result<(), sqlx::Error> {
+    if source.geom_column == GEOM_3857 {
+        return Ok(());
+    }
+    match mode {
+        PrepMode::Column => prepare_column(db_pool, source, config).await,
+        PrepMode::Shadow => prepare_shadow(db_pool, source, config).await,
+        PrepMode::Expression => prepare_expression(db_pool, source).await,
+        PrepMode::ReadOnly => Ok(()),
+    }
+}
+
+
+// Kolom geom_3857 di tabel sumber
+async fn prepare_column(db_pool: &PgPool, source: &LayerSource, config: &BackfillConfig) -> Result<(), sqlx::Error> {
+    let check_col = sqlx::query(
+        r#"
+        SELECT column_name
+        FROM information_schema.columns
+        WHERE table_schema = $1
+        AND table_name = $2
+        AND column_name = $3
+        "#
+    )
+    .bind(&source.schema)
+    .bind(&source.table)
+    .bind(&source.prepared_column)
+    .fetch_optional(db_pool)
+    .await?;
+
+    // 🔹 Jika kolom geom_3857 ({kolom}_3857) belum ada → buat kolom baru dengan tipe geometri 2D saja
+    if check_col.is_none() {
+        column_backfill(source).mark_pending(db_pool).await?;
+        sqlx::query(&add_column_sql(source)).execute(db_pool).await?;
+        println!("   ✅ Created column: {} ({}, 3857)", source.prepared_column, source.column_type());
+    }
+
+    // Trigger dipasang sebelum backfill supaya baris yang berubah selama backfill tidak terlewat
+    sync_column_trigger(db_pool, source).await?;
+
+    // Transform dan isi data dari kolom asli ke geom_3857 (force 2D dengan ST_Force2D), per batch key
+    column_backfill(source).run(db_pool, config).await?;
+    println!("   ✅ Transformed data from SRID {} to 3857 (forced to 2D)", source.srid);
+
+    sqlx::query(&column_index_sql(source)).execute(db_pool).await?;
+    Ok(())
+}
+
+
+// Tabel bayangan di PREP_SCHEMA, key = primary key tabel sumber
+async fn prepare_shadow(db_pool: &PgPool, source: &LayerSource, config: &BackfillConfig) -> Result<(), sqlx::Error> {
+    let pk = primary_key(db_pool, &source.schema, &source.table).await?;
+    if pk.is_empty() {
+        warn!("{}.{} has no primary key, shadow table skipped", source.schema, source.table);
+        return Ok(());
+    }
+
+    let shadow = source.shadow_table();
+
+    sqlx::query(&create_schema_sql(&prep_schema()))
+        .execute(db_pool)
+        .await?;
+
+    if !relation_exists(db_pool, &shadow).await? {
+        shadow_backfill(source, &pk).mark_pending(db_pool).await?;
+        for sql in shadow_create_sql(source, &pk) {
+            sqlx::query(&sql).execute(db_pool).await?;
+        }
+        println!("   ✅ Created shadow table: {}", shadow);
+    }
+
+    sync_shadow_trigger(db_pool, source, &pk).await?;
+
+    // Baris baru di sumber → isi per batch key; baris yang sudah dihapus di sumber → buang
+    let inserted = shadow_backfill(source, &pk).run(db_pool, config).await?;
+    let deleted = sqlx::query(&shadow_cleanup_sql(source, &pk))
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+    println!("   ✅ Shadow rows: {} added, {} removed", inserted, deleted);
+
+    sqlx::query(&shadow_index_sql(source)).execute(db_pool).await?;
+    Ok(())
+}
+
+
+// Expression index; tabel sumber tidak berubah, hanya index baru
+async fn prepare_expression(db_pool: &PgPool, source: &LayerSource) -> Result<(), sqlx::Error> {
+    sqlx::query(&expression_index_sql(source)).execute(db_pool).await?;
+    println!("   ✅ Expression index on {}", source.transform(""));
+    Ok(())
+}
+
+
+// ---------- Trigger sinkronisasi ----------
+//
+// Opsional per layer (`sync_trigger` di layers.json, default env GEOM_SYNC_TRIGGER=false).
+// Mode column: BEFORE INSERT/UPDATE mengisi NEW.geom_3857 dari kolom asli.
+// Mode shadow: AFTER INSERT/UPDATE/DELETE/TRUNCATE memperbarui tabel bayangan.
+// Function trigger disimpan di PREP_SCHEMA supaya schema sumber tetap bersih. Function berjalan
+// sebagai SECURITY DEFINER (hak role persiapan yang membuatnya, search_path dikunci ke pg_catalog
+// dan schema PostGIS), sehingga aplikasi yang menulis ke tabel sumber tidak butuh hak atas
+// PREP_SCHEMA atau tabel bayangan.
+
+// Nama trigger per kolom hasil persiapan, mis. tile_sync_geom_3857 + tile_sync_geom_3857_truncate
+fn sync_triggers(source: &LayerSource) -> [String; 2] {
+    let name = format!("tile_sync_{}", source.prepared_column);
+    [pg_identifier(&name), pg_identifier(&format!("{}_truncate", name))]
+}
+
+
+pub fn layer_sync_trigger(layer_id: &str) -> bool {
+    layer_config(layer_id).sync_trigger.unwrap_or_else(|| {
+        std::env::var("GEOM_SYNC_TRIGGER")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false)
+    })
+}
+
+
+pub(crate) fn sync_function(source: &LayerSource) -> String {
+    format!(
+        "{}.{}",
+        quote_ident(&prep_schema()),
+        quote_ident(&pg_identifier(&format!("sync_{}", source.prep_name())))
+    )
+}
+
+
+/// Schema tempat extension PostGIS dipasang (sudah di-quote); `public` jika tidak ditemukan
+pub(crate) async fn postgis_schema(db_pool: &PgPool) -> Result<String, sqlx::Error> {
+    let schema: Option<String> = sqlx::query_scalar(
+        "SELECT n.nspname::text FROM pg_extension e JOIN pg_namespace n ON n.oid = e.extnamespace \
+         WHERE e.extname = 'postgis'"
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    Ok(quote_ident(schema.as_deref().unwrap_or("public")))
+}
+
+
+/// DDL function trigger. Trigger tidak butuh hak EXECUTE saat dijalankan, jadi function
+/// ditutup dari PUBLIC supaya tidak bisa dipanggil langsung dengan hak pemiliknya.
+/// `postgis_schema` ikut search_path supaya ST_Transform dkk. ditemukan di mana pun PostGIS dipasang.
+pub(crate) fn sync_function_sql(source: &LayerSource, body: &str, postgis_schema: &str) -> [String; 2] {
+    let function = sync_function(source);
+    [
+        format!(
+            "CREATE OR REPLACE FUNCTION {}() RETURNS trigger LANGUAGE plpgsql \
+             SECURITY DEFINER SET search_path = pg_catalog, {} AS $sync$\nBEGIN\n{}\nEND;\n$sync$",
+            function, postgis_schema, body
+        ),
+        format!("REVOKE ALL ON FUNCTION {}() FROM PUBLIC", function),
+    ]
+}
+
+
+/// Ganti trigger lama dengan `triggers` (nama, definisi setelah `CREATE TRIGGER nama`)
+pub(crate) fn sync_trigger_sql(source: &LayerSource, triggers: &[(String, String)]) -> Vec<String> {
+    let function = sync_function(source);
+    let drops = sync_triggers(source)
+        .into_iter()
+        .map(|name| format!("DROP TRIGGER IF EXISTS {} ON {}", quote_ident(&name), source.qualified()));
+    let creates = triggers.iter().map(|(name, definition)| {
+        format!("CREATE TRIGGER {} {} EXECUTE FUNCTION {}()", quote_ident(name), definition, function)
+    });
+    drops.chain(creates).collect()
+}
+
+
+pub(crate) fn drop_sync_trigger_sql(source: &LayerSource) -> Vec<String> {
+    let mut statements = sync_trigger_sql(source, &[]);
+    statements.push(format!("DROP FUNCTION IF EXISTS {}()", sync_function(source)));
+    statements
+}
+
+
+pub(crate) async fn has_sync_trigger(db_pool: &PgPool, source: &LayerSource) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT EXISTS (SELECT 1 FROM pg_trigger WHERE tgrelid = to_regclass($1) AND tgname = $2) AS found"
+    )
+    .bind(source.qualified())
+    .bind(&sync_triggers(source)[0])
+    .fetch_one(db_pool)
+    .await?;
+    row.try_get("found")
+}
+
+
+/// Pasang (atau ganti) function + trigger dalam satu transaksi.
+/// `triggers` berisi (nama, definisi setelah `CREATE TRIGGER nama`).
+async fn install_sync_trigger(
+    db_pool: &PgPool,
+    source: &LayerSource,
+    body: &str,
+    triggers: &[(String, String)],
+) -> Result<(), sqlx::Error> {
+    let function = sync_function(source);
+    let postgis = postgis_schema(db_pool).await?;
+
+    let mut tx = db_pool.begin().await?;
+    sqlx::query(&create_schema_sql(&prep_schema()))
+        .execute(&mut *tx)
+        .await?;
+    for statement in sync_function_sql(source, body, &postgis).iter().chain(&sync_trigger_sql(source, triggers)) {
+        sqlx::query(statement).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+
+    println!("   ✅ Sync trigger installed: {}", function);
+    Ok(())
+}
+
+
+/// Buang trigger yang sudah dimatikan di konfigurasi; tidak menulis apa pun jika memang tidak ada
+async fn drop_sync_trigger(db_pool: &PgPool, source: &LayerSource) -> Result<(), sqlx::Error> {
+    if !has_sync_trigger(db_pool, source).await? {
+        return Ok(());
+    }
+    for statement in drop_sync_trigger_sql(source) {
+        sqlx::query(&statement).execute(db_pool).await?;
+    }
+    println!("   ✅ Sync trigger removed");
+    Ok(())
+}
+
+
+/// Body function trigger + daftar (nama, definisi trigger)
+pub(crate) type SyncDefinition = (String, Vec<(String, String)>);
+
+pub(crate) fn column_sync(source: &LayerSource) -> SyncDefinition {
+    let geom = quote_ident(&source.geom_column);
+    let body = format!(
+        "    NEW.{} := {};\n    RETURN NEW;",
+        quote_ident(&source.prepared_column),
+        source.transform("NEW")
+    );
+    let trigger = format!(
+        "BEFORE INSERT OR UPDATE OF {} ON {} FOR EACH ROW",
+        geom, source.qualified()
+    );
+    let [name, _] = sync_triggers(source);
+    (body, vec![(name, trigger)])
+}
+
+
+async fn sync_column_trigger(db_pool: &PgPool, source: &LayerSource) -> Result<(), sqlx::Error> {
+    if !layer_sync_trigger(&source.id) {
+        return drop_sync_trigger(db_pool, source).await;
+    }
+    let (body, triggers) = column_sync(source);
+    install_sync_trigger(db_pool, source, &body, &triggers).await
+}
+
+
+pub(crate) fn shadow_sync(source: &LayerSource, pk: &[String]) -> SyncDefinition {
+    let shadow = source.shadow_table();
+    let pk_list: Vec<String> = pk.iter().map(|c| quote_ident(c)).collect();
+    let pk_list = pk_list.join(", ");
+    let body = format!(
+        r#"    IF TG_OP = 'TRUNCATE' THEN
+        TRUNCATE {shadow};
+        RETURN NULL;
+    END IF;
+    IF TG_OP = 'DELETE' OR (TG_OP = 'UPDATE' AND ROW({old_pk}) IS DISTINCT FROM ROW({new_pk})) THEN
+        DELETE FROM {shadow} s WHERE {old_match};
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        INSERT INTO {shadow} ({pk_list}, {g}) VALUES ({new_pk}, {geom})
+        ON CONFLICT ({pk_list}) DO UPDATE SET {g} = EXCLUDED.{g};
+    END IF;
+    RETURN NULL;"#,
+        old_pk = prefixed(pk, "OLD"),
+        new_pk = prefixed(pk, "NEW"),
+        old_match = key_match(pk, "s", "OLD"),
+        geom = source.transform("NEW"),
+        g = GEOM_3857,
+    );
+
+    // UPDATE hanya relevan jika geometri atau key berubah
+    let mut update_of = pk.to_vec();
+    update_of.push(source.geom_column.clone());
+    let update_of: Vec<String> = update_of.iter().map(|c| quote_ident(c)).collect();
+    let table = source.qualified();
+    let [name, truncate_name] = sync_triggers(source);
+    let triggers = vec![
+        (
+            name,
+            format!("AFTER INSERT OR UPDATE OF {} OR DELETE ON {} FOR EACH ROW", update_of.join(", "), table),
+        ),
+        (truncate_name, format!("AFTER TRUNCATE ON {} FOR EACH STATEMENT", table)),
+    ];
+    (body, triggers)
+}
+
+
+async fn sync_shadow_trigger(db_pool: &PgPool, source: &LayerSource, pk: &[String]) -> Result<(), sqlx::Error> {
+    if !layer_sync_trigger(&source.id) {
+        return drop_sync_trigger(db_pool, source).await;
+    }
+    let (body, triggers) = shadow_sync(source, pk);
+    install_sync_trigger(db_pool, source, &body, &triggers).await
+}
+
+
+// ---------- Verifikasi ----------
+
+pub enum Verification {
+    /// Mode expression / none: geom_3857 dihitung saat query, selalu sinkron
+    OnTheFly,
+    /// Kolom geom_3857 / tabel bayangan belum ada
+    NotPrepared,
+    Checked {
+        mismatched: i64,
+        orphaned: i64,
+        samples: Vec<String>,
+    },
+}
+
+const VERIFY_SAMPLES: i64 = 10;
+
+
+/// Bandingkan geom_3857 tersimpan dengan hasil transform kolom asli
+pub async fn verify(db_pool: &PgPool, source: &LayerSource, mode: PrepMode) -> Result<Verification, sqlx::Error> {
+    let table = source.qualified();
+    let expected = source.transform("t");
+    let pk = primary_key(db_pool, &source.schema, &source.table).await?;
+    let key = row_key(&pk);
+
+    let (from, stored, orphan_sql) = match mode {
+        PrepMode::Expression | PrepMode::ReadOnly => return Ok(Verification::OnTheFly),
+        PrepMode::Column => {
+            let has_column = sqlx::query(
+                "SELECT 1 FROM information_schema.columns WHERE table_schema = $1 AND table_name = $2 AND column_name = $3"
+            )
+            .bind(&source.schema)
+            .bind(&source.table)
+            .bind(&source.prepared_column)
+            .fetch_optional(db_pool)
+            .await?
+            .is_some();
+            if !has_column {
+                return Ok(Verification::NotPrepared);
+            }
+            (format!("{} t", table), format!("t.{}", quote_ident(&source.prepared_column)), None)
+        }
+        PrepMode::Shadow => {
+            let shadow = source.shadow_table();
+            if pk.is_empty() || !relation_exists(db_pool, &shadow).await? {
+                return Ok(Verification::NotPrepared);
+            }
+            (
+                format!("{} t LEFT JOIN {} s ON {}", table, shadow, key_match(&pk, "s", "t")),
+                format!("s.{}", GEOM_3857),
+                Some(format!(
+                    "SELECT count(*) AS orphaned FROM {} s WHERE NOT EXISTS (SELECT 1 FROM {} t WHERE {})",
+                    shadow, table, key_match(&pk, "s", "t")
+                )),
+            )
+        }
+    };
+
+    let rows = sqlx::query(&format!(
+        "SELECT {key} AS key, count(*) OVER () AS total FROM {from} \
+         WHERE {stored} IS DISTINCT FROM {expected} LIMIT {VERIFY_SAMPLES}"
+    ))
+    .fetch_all(db_pool)
+    .await?;
+    let mismatched = match rows.first() {
+        Some(row) => row.try_get("total")?,
+        None => 0,
+    };
+    let samples = rows.iter().map(|r| r.try_get("key")).collect::<Result<Vec<String>, _>>()?;
+
+    let orphaned = match orphan_sql {
+        Some(sql) => sqlx::query(&sql).fetch_one(db_pool).await?.try_get("orphaned")?,
+        None => 0,
+    };
+
+    Ok(Verification::Checked { mismatched, orphaned, samples })
+}
+
+
+/// `prepare --verify`: laporan semua layer. Mengembalikan true jika semua sinkron.
+pub async fn verify_layers(db_pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let sources = layer_sources(db_pool).await?;
+    let mut in_sync = true;
+
+    println!("{:<30} {:<12} {:<8} Status", "Table", "Mode", "Trigger");
+    println!("{}", "─".repeat(85));
+
+    for source in sources {
+        if source.geom_column == GEOM_3857 || !matches!(source.relkind.as_deref(), Some("r") | Some("m")) {
+            continue;
+        }
+        let mode = layer_prep_mode(&source.id);
+        if let Some(problem) = source.problem() {
+            println!("{:<30} {:<12} {:<8} - skipped: {}", format!("{}.{}", source.schema, source.id), mode, "-", problem);
+            continue;
+        }
+        let trigger = if has_sync_trigger(db_pool, &source).await? { "yes" } else { "no" };
+        let status = match verify(db_pool, &source, mode).await? {
+            Verification::OnTheFly => "- transformed on the fly".to_string(),
+            Verification::NotPrepared => {
+                in_sync = false;
+                "✗ not prepared".to_string()
+            }
+            Verification::Checked { mismatched: 0, orphaned: 0, .. } => "✓ in sync".to_string(),
+            Verification::Checked { mismatched, orphaned, samples } => {
+                in_sync = false;
+                let mut status = format!("✗ {} rows differ", mismatched);
+                if !samples.is_empty() {
+                    status.push_str(&format!(" (e.g. {})", samples.join("; ")));
+                }
+                if orphaned > 0 {
+                    status.push_str(&format!(", {} orphaned shadow rows", orphaned));
+                }
+                status
+            }
+        };
+        println!(
+            "{:<30} {:<12} {:<8} {}",
+            format!("{}.{}", source.schema, source.id), mode, trigger, status
+        );
+    }
+
+    Ok(in_sync)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::utils::MAX_IDENTIFIER_LEN;
+
+    fn source(table: &str, geom_column: &str) -> LayerSource {
+        LayerSource {
+            id: table.to_string(),
+            schema: "public".to_string(),
+            table: table.to_string(),
+            geom_column: geom_column.to_string(),
+            geom_type: "POLYGON".to_string(),
+            srid: 4326,
+            relkind: Some("r".to_string()),
+            geography: false,
+            prepared_column: GEOM_3857.to_string(),
+            selectable: true,
+        }
+    }
+
+    #[test]
+    fn long_names_fit_namedatalen() {
+        let table = "t".repeat(60);
+        let column = "c".repeat(62);
+        let layers = table_sources(vec![source(&table, "geom"), source(&table, &column)]);
+        assert_eq!(layers.len(), 2);
+        let long = &layers[1];
+
+        assert_eq!(long.prepared_column.len(), MAX_IDENTIFIER_LEN);
+        assert!(long.prep_name().len() <= MAX_IDENTIFIER_LEN);
+        assert!(column_index_name(long).len() <= MAX_IDENTIFIER_LEN);
+        assert!(shadow_index_name(long).len() <= MAX_IDENTIFIER_LEN);
+        assert!(expression_index_name(long).len() <= MAX_IDENTIFIER_LEN);
+        assert_ne!(column_index_name(&layers[0]), column_index_name(long));
+    }
+
+    #[test]
+    fn hashed_prepared_column_is_not_published() {
+        let column = "c".repeat(62);
+        let prepared = split_prepared_column("parcels", &column);
+        let layers = table_sources(vec![
+            source("parcels", "geom"),
+            source("parcels", &column),
+            source("parcels", &prepared),
+        ]);
+        let columns: Vec<&str> = layers.iter().map(|l| l.geom_column.as_str()).collect();
+        assert_eq!(columns, ["geom", column.as_str()]);
+    }
+
+    fn ids(layers: &[LayerSource]) -> Vec<(&str, &str, &str)> {
+        layers
+            .iter()
+            .map(|l| (l.id.as_str(), l.geom_column.as_str(), l.prepared_column.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn first_column_keeps_table_name() {
+        let layers = table_sources(vec![
+            source("parcels", "boundary"),
+            source("parcels", "centroid"),
+            source("parcels", GEOM_3857),
+            source("parcels", "centroid_3857"),
+        ]);
+        assert_eq!(
+            ids(&layers),
+            [("parcels", "boundary", GEOM_3857), ("parcels.centroid", "centroid", "centroid_3857")]
+        );
+        assert_eq!(layers[0].prep_name(), "public__parcels");
+        assert_eq!(layers[1].prep_name(), "public__parcels__centroid");
+    }
+
+    #[test]
+    fn prepared_copy_of_single_column_is_hidden() {
+        let layers = table_sources(vec![source("roads", "wkb_geometry"), source("roads", GEOM_3857)]);
+        assert_eq!(ids(&layers), [("roads", "wkb_geometry", GEOM_3857)]);
+    }
+
+    #[test]
+    fn legacy_geom_3857_survives_new_column() {
+        let layers = table_sources(vec![source("zones", GEOM_3857), source("zones", "outline")]);
+        assert_eq!(
+            ids(&layers),
+            [("zones", GEOM_3857, GEOM_3857), ("zones.outline", "outline", "outline_3857")]
+        );
+    }
+
+    #[test]
+    fn added_geom_column_does_not_reuse_geom_3857() {
+        let layers = table_sources(vec![source("sites", "boundary"), source("sites", "geom")]);
+        assert_eq!(
+            ids(&layers),
+            [("sites", "boundary", GEOM_3857), ("sites.geom", "geom", "sites_geom_3857")]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+// Hak akses role database yang dipakai server.
+//
+// Server bisa berjalan dengan role yang hanya punya SELECT. Saat startup hak akses dicek lewat
+// has_*_privilege (tanpa mencoba DDL lalu gagal); tanpa hak DDL server masuk mode read-only:
+// migrasi dan persiapan geometri dilewati dengan peringatan per layer, layer tetap dilayani
+// on the fly atau dari hasil persiapan yang sudah ada. Layer yang tidak bisa di-SELECT oleh role
+// tidak dipublikasikan (lihat LayerSource::problem).
+//
+//   READ_ONLY   paksa mode read-only walaupun role punya hak DDL (default false)
+use once_cell::sync::OnceCell;
+use log::warn;
+use sqlx::{PgPool, Row};
+use super::db::service_schema;
+use super::prep::{self, LayerSource, PrepMode, GEOM_3857};
+
+static CAPABILITIES: OnceCell<Capabilities> = OnceCell::new();
+
+
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub role: String,
+    /// Hot standby atau default_transaction_read_only: tidak ada yang bisa ditulis
+    pub read_only_database: bool,
+    /// CREATE di SERVICE_SCHEMA (atau di database jika schema belum ada)
+    pub create_service_schema: bool,
+    /// CREATE di PREP_SCHEMA (atau di database jika schema belum ada)
+    pub create_prep_schema: bool,
+    /// READ_ONLY=true
+    pub forced_read_only: bool,
+}
+
+impl Capabilities {
+    /// Bisa memasang migrasi
+    pub fn can_migrate(&self) -> bool {
+        !self.read_only_database && self.create_service_schema
+    }
+
+    /// Bisa menulis di PREP_SCHEMA (tabel bayangan, progress backfill, laporan validitas)
+    pub fn can_write_prep_schema(&self) -> bool {
+        !self.read_only_database && self.create_prep_schema
+    }
+
+    /// Mode read-only: server tidak menjalankan DDL apa pun
+    pub fn read_only(&self) -> bool {
+        self.forced_read_only || !self.can_migrate()
+    }
+
+    /// Ringkasan untuk /layers/status
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "role": self.role,
+            "read_only": self.read_only(),
+            "read_only_database": self.read_only_database,
+            "migrate": self.can_migrate(),
+            "prepare": !self.read_only() && self.can_write_prep_schema(),
+        })
+    }
+}
+
+
+fn forced_read_only() -> bool {
+    std::env::var("READ_ONLY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(false)
+}
+
+
+/// Cek hak akses role koneksi saat ini. Schema yang belum ada butuh CREATE di database.
+pub async fn detect(db_pool: &PgPool) -> Result<Capabilities, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            current_user::text AS role,
+            pg_is_in_recovery() OR current_setting('transaction_read_only')::boolean AS read_only,
+            has_database_privilege(current_database(), 'CREATE') AS create_schema,
+            CASE WHEN to_regnamespace(quote_ident($1)) IS NULL THEN NULL
+                ELSE has_schema_privilege($1, 'CREATE') END AS service_create,
+            CASE WHEN to_regnamespace(quote_ident($2)) IS NULL THEN NULL
+                ELSE has_schema_privilege($2, 'CREATE') END AS prep_create
+        "#
+    )
+    .bind(service_schema())
+    .bind(prep::prep_schema())
+    .fetch_one(db_pool)
+    .await?;
+
+    let create_schema: bool = row.try_get("create_schema")?;
+    let can_create = |column: &str| -> Result<bool, sqlx::Error> {
+        let create: Option<bool> = row.try_get(column)?;
+        Ok(create.unwrap_or(create_schema))
+    };
+    Ok(Capabilities {
+        role: row.try_get("role")?,
+        read_only_database: row.try_get("read_only")?,
+        create_service_schema: can_create("service_create")?,
+        create_prep_schema: can_create("prep_create")?,
+        forced_read_only: forced_read_only(),
+    })
+}
+
+
+/// Cek hak akses sekali saat startup, simpan untuk /layers/status, lalu cetak ringkasannya
+pub async fn init(db_pool: &PgPool) -> Result<&'static Capabilities, sqlx::Error> {
+    let capabilities = detect(db_pool).await?;
+    let capabilities = CAPABILITIES.get_or_init(|| capabilities);
+
+    let yes_no = |v: bool| if v { "yes" } else { "no" };
+    println!("\n🔑 Database role {}", capabilities.role);
+    println!("   read-only database : {}", yes_no(capabilities.read_only_database));
+    println!("   migrations         : {} (schema {})", yes_no(capabilities.can_migrate()), service_schema());
+    println!("   preparation        : {} (schema {})", yes_no(capabilities.can_write_prep_schema()), prep::prep_schema());
+    if capabilities.read_only() {
+        let reason = if capabilities.read_only_database {
+            "the database is read-only".to_string()
+        } else if capabilities.forced_read_only {
+            "READ_ONLY=true".to_string()
+        } else {
+            format!("role {} cannot create objects in schema {}", capabilities.role, service_schema())
+        };
+        warn!("Running in read-only mode ({}): migrations and geometry preparation are skipped", reason);
+        println!("   ⚠️  Read-only mode: {}", reason);
+    }
+    println!();
+    Ok(capabilities)
+}
+
+
+/// Hak akses yang dicek saat startup (None di luar server, mis. perintah CLI)
+pub fn current() -> Option<&'static Capabilities> {
+    CAPABILITIES.get()
+}
+
+
+/// Apakah relasi (sudah di-quote) ada dan bisa di-SELECT oleh role
+pub(crate) async fn can_select(db_pool: &PgPool, qualified: &str) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT CASE WHEN to_regclass($1) IS NULL THEN false ELSE has_table_privilege($1, 'SELECT') END AS allowed"
+    )
+    .bind(qualified)
+    .fetch_one(db_pool)
+    .await?;
+    row.try_get("allowed")
+}
+
+
+/// Error karena hak akses kurang atau database read-only
+pub fn is_permission_error(error: &sqlx::Error) -> bool {
+    error
+        .as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == "42501" || code == "25006")
+}
+
+
+/// Alasan role tidak bisa menyiapkan layer dengan mode `mode` (None = bisa).
+/// Mode column dan expression mengubah tabel sumber (ALTER TABLE / CREATE INDEX butuh owner);
+/// mode column dan shadow menulis ke PREP_SCHEMA; trigger sinkronisasi butuh hak TRIGGER.
+pub async fn prepare_denied(
+    db_pool: &PgPool,
+    capabilities: &Capabilities,
+    source: &LayerSource,
+    mode: PrepMode,
+) -> Result<Option<String>, sqlx::Error> {
+    if mode == PrepMode::ReadOnly || source.geom_column == GEOM_3857 {
+        return Ok(None);
+    }
+    if capabilities.read_only_database {
+        return Ok(Some("the database is read-only".to_string()));
+    }
+
+    let row = sqlx::query(
+        "SELECT pg_has_role(c.relowner, 'USAGE') AS owner, has_table_privilege(c.oid, 'TRIGGER') AS can_trigger \
+         FROM pg_class c WHERE c.oid = to_regclass($1)"
+    )
+    .bind(source.qualified())
+    .fetch_optional(db_pool)
+    .await?;
+    let (owner, can_trigger) = match row {
+        Some(row) => (row.try_get("owner")?, row.try_get("can_trigger")?),
+        None => (false, false),
+    };
+
+    let table = format!("{}.{}", source.schema, source.table);
+    if matches!(mode, PrepMode::Column | PrepMode::Expression) && !owner {
+        return Ok(Some(format!(
+            "role {} does not own {} (mode {} alters the table)",
+            capabilities.role, table, mode
+        )));
+    }
+    if mode.is_stored() && !capabilities.create_prep_schema {
+        return Ok(Some(format!(
+            "role {} cannot create objects in schema {}",
+            capabilities.role, prep::prep_schema()
+        )));
+    }
+    if mode == PrepMode::Shadow && prep::layer_sync_trigger(&source.id) && !can_trigger {
+        return Ok(Some(format!(
+            "role {} cannot create triggers on {} (sync_trigger)",
+            capabilities.role, table
+        )));
+    }
+    Ok(None)
+}
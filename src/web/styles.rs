@@ -0,0 +1,358 @@
+// TileJSON dan style MapLibre GL yang di-generate dari katalog layer.
+//
+//   /tiles/{layer}.json            TileJSON 3.0.0 (source untuk style)
+//   /styles/{layer}.json           style satu layer
+//   /styles/groups/{group}.json    style gabungan semua layer dalam satu grup (LayerConfig.group / schema)
+//
+// Style memakai fill / line / circle sesuai geom_type layer. Dengan ?color_by=kolom warna
+// diambil dari statistik kolom (ramp min-max untuk angka, kategori terbanyak untuk lainnya);
+// kolom harus ada di `properties` layer supaya nilainya ikut di tile. Statistik dihitung
+// dengan scan tabel, jadi di-cache per (layer, kolom) selama versi layer (web::versions)
+// tidak berubah.
+use std::collections::HashMap;
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+use actix_web::{web, HttpResponse, HttpRequest};
+use actix_web::http::StatusCode;
+use log::error;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::{PgPool, Row};
+use super::features::Column;
+use super::ogc::layer_bbox_crs84;
+use super::utils::quote_ident;
+use super::assets::style_sprite;
+use super::versions::current_version;
+use super::web_handler::{Layer, all_layers, base_url, get_layer_detail, ensure_layers_loaded, json_error};
+
+// Warna dasar layer, dipilih dari nama layer supaya stabil antar request
+const PALETTE: &[&str] = &[
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd",
+    "#8c564b", "#e377c2", "#7f7f7f", "#bcbd22", "#17becf",
+];
+
+// Ramp sekuensial (viridis) untuk kolom angka
+const RAMP: &[&str] = &["#440154", "#3b528b", "#21918c", "#5ec962", "#fde725"];
+
+const NUMERIC_TYPES: &[&str] = &["int2", "int4", "int8", "float4", "float8", "numeric"];
+const MAX_CATEGORIES: i64 = 10;
+const DEFAULT_CATEGORY_COLOR: &str = "#cccccc";
+
+// (layer, kolom) → (versi layer saat dihitung, ekspresi warna)
+type ColorCache = HashMap<(String, String), (String, Value)>;
+
+static COLOR_EXPRESSIONS: Lazy<RwLock<ColorCache>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+
+#[derive(Deserialize)]
+pub struct StyleQuery {
+    color_by: Option<String>,
+}
+
+
+fn is_numeric(column: &Column) -> bool {
+    NUMERIC_TYPES.contains(&column.udt_name.as_str())
+}
+
+
+fn base_color(layer: &Layer) -> &'static str {
+    let hash = layer.table_name.bytes().fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+    PALETTE[hash % PALETTE.len()]
+}
+
+
+// Tipe field TileJSON vector_layers
+fn field_type(column: &Column) -> &'static str {
+    if is_numeric(column) {
+        "Number"
+    } else if column.udt_name == "bool" {
+        "Boolean"
+    } else {
+        "String"
+    }
+}
+
+
+fn tilejson_url(base: &str, layer: &Layer) -> String {
+    format!("{}/tiles/{}.json", base, layer.table_name)
+}
+
+
+fn tilejson_body(layer: &Layer, base: &str) -> Value {
+    let bounds = layer_bbox_crs84(layer);
+    let fields: serde_json::Map<String, Value> = layer
+        .properties
+        .iter()
+        .map(|c| (c.name.clone(), json!(field_type(c))))
+        .collect();
+
+    json!({
+        "tilejson": "3.0.0",
+        "name": layer.table_name,
+        "scheme": "xyz",
+        "tiles": [format!("{}/tiles/{}/{{z}}/{{x}}/{{y}}.pbf", base, layer.table_name)],
+        "minzoom": layer.minzoom,
+        "maxzoom": layer.maxzoom,
+        "bounds": bounds,
+        "center": [(bounds[0] + bounds[2]) / 2.0, (bounds[1] + bounds[3]) / 2.0, layer.minzoom],
+        "vector_layers": [{
+            "id": layer.table_name,
+            "fields": fields,
+            "minzoom": layer.minzoom,
+            "maxzoom": layer.maxzoom,
+        }],
+    })
+}
+
+
+// Ekspresi warna dari cache; dihitung ulang jika versi layer sudah berubah sejak terakhir
+async fn cached_color_expression(db_pool: &PgPool, layer: &Layer, column: &Column) -> Result<Value, sqlx::Error> {
+    let key = (layer.table_name.clone(), column.name.clone());
+    let version = current_version(&layer.table_name).await;
+    if let Some((cached_version, expression)) = COLOR_EXPRESSIONS.read().await.get(&key)
+        && *cached_version == version
+    {
+        return Ok(expression.clone());
+    }
+
+    let expression = color_expression(db_pool, layer, column).await?;
+    COLOR_EXPRESSIONS.write().await.insert(key, (version, expression.clone()));
+    Ok(expression)
+}
+
+
+// Ekspresi warna dari statistik kolom: interpolate (angka) atau match (kategori)
+async fn color_expression(db_pool: &PgPool, layer: &Layer, column: &Column) -> Result<Value, sqlx::Error> {
+    let table = format!("{}.{}", quote_ident(&layer.schema), quote_ident(&layer.source_table));
+    let col = quote_ident(&column.name);
+
+    if is_numeric(column) {
+        let row = sqlx::query(&format!("SELECT min({col})::float8 AS min, max({col})::float8 AS max FROM {table}"))
+            .fetch_one(db_pool)
+            .await?;
+        let min: Option<f64> = row.try_get("min")?;
+        let max: Option<f64> = row.try_get("max")?;
+
+        let (Some(min), Some(max)) = (min, max) else {
+            return Ok(json!(RAMP[0]));
+        };
+        if max <= min {
+            return Ok(json!(RAMP[RAMP.len() / 2]));
+        }
+
+        let mut expression = vec![json!("interpolate"), json!(["linear"]), json!(["to-number", ["get", column.name]])];
+        for (i, color) in RAMP.iter().enumerate() {
+            let stop = min + (max - min) * i as f64 / (RAMP.len() - 1) as f64;
+            expression.push(json!(stop));
+            expression.push(json!(color));
+        }
+        return Ok(Value::Array(expression));
+    }
+
+    let rows = sqlx::query(&format!(
+        "SELECT {col}::text AS value FROM {table} WHERE {col} IS NOT NULL \
+         GROUP BY 1 ORDER BY count(*) DESC, 1 LIMIT {MAX_CATEGORIES}"
+    ))
+    .fetch_all(db_pool)
+    .await?;
+    if rows.is_empty() {
+        return Ok(json!(DEFAULT_CATEGORY_COLOR));
+    }
+
+    let mut expression = vec![json!("match"), json!(["to-string", ["get", column.name]])];
+    for (i, row) in rows.iter().enumerate() {
+        let value: String = row.try_get("value")?;
+        expression.push(json!(value));
+        expression.push(json!(PALETTE[i % PALETTE.len()]));
+    }
+    expression.push(json!(DEFAULT_CATEGORY_COLOR));
+    Ok(Value::Array(expression))
+}
+
+
+// Layer style MapLibre untuk satu layer data, dipilih dari geom_type
+fn style_layers(layer: &Layer, color: Value) -> Vec<Value> {
+    let id = &layer.table_name;
+    let style_layer = |suffix: &str, kind: &str, paint: Value, geometry: Option<&str>| {
+        let mut l = json!({
+            "id": format!("{}-{}", id, suffix),
+            "type": kind,
+            "source": id,
+            "source-layer": id,
+            "paint": paint,
+        });
+        if let Some(t) = geometry {
+            l["filter"] = json!(["==", ["geometry-type"], t]);
+        }
+        if kind == "line" {
+            l["layout"] = json!({ "line-cap": "round", "line-join": "round" });
+        }
+        l
+    };
+
+    let fill = |g| style_layer("fill", "fill", json!({ "fill-color": color, "fill-opacity": 0.4 }), g);
+    let outline = |g| style_layer("outline", "line", json!({ "line-color": color, "line-width": 1 }), g);
+    let line = |g| style_layer("line", "line", json!({ "line-color": color, "line-width": 2 }), g);
+    let circle = |g| style_layer("circle", "circle", json!({
+        "circle-color": color,
+        "circle-radius": 4,
+        "circle-stroke-color": "#ffffff",
+        "circle-stroke-width": 1,
+    }), g);
+
+    let geom_type = layer.geom_type.to_uppercase();
+    if geom_type.contains("POLYGON") {
+        vec![fill(None), outline(None)]
+    } else if geom_type.contains("LINE") {
+        vec![line(None)]
+    } else if geom_type.contains("POINT") {
+        vec![circle(None)]
+    } else {
+        // GEOMETRY / GEOMETRYCOLLECTION: semua tipe, dibedakan dengan filter
+        vec![
+            fill(Some("Polygon")),
+            outline(Some("Polygon")),
+            line(Some("LineString")),
+            circle(Some("Point")),
+        ]
+    }
+}
+
+
+// Warna layer: dari ?color_by jika kolomnya ada di properties tile, selain itu warna dasar
+async fn layer_color(db_pool: &PgPool, layer: &Layer, color_by: Option<&str>) -> Result<Value, HttpResponse> {
+    let Some(name) = color_by else {
+        return Ok(json!(base_color(layer)));
+    };
+    let Some(column) = layer.properties.iter().find(|c| c.name == name) else {
+        return Err(json_error(
+            StatusCode::BAD_REQUEST,
+            format!("Column {} is not a tile property of {}; add it to \"properties\" in the layer config", name, layer.table_name),
+        ));
+    };
+
+    cached_color_expression(db_pool, layer, column).await.map_err(|e| {
+        error!("Failed to compute color stats for {}.{}: {:?}", layer.table_name, name, e);
+        json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to compute column statistics")
+    })
+}
+
+
+fn style_body(name: &str, layers: &[Layer], style_layers: Vec<Value>, base: &str) -> Value {
+    let sources: serde_json::Map<String, Value> = layers
+        .iter()
+        .map(|l| (l.table_name.clone(), json!({ "type": "vector", "url": tilejson_url(base, l) })))
+        .collect();
+
+    // Center & zoom dari gabungan bounds semua layer
+    let bounds = layers
+        .iter()
+        .map(layer_bbox_crs84)
+        .reduce(|a, b| [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])])
+        .unwrap_or([-180.0, -85.0, 180.0, 85.0]);
+    let zoom = layers.iter().map(|l| l.minzoom).min().unwrap_or(0);
+
+    let mut all = vec![json!({
+        "id": "background",
+        "type": "background",
+        "paint": { "background-color": "#f8f8f8" },
+    })];
+    all.extend(style_layers);
+
+    // Glyph & sprite di-host sendiri (lihat assets.rs), tidak bergantung URL pihak ketiga
+    let mut style = json!({
+        "version": 8,
+        "name": name,
+        "center": [(bounds[0] + bounds[2]) / 2.0, (bounds[1] + bounds[3]) / 2.0],
+        "zoom": zoom,
+        "glyphs": format!("{}/fonts/{{fontstack}}/{{range}}.pbf", base),
+        "sources": sources,
+        "layers": all,
+    });
+    if let Some(sprite) = style_sprite() {
+        style["sprite"] = json!(format!("{}/sprites/{}", base, sprite));
+    }
+    style
+}
+
+
+fn json_response(body: Value) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .json(body)
+}
+
+
+async fn find_layer(db_pool: &PgPool, req: &HttpRequest, table_name: &str) -> Result<Layer, HttpResponse> {
+    ensure_layers_loaded(db_pool, req).await;
+    get_layer_detail(table_name.to_string())
+        .await
+        .ok_or_else(|| json_error(StatusCode::NOT_FOUND, format!("Layer not found: {}", table_name)))
+}
+
+
+pub async fn tilejson(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> HttpResponse {
+    match find_layer(db_pool.get_ref(), &req, &path.into_inner()).await {
+        Ok(layer) => json_response(tilejson_body(&layer, &base_url(&req))),
+        Err(resp) => resp,
+    }
+}
+
+
+pub async fn layer_style(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    query: web::Query<StyleQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let layer = match find_layer(db_pool.get_ref(), &req, &path.into_inner()).await {
+        Ok(layer) => layer,
+        Err(resp) => return resp,
+    };
+    let color = match layer_color(db_pool.get_ref(), &layer, query.color_by.as_deref()).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+
+    let layers = style_layers(&layer, color);
+    json_response(style_body(&layer.table_name, std::slice::from_ref(&layer), layers, &base_url(&req)))
+}
+
+
+pub async fn group_style(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    query: web::Query<StyleQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let group = path.into_inner();
+    let layers: Vec<Layer> = all_layers(db_pool.get_ref(), &req)
+        .await
+        .into_iter()
+        .filter(|l| l.group == group)
+        .collect();
+    if layers.is_empty() {
+        return json_error(StatusCode::NOT_FOUND, format!("Layer group not found: {}", group));
+    }
+
+    // Di style grup, color_by hanya dipakai layer yang punya kolom tersebut
+    let mut all = Vec::new();
+    for layer in &layers {
+        let color_by = query
+            .color_by
+            .as_deref()
+            .filter(|name| layer.properties.iter().any(|c| &c.name == name));
+        let color = match layer_color(db_pool.get_ref(), layer, color_by).await {
+            Ok(c) => c,
+            Err(resp) => return resp,
+        };
+        all.extend(style_layers(layer, color));
+    }
+
+    json_response(style_body(&group, &layers, all, &base_url(&req)))
+}
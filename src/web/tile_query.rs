@@ -0,0 +1,219 @@
+// Query tile MVT per layer, dirender di Rust (pengganti function PL/pgSQL get_tile).
+//
+// Semua identifier dari katalog di-quote dengan quote_ident / quote_literal; yang berubah per
+// request hanya parameter: $1..$4 bbox tile (SRID tile matrix set) dan $5 zoom. Teks SQL jadi
+// tetap per (layer, SRID), disimpan di Layer.tile_queries saat layer dimuat, sehingga sqlx
+// memakai ulang prepared statement yang sama di setiap koneksi.
+use std::collections::HashMap;
+use once_cell::sync::Lazy;
+use super::tms;
+use super::utils::{quote_ident, quote_literal};
+use super::web_handler::Layer;
+
+const TILE_QUERY: &str = r#"
+SELECT COALESCE(ST_AsMVT(tile, {layer}, 4096, 'geom', 'gid'), ''::bytea)
+FROM (
+    SELECT
+        {id} AS gid{properties},
+        ST_AsMVTGeom({geom}, ST_MakeEnvelope($1, $2, $3, $4, {srid}), 4096, 256, true) AS geom
+    FROM {relation} AS t
+    WHERE t.geom_3857 && {bbox_3857}
+) tile
+"#;
+
+// Simplifikasi di geom_3857 sesuai zoom ($5)
+const SIMPLIFY: &str = "CASE \
+    WHEN $5 >= 17 THEN t.geom_3857 \
+    WHEN $5 <= 5 THEN ST_SimplifyVW(t.geom_3857, 1e-6 * power(2, 17 - $5)) \
+    WHEN $5 <= 8 THEN ST_SimplifyVW(t.geom_3857, 1e-7 * power(2, 17 - $5)) \
+    ELSE ST_SimplifyVW(t.geom_3857, 1e-8 * power(2, 17 - $5)) END";
+
+// Bbox tile dalam 3857 untuk filter index geom_3857
+const BBOX_3857: &str = "ST_MakeEnvelope($1, $2, $3, $4, 3857)";
+// Web Mercator tidak terdefinisi di kutub, potong dulu sebelum transform
+const BBOX_4326: &str = "ST_Transform(ST_ClipByBox2D(ST_MakeEnvelope($1, $2, $3, $4, 4326), \
+    ST_MakeEnvelope(-180, -85.0511287798, 180, 85.0511287798, 4326)), 3857)";
+// Tepi envelope grid lain tidak lurus di 3857: densify dulu supaya bbox hasil transform
+// menutupi seluruh tile (sama dengan layer_tms_bounds di web_handler)
+const BBOX_OTHER: &str = "ST_Transform(ST_Segmentize(ST_MakeEnvelope($1, $2, $3, $4, {srid}), \
+    GREATEST($3 - $1, $4 - $2) / 64), 3857)";
+
+/// Semua potongan SQL di atas; ikut fingerprint versi layer (web::versions), jadi perubahan
+/// query otomatis membuat tile lama di cache tidak terpakai
+pub static TILE_QUERY_FINGERPRINT: Lazy<String> =
+    Lazy::new(|| [TILE_QUERY, SIMPLIFY, BBOX_3857, BBOX_4326, BBOX_OTHER].join("\n"));
+
+
+// Isi placeholder `{nama}` dalam satu kali jalan, jadi nilai yang sudah dimasukkan (mis. nama
+// kolom yang kebetulan berisi "{srid}") tidak pernah ikut diganti
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        let value = tail.find('}').and_then(|end| {
+            let name = &tail[1..end];
+            values.iter().find(|(k, _)| *k == name).map(|(_, v)| (*v, end))
+        });
+        match value {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &tail[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+
+/// Query tile satu layer untuk tile matrix set dengan SRID `srid`
+pub fn render(layer: &Layer, srid: i32) -> String {
+    let properties: String = layer
+        .properties
+        .iter()
+        .map(|c| format!(", t.{}", quote_ident(&c.name)))
+        .collect();
+    let geom = if srid == 3857 {
+        SIMPLIFY.to_string()
+    } else {
+        format!("ST_Transform({}, {})", SIMPLIFY, srid)
+    };
+    let srid_text = srid.to_string();
+    let bbox_3857 = match srid {
+        3857 => BBOX_3857.to_string(),
+        4326 => BBOX_4326.to_string(),
+        _ => fill(BBOX_OTHER, &[("srid", &srid_text)]),
+    };
+
+    fill(
+        TILE_QUERY,
+        &[
+            ("layer", &quote_literal(&layer.table_name)),
+            ("id", &layer.feature_id.sql(&layer.geom_column)),
+            ("properties", &properties),
+            ("geom", &geom),
+            ("srid", &srid_text),
+            ("relation", &layer.relation),
+            ("bbox_3857", &bbox_3857),
+        ],
+    )
+}
+
+
+/// Query tile layer untuk setiap SRID tile matrix set yang terdaftar
+pub fn render_all(layer: &Layer) -> HashMap<i32, String> {
+    tms::tile_matrix_sets()
+        .filter_map(|t| t.srid())
+        .map(|srid| (srid, render(layer, srid)))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::feature_id::FeatureId;
+    use crate::web::features::Column;
+    use crate::web::prep::PrepMode;
+
+    fn layer(feature_id: FeatureId, properties: &[&str]) -> Layer {
+        Layer {
+            schema: "public".to_string(),
+            table_name: "roads".to_string(),
+            source_table: "roads".to_string(),
+            group: "public".to_string(),
+            geom_column: "geom_3857".to_string(),
+            source_column: "geom".to_string(),
+            geom_type: "LINESTRING".to_string(),
+            srid: 4326,
+            bbox: [0.0; 4],
+            minzoom: 0,
+            maxzoom: 22,
+            url: String::new(),
+            prep: PrepMode::Column,
+            relation: "\"public\".\"roads\"".to_string(),
+            has_ctid: true,
+            tms_bounds: HashMap::new(),
+            feature_id,
+            properties: properties
+                .iter()
+                .map(|name| Column { name: name.to_string(), udt_name: "text".to_string() })
+                .collect(),
+            tile_queries: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn fill_substitutes_once() {
+        let sql = fill("{a} {b} {a}", &[("a", "{b}"), ("b", "x")]);
+        assert_eq!(sql, "{b} x {b}");
+    }
+
+    #[test]
+    fn fill_keeps_unknown_placeholders() {
+        assert_eq!(fill("{unknown} {srid}", &[("srid", "3857")]), "{unknown} 3857");
+        assert_eq!(fill("a { b", &[]), "a { b");
+    }
+
+    #[test]
+    fn render_web_mercator() {
+        let sql = render(&layer(FeatureId::Column("gid".to_string()), &[]), 3857);
+        assert!(sql.contains("ST_AsMVT(tile, 'roads', 4096, 'geom', 'gid')"));
+        assert!(sql.contains("\"gid\" AS gid,"));
+        assert!(sql.contains("FROM \"public\".\"roads\" AS t"));
+        assert!(sql.contains("WHERE t.geom_3857 && ST_MakeEnvelope($1, $2, $3, $4, 3857)"));
+        assert!(sql.contains(&format!("ST_AsMVTGeom({}, ST_MakeEnvelope($1, $2, $3, $4, 3857)", SIMPLIFY)));
+        assert!(!sql.contains("ST_Transform"));
+    }
+
+    #[test]
+    fn render_crs84_clips_to_mercator() {
+        let sql = render(&layer(FeatureId::Column("gid".to_string()), &[]), 4326);
+        assert!(sql.contains(&format!("ST_AsMVTGeom(ST_Transform({}, 4326), ST_MakeEnvelope($1, $2, $3, $4, 4326)", SIMPLIFY)));
+        assert!(sql.contains(&format!("WHERE t.geom_3857 && {}", BBOX_4326)));
+    }
+
+    #[test]
+    fn render_custom_srid() {
+        let sql = render(&layer(FeatureId::Column("gid".to_string()), &[]), 3395);
+        assert!(sql.contains(
+            "WHERE t.geom_3857 && ST_Transform(ST_Segmentize(ST_MakeEnvelope($1, $2, $3, $4, 3395), \
+             GREATEST($3 - $1, $4 - $2) / 64), 3857)"
+        ));
+        assert!(sql.contains("ST_MakeEnvelope($1, $2, $3, $4, 3395), 4096, 256, true)"));
+        assert!(!sql.contains("{srid}"));
+    }
+
+    #[test]
+    fn render_custom_srid_densifies_filter_box() {
+        let sql = render(&layer(FeatureId::Column("gid".to_string()), &[]), 25832);
+        let filter = sql.split("WHERE t.geom_3857 && ").nth(1).unwrap();
+        assert!(filter.starts_with("ST_Transform(ST_Segmentize(ST_MakeEnvelope($1, $2, $3, $4, 25832),"));
+        assert!(filter.contains("GREATEST($3 - $1, $4 - $2) / 64), 3857)"));
+        // Envelope untuk ST_AsMVTGeom tetap persegi panjang di SRID grid
+        assert!(sql.contains("ST_AsMVTGeom(ST_Transform("));
+        assert!(sql.contains("ST_MakeEnvelope($1, $2, $3, $4, 25832), 4096, 256, true)"));
+    }
+
+    #[test]
+    fn render_quotes_identifiers_and_properties() {
+        let mut layer = layer(FeatureId::Hashed(vec!["Kode \"A\"".to_string()]), &["name", "{srid}", "o'neil"]);
+        layer.table_name = "it's.geom".to_string();
+        let sql = render(&layer, 3857);
+        assert!(sql.contains("ST_AsMVT(tile, 'it''s.geom', 4096"));
+        assert!(sql.contains("(hashtextextended(concat_ws(E'\\x1f', \"Kode \"\"A\"\"\"::text), 0) & 9007199254740991) AS gid"));
+        assert!(sql.contains("AS gid, t.\"name\", t.\"{srid}\", t.\"o'neil\","));
+    }
+
+    #[test]
+    fn render_geometry_hash_id() {
+        let sql = render(&layer(FeatureId::Geometry, &[]), 3857);
+        assert!(sql.contains("(hashtextextended(md5(ST_AsBinary(\"geom_3857\")), 0) & 9007199254740991) AS gid"));
+    }
+}
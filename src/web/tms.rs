@@ -0,0 +1,374 @@
+// Tile matrix set (OGC Two Dimensional Tile Matrix Set 2.0).
+//
+// Built-in: WebMercatorQuad (EPSG:3857) dan WorldCRS84Quad (EPSG:4326).
+// Grid lain dibaca dari file JSON definisi TMS 2.0 di direktori TMS_DIR (default "tms"),
+// satu file per tile matrix set.
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use log::{info, warn};
+use super::config::MAX_SUPPORTED_ZOOM;
+use super::utils::{BBox, TileError};
+
+pub const WEB_MERCATOR_QUAD: &str = "WebMercatorQuad";
+pub const WORLD_CRS84_QUAD: &str = "WorldCRS84Quad";
+
+// Ukuran pixel standar OGC (0.28 mm) untuk menghitung scaleDenominator
+const STANDARD_PIXEL_SIZE: f64 = 0.00028;
+const EARTH_RADIUS: f64 = 6378137.0;
+
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TileMatrixSet {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    pub crs: Crs,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ordered_axes: Option<Vec<String>>,
+    pub tile_matrices: Vec<TileMatrix>,
+}
+
+// TMS 2.0 mengizinkan crs sebagai URI string atau object {"uri": ...}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Crs {
+    Uri(String),
+    Object { uri: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TileMatrix {
+    pub id: String,
+    pub scale_denominator: f64,
+    pub cell_size: f64,
+    #[serde(default = "default_corner")]
+    pub corner_of_origin: String,
+    pub point_of_origin: [f64; 2],
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub matrix_width: u64,
+    pub matrix_height: u64,
+}
+
+fn default_corner() -> String {
+    "topLeft".to_string()
+}
+
+
+impl TileMatrixSet {
+    pub fn crs_uri(&self) -> &str {
+        match &self.crs {
+            Crs::Uri(uri) | Crs::Object { uri } => uri,
+        }
+    }
+
+    /// SRID PostGIS dari URI CRS (".../EPSG/0/3857" -> 3857, CRS84 -> 4326)
+    pub fn srid(&self) -> Option<i32> {
+        let uri = self.crs_uri();
+        if uri.ends_with("CRS84") {
+            return Some(4326);
+        }
+        uri.trim_end_matches('/')
+            .rsplit(['/', ':'])
+            .next()
+            .and_then(|code| code.parse().ok())
+    }
+
+    // CRS dengan urutan axis lat/lon (northing dulu) menulis pointOfOrigin sebagai [y, x]
+    fn northing_first(&self) -> bool {
+        self.ordered_axes
+            .as_ref()
+            .and_then(|axes| axes.first())
+            .map(|axis| matches!(axis.to_ascii_lowercase().as_str(), "lat" | "n" | "y" | "northing"))
+            .unwrap_or(false)
+            && self.srid() != Some(3857)
+    }
+
+    pub fn matrix(&self, z: u32) -> Option<&TileMatrix> {
+        self.tile_matrices.get(z as usize)
+    }
+
+    /// Index tile matrix (dipakai sebagai z) dari id-nya, mis. "12" -> 12
+    pub fn matrix_index(&self, id: &str) -> Option<u32> {
+        self.tile_matrices
+            .iter()
+            .position(|m| m.id == id)
+            .map(|i| i as u32)
+    }
+
+    pub fn max_zoom(&self) -> u32 {
+        self.tile_matrices.len().saturating_sub(1) as u32
+    }
+
+    pub fn validate_tile(&self, z: u32, x: u32, y: u32) -> Result<(), TileError> {
+        let matrix = self
+            .matrix(z)
+            .ok_or(TileError::ZoomTooLarge { z, max: self.max_zoom() })?;
+        if x as u64 >= matrix.matrix_width || y as u64 >= matrix.matrix_height {
+            return Err(TileError::OutOfRange {
+                z,
+                x,
+                y,
+                width: matrix.matrix_width,
+                height: matrix.matrix_height,
+            });
+        }
+        Ok(())
+    }
+
+    /// Bounding box tile dalam CRS tile matrix set (urutan x/east, y/north)
+    pub fn tile_bbox(&self, z: u32, x: u32, y: u32) -> Option<BBox> {
+        let matrix = self.matrix(z)?;
+        let span_x = matrix.cell_size * matrix.tile_width as f64;
+        let span_y = matrix.cell_size * matrix.tile_height as f64;
+
+        let [a, b] = matrix.point_of_origin;
+        let (origin_x, origin_y) = if self.northing_first() { (b, a) } else { (a, b) };
+
+        let minx = origin_x + x as f64 * span_x;
+        let (miny, maxy) = if matrix.corner_of_origin == "bottomLeft" {
+            let miny = origin_y + y as f64 * span_y;
+            (miny, miny + span_y)
+        } else {
+            let maxy = origin_y - y as f64 * span_y;
+            (maxy - span_y, maxy)
+        };
+
+        Some(BBox { minx, miny, maxx: minx + span_x, maxy })
+    }
+
+    /// Rentang kolom/baris tile yang menutupi bounds (dalam CRS TMS): (min_col, max_col, min_row, max_row)
+    pub fn tile_range(&self, z: u32, bounds: &[f64; 4]) -> Option<(u64, u64, u64, u64)> {
+        let matrix = self.matrix(z)?;
+        let span_x = matrix.cell_size * matrix.tile_width as f64;
+        let span_y = matrix.cell_size * matrix.tile_height as f64;
+
+        let [a, b] = matrix.point_of_origin;
+        let (origin_x, origin_y) = if self.northing_first() { (b, a) } else { (a, b) };
+
+        let clamp = |v: f64, max: u64| -> u64 { (v.floor().max(0.0) as u64).min(max.saturating_sub(1)) };
+
+        let min_col = clamp((bounds[0] - origin_x) / span_x, matrix.matrix_width);
+        let max_col = clamp((bounds[2] - origin_x) / span_x, matrix.matrix_width);
+        let (min_row, max_row) = if matrix.corner_of_origin == "bottomLeft" {
+            (
+                clamp((bounds[1] - origin_y) / span_y, matrix.matrix_height),
+                clamp((bounds[3] - origin_y) / span_y, matrix.matrix_height),
+            )
+        } else {
+            (
+                clamp((origin_y - bounds[3]) / span_y, matrix.matrix_height),
+                clamp((origin_y - bounds[1]) / span_y, matrix.matrix_height),
+            )
+        };
+
+        Some((min_col, max_col, min_row, max_row))
+    }
+}
+
+
+fn web_mercator_quad() -> TileMatrixSet {
+    let extent = PI * EARTH_RADIUS;
+    let tile_matrices = (0..=MAX_SUPPORTED_ZOOM)
+        .map(|z| {
+            let n = 1u64 << z;
+            let cell_size = 2.0 * extent / 256.0 / n as f64;
+            TileMatrix {
+                id: z.to_string(),
+                scale_denominator: cell_size / STANDARD_PIXEL_SIZE,
+                cell_size,
+                corner_of_origin: default_corner(),
+                point_of_origin: [-extent, extent],
+                tile_width: 256,
+                tile_height: 256,
+                matrix_width: n,
+                matrix_height: n,
+            }
+        })
+        .collect();
+
+    TileMatrixSet {
+        id: WEB_MERCATOR_QUAD.to_string(),
+        title: Some("Google Maps Compatible for the World".to_string()),
+        uri: Some("http://www.opengis.net/def/tilematrixset/OGC/1.0/WebMercatorQuad".to_string()),
+        crs: Crs::Uri("http://www.opengis.net/def/crs/EPSG/0/3857".to_string()),
+        ordered_axes: Some(vec!["X".to_string(), "Y".to_string()]),
+        tile_matrices,
+    }
+}
+
+
+fn world_crs84_quad() -> TileMatrixSet {
+    // 1 derajat dalam meter di ekuator, untuk scaleDenominator
+    let meters_per_degree = 2.0 * PI * EARTH_RADIUS / 360.0;
+    let tile_matrices = (0..=MAX_SUPPORTED_ZOOM)
+        .map(|z| {
+            let n = 1u64 << z;
+            let cell_size = 180.0 / 256.0 / n as f64;
+            TileMatrix {
+                id: z.to_string(),
+                scale_denominator: cell_size * meters_per_degree / STANDARD_PIXEL_SIZE,
+                cell_size,
+                corner_of_origin: default_corner(),
+                point_of_origin: [-180.0, 90.0],
+                tile_width: 256,
+                tile_height: 256,
+                matrix_width: n * 2,
+                matrix_height: n,
+            }
+        })
+        .collect();
+
+    TileMatrixSet {
+        id: WORLD_CRS84_QUAD.to_string(),
+        title: Some("CRS84 for the World".to_string()),
+        uri: Some("http://www.opengis.net/def/tilematrixset/OGC/1.0/WorldCRS84Quad".to_string()),
+        crs: Crs::Uri("http://www.opengis.net/def/crs/OGC/1.3/CRS84".to_string()),
+        ordered_axes: Some(vec!["Lon".to_string(), "Lat".to_string()]),
+        tile_matrices,
+    }
+}
+
+
+// Registry tile matrix set, urut berdasarkan id
+static TILE_MATRIX_SETS: Lazy<BTreeMap<String, TileMatrixSet>> = Lazy::new(|| {
+    let mut sets = BTreeMap::new();
+    for tms in [web_mercator_quad(), world_crs84_quad()] {
+        sets.insert(tms.id.clone(), tms);
+    }
+
+    let dir = std::env::var("TMS_DIR").unwrap_or_else(|_| "tms".to_string());
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return sets;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|c| serde_json::from_str::<TileMatrixSet>(&c).map_err(|e| e.to_string()));
+
+        match parsed {
+            Ok(tms) if tms.srid().is_none() => {
+                warn!("Skipping tile matrix set {}: unsupported CRS {}", path.display(), tms.crs_uri());
+            }
+            Ok(tms) => {
+                info!("Loaded tile matrix set {} (EPSG:{}) from {}", tms.id, tms.srid().unwrap_or(0), path.display());
+                sets.insert(tms.id.clone(), tms);
+            }
+            Err(e) => warn!("Invalid tile matrix set {}: {}", path.display(), e),
+        }
+    }
+
+    sets
+});
+
+
+pub fn get_tile_matrix_set(id: &str) -> Option<&'static TileMatrixSet> {
+    TILE_MATRIX_SETS.get(id)
+}
+
+pub fn tile_matrix_sets() -> impl Iterator<Item = &'static TileMatrixSet> {
+    TILE_MATRIX_SETS.values()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(tms: &TileMatrixSet, z: u32, x: u32, y: u32) -> [f64; 4] {
+        let b = tms.tile_bbox(z, x, y).unwrap();
+        [b.minx, b.miny, b.maxx, b.maxy]
+    }
+
+    // Satu tile matrix 4 x 3 tile @ 2560 unit; pointOfOrigin mengikuti urutan axis CRS
+    fn custom(crs: &str, axes: [&str; 2], corner: &str, origin: [f64; 2]) -> TileMatrixSet {
+        serde_json::from_value(serde_json::json!({
+            "id": "Custom",
+            "crs": crs,
+            "orderedAxes": axes,
+            "tileMatrices": [{
+                "id": "0",
+                "scaleDenominator": 1.0,
+                "cellSize": 10.0,
+                "cornerOfOrigin": corner,
+                "pointOfOrigin": origin,
+                "tileWidth": 256,
+                "tileHeight": 256,
+                "matrixWidth": 4,
+                "matrixHeight": 3
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn world_crs84_quad_has_two_columns_at_zoom_zero() {
+        let tms = world_crs84_quad();
+        assert_eq!(tms.srid(), Some(4326));
+        assert_eq!(tms.max_zoom(), MAX_SUPPORTED_ZOOM);
+        assert!(tms.validate_tile(0, 1, 0).is_ok());
+        assert!(matches!(tms.validate_tile(0, 2, 0), Err(TileError::OutOfRange { width: 2, height: 1, .. })));
+        assert!(matches!(tms.validate_tile(0, 0, 1), Err(TileError::OutOfRange { .. })));
+        assert!(tms.validate_tile(MAX_SUPPORTED_ZOOM, (1 << 31) - 1, (1 << 30) - 1).is_ok());
+        assert!(matches!(
+            tms.validate_tile(MAX_SUPPORTED_ZOOM + 1, 0, 0),
+            Err(TileError::ZoomTooLarge { max: MAX_SUPPORTED_ZOOM, .. })
+        ));
+    }
+
+    #[test]
+    fn world_crs84_quad_tile_bbox() {
+        let tms = world_crs84_quad();
+        assert_eq!(bbox(&tms, 0, 0, 0), [-180.0, -90.0, 0.0, 90.0]);
+        assert_eq!(bbox(&tms, 0, 1, 0), [0.0, -90.0, 180.0, 90.0]);
+        assert_eq!(bbox(&tms, 1, 3, 1), [90.0, -90.0, 180.0, 0.0]);
+        assert!(tms.tile_bbox(MAX_SUPPORTED_ZOOM + 1, 0, 0).is_none());
+    }
+
+    #[test]
+    fn custom_grid_validates_matrix_size() {
+        let tms = custom("http://www.opengis.net/def/crs/EPSG/0/25832", ["E", "N"], "topLeft", [0.0, 7680.0]);
+        assert_eq!(tms.srid(), Some(25832));
+        assert!(tms.validate_tile(0, 3, 2).is_ok());
+        assert!(matches!(tms.validate_tile(0, 4, 0), Err(TileError::OutOfRange { width: 4, height: 3, .. })));
+        assert!(matches!(tms.validate_tile(0, 0, 3), Err(TileError::OutOfRange { .. })));
+        assert!(matches!(tms.validate_tile(1, 0, 0), Err(TileError::ZoomTooLarge { max: 0, .. })));
+    }
+
+    #[test]
+    fn custom_grid_top_left() {
+        let tms = custom("http://www.opengis.net/def/crs/EPSG/0/25832", ["E", "N"], "topLeft", [0.0, 7680.0]);
+        assert_eq!(bbox(&tms, 0, 0, 0), [0.0, 5120.0, 2560.0, 7680.0]);
+        assert_eq!(bbox(&tms, 0, 1, 2), [2560.0, 0.0, 5120.0, 2560.0]);
+    }
+
+    #[test]
+    fn custom_grid_bottom_left_counts_rows_up() {
+        let tms = custom("http://www.opengis.net/def/crs/EPSG/0/25832", ["E", "N"], "bottomLeft", [0.0, 0.0]);
+        assert_eq!(bbox(&tms, 0, 0, 0), [0.0, 0.0, 2560.0, 2560.0]);
+        assert_eq!(bbox(&tms, 0, 1, 2), [2560.0, 5120.0, 5120.0, 7680.0]);
+    }
+
+    #[test]
+    fn northing_first_origin_is_swapped() {
+        // EPSG:4326 menulis pointOfOrigin sebagai [lat, lon]
+        let tms = custom("http://www.opengis.net/def/crs/EPSG/0/4326", ["Lat", "Lon"], "topLeft", [90.0, -180.0]);
+        assert_eq!(bbox(&tms, 0, 0, 0), [-180.0, 90.0 - 2560.0, -180.0 + 2560.0, 90.0]);
+
+        // EPSG:3857 selalu x/y, walau orderedAxes menyebut northing dulu
+        let tms = custom("http://www.opengis.net/def/crs/EPSG/0/3857", ["N", "E"], "topLeft", [0.0, 7680.0]);
+        assert_eq!(bbox(&tms, 0, 0, 0), [0.0, 5120.0, 2560.0, 7680.0]);
+    }
+}
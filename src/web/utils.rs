@@ -1,6 +1,10 @@
 use std::f64::consts::PI;
 use sqlx::PgPool;
-use sqlx::Row;
+use sha2::{Digest, Sha256};
+use super::prep::{self, PrepMode};
+use super::privileges;
+use super::backfill::BackfillConfig;
+use super::validity;
 
 
 pub struct BBox {
@@ -11,26 +15,89 @@ pub struct BBox {
 }
 
 
-pub fn tile_to_bbox(z: u32, x: u32, y: u32) -> BBox {
-    let n = 2u32.pow(z) as f64;
-    
-    // Longitude dalam derajat
-    let lon_deg_min = x as f64 / n * 360.0 - 180.0;
-    let lon_deg_max = (x + 1) as f64 / n * 360.0 - 180.0;
-    
-    // Latitude dalam radian (inverse Web Mercator tile formula)
-    let lat_rad_min = ((PI * (1.0 - 2.0 * (y + 1) as f64 / n)).sinh()).atan();
-    let lat_rad_max = ((PI * (1.0 - 2.0 * y as f64 / n)).sinh()).atan();
-    
-    // Konversi ke Web Mercator (EPSG:3857)
+impl BBox {
+    /// Perluas bbox dengan rasio tertentu dari lebar/tinggi (mis. buffer MVT 256/4096)
+    pub fn expand(&self, ratio: f64) -> BBox {
+        let dx = (self.maxx - self.minx) * ratio;
+        let dy = (self.maxy - self.miny) * ratio;
+        BBox {
+            minx: self.minx - dx,
+            miny: self.miny - dy,
+            maxx: self.maxx + dx,
+            maxy: self.maxy + dy,
+        }
+    }
+
+    pub fn intersects(&self, other: &[f64; 4]) -> bool {
+        self.minx <= other[2] && self.maxx >= other[0] && self.miny <= other[3] && self.maxy >= other[1]
+    }
+}
+
+
+#[derive(Debug)]
+pub enum TileError {
+    ZoomTooLarge { z: u32, max: u32 },
+    OutOfRange { z: u32, x: u32, y: u32, width: u64, height: u64 },
+}
+
+impl std::fmt::Display for TileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TileError::ZoomTooLarge { z, max } => write!(f, "Zoom level {} is not supported (max {})", z, max),
+            TileError::OutOfRange { z, x, y, width, height } => write!(
+                f,
+                "Tile {}/{}/{} is outside the tile matrix (x must be < {}, y must be < {})",
+                z, x, y, width, height
+            ),
+        }
+    }
+}
+
+/// Quote identifier Postgres (setara quote_ident / format('%I')), untuk nama kolom & tabel di SQL dinamis
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quote literal string Postgres (setara quote_literal / format('%L'))
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Panjang maksimum identifier Postgres (NAMEDATALEN - 1, dalam byte)
+pub const MAX_IDENTIFIER_LEN: usize = 63;
+
+/// Nama objek yang muat di NAMEDATALEN. Postgres memotong nama yang lebih panjang tanpa error,
+/// sehingga dua nama panjang bisa menjadi sama; nama seperti itu dipotong lalu diberi akhiran
+/// 8 karakter hex dari sha256 nama lengkapnya.
+pub fn pg_identifier(name: &str) -> String {
+    if name.len() <= MAX_IDENTIFIER_LEN {
+        return name.to_string();
+    }
+    let hash = hex::encode(Sha256::digest(name.as_bytes()));
+    let mut end = MAX_IDENTIFIER_LEN - 9;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}_{}", &name[..end], &hash[..8])
+}
+
+/// CREATE SCHEMA hanya jika schema belum ada. `CREATE SCHEMA IF NOT EXISTS` tetap menuntut hak
+/// CREATE di database walaupun schema sudah ada, sehingga role yang hanya punya CREATE di
+/// schema itu akan ditolak.
+pub fn create_schema_sql(schema: &str) -> String {
+    format!(
+        "DO $create_schema$ BEGIN IF to_regnamespace({}) IS NULL THEN CREATE SCHEMA IF NOT EXISTS {}; END IF; END $create_schema$",
+        quote_literal(&quote_ident(schema)),
+        quote_ident(schema)
+    )
+}
+
+/// Konversi bbox EPSG:3857 ke lon/lat (EPSG:4326)
+pub fn bbox_3857_to_4326(bbox: &[f64; 4]) -> [f64; 4] {
     const R: f64 = 6378137.0;
-    
-    let minx = R * lon_deg_min.to_radians();
-    let maxx = R * lon_deg_max.to_radians();
-    let miny = R * ((PI / 4.0 + lat_rad_min / 2.0).tan().ln());
-    let maxy = R * ((PI / 4.0 + lat_rad_max / 2.0).tan().ln());
-    
-    BBox { minx, miny, maxx, maxy }
+    let lon = |x: f64| (x / R).to_degrees();
+    let lat = |y: f64| (2.0 * (y / R).exp().atan() - PI / 2.0).to_degrees();
+    [lon(bbox[0]), lat(bbox[1]), lon(bbox[2]), lat(bbox[3])]
 }
 
 
@@ -94,117 +161,65 @@ pub fn tile_to_bbox(z: u32, x: u32, y: u32) -> BBox {
 // }
 
 
-/// Check geom column dan GiST index, buat kolom geom_3857 dan index jika belum ada
-pub async fn check_and_create_geom_index(db_pool: &PgPool) -> Result<(), sqlx::Error> {
+/// Siapkan geometri 3857 setiap layer sesuai mode `prep` (lihat web::prep).
+/// Mode column membuat kolom geom_3857 + GiST index di tabel sumber seperti sebelumnya;
+/// backfill berjalan per batch key dan bisa dilanjutkan (lihat web::backfill).
+/// Layer yang tidak boleh disiapkan oleh role koneksi dilewati (lihat web::privileges).
+pub async fn check_and_create_geom_index(db_pool: &PgPool, config: &BackfillConfig) -> Result<(), sqlx::Error> {
     println!("\n📊 Checking geom column status...\n");
 
-    // Ambil semua table dari geometry_columns
-    let rows = sqlx::query(
-        r#"
-        SELECT 
-            gc.f_table_schema,
-            gc.f_table_name,
-            gc.f_geometry_column AS geom_col,
-            gc.type AS geom_type,
-            gc.srid
-        FROM public.geometry_columns gc
-        JOIN pg_class c
-            ON c.relname = gc.f_table_name
-        JOIN pg_namespace n
-            ON n.oid = c.relnamespace
-            AND n.nspname = gc.f_table_schema
-        WHERE c.relkind IN ('r','m')
-        ORDER BY gc.f_table_schema, gc.f_table_name
-        "#
-    )
-    .fetch_all(db_pool)
-    .await?;
-
-    println!("{:<30} {:<15} {:<15} {:<15} {:<10}", "Table", "Geom Column", "Geom Type", "geom_3857", "Index");
-    println!("{}", "─".repeat(90));
-
-    for row in rows {
-        let schema: String = row.try_get("f_table_schema")?;
-        let table: String = row.try_get("f_table_name")?;
-        let geom_col: String = row.try_get("geom_col")?;
-        let geom_type: String = row.try_get("geom_type")?;
-        let srid: i32 = row.try_get("srid")?;
-
-        let table_name = format!("{}.{}", schema, table);
-
-        // 🔹 Cek apakah kolom geom_3857 sudah ada
-        let check_col = sqlx::query(
-            r#"
-            SELECT column_name 
-            FROM information_schema.columns 
-            WHERE table_schema = $1 
-            AND table_name = $2 
-            AND column_name = 'geom_3857'
-            "#
-        )
-        .bind(&schema)
-        .bind(&table)
-        .fetch_optional(db_pool)
-        .await?;
-
-        let has_geom_3857 = check_col.is_some();
-        let geom_3857_status = if has_geom_3857 { "YES" } else { "NO" };
-
-        // 🔹 Jika kolom geom_3857 belum ada → buat kolom baru dan transform dari kolom asli
-        if !has_geom_3857 {
-            println!("{:<30} {:<15} {:<15} {:<15} {:<10}", table_name, geom_col, geom_type, "CREATING...", "-");
-            
-            // Tambah kolom geom_3857 dengan tipe geometri 2D saja
-            let add_col_sql = format!(
-                "ALTER TABLE {}.{} ADD COLUMN geom_3857 geometry({}, 3857)",
-                schema, table, geom_type
-            );
-            sqlx::query(&add_col_sql).execute(db_pool).await?;
-            println!("   ✅ Created column: geom_3857 ({}, 3857)", geom_type);
-
-            
-        }
+    let capabilities = privileges::detect(db_pool).await?;
+    let sources = prep::layer_sources(db_pool).await?;
 
-        // Transform dan isi data dari kolom asli ke geom_3857 (force 2D dengan ST_Force2D)
-        let transform_sql = format!(
-                "UPDATE {}.{} SET geom_3857 = ST_Transform(ST_Force2D({}), 3857) WHERE geom_3857 IS NULL",
-                schema, table, geom_col
-            );
-        sqlx::query(&transform_sql).execute(db_pool).await?;
-            println!("   ✅ Transformed data from SRID {} to 3857 (forced to 2D)", srid);
-
-        // 🔹 Cek apakah index sudah ada
-        let idx_name = format!("idx_{}_geom_3857_gist", table);
-        let check_idx = sqlx::query(
-            r#"
-            SELECT indexname 
-            FROM pg_indexes 
-            WHERE schemaname = $1 
-            AND tablename = $2 
-            AND indexname = $3
-            "#
-        )
-        .bind(&schema)
-        .bind(&table)
-        .bind(&idx_name)
-        .fetch_optional(db_pool)
-        .await?;
+    println!("{:<30} {:<15} {:<15} {:<12} {:<10}", "Table", "Geom Column", "Geom Type", "Mode", "SRID");
+    println!("{}", "─".repeat(85));
 
-        let has_index = check_idx.is_some();
-        let index_status = if has_index { "YES" } else { "NO" };
+    for source in sources {
+        let mode = prep::layer_prep_mode(&source.id);
+        let table_name = format!("{}.{}", source.schema, source.id);
+        println!(
+            "{:<30} {:<15} {:<15} {:<12} {:<10}",
+            table_name, source.geom_column, source.geom_type, mode, source.srid
+        );
 
-        if has_geom_3857 {
-            println!("{:<30} {:<15} {:<15} {:<15} {:<10}", table_name, geom_col, geom_type, geom_3857_status, index_status);
+        // View dan relasi lain tidak bisa di-ALTER / di-index
+        if !matches!(source.relkind.as_deref(), Some("r") | Some("m")) {
+            continue;
+        }
+        // Tabel yang tidak bisa dipublikasikan tidak perlu disiapkan
+        if let Some(problem) = source.problem() {
+            println!("   ❌ {}", problem);
+            continue;
+        }
+        // Mode read-only: tidak ada DDL sama sekali; layer tetap dilayani dari yang sudah ada
+        let writes = mode != PrepMode::ReadOnly && source.geom_column != prep::GEOM_3857;
+        let denied = match privileges::prepare_denied(db_pool, &capabilities, &source, mode).await? {
+            Some(reason) => Some(reason),
+            None if writes && capabilities.read_only() => Some("server runs in read-only mode".to_string()),
+            None => None,
+        };
+        if let Some(reason) = denied {
+            println!("   ⚠️  Preparation skipped: {}", reason);
+            continue;
+        }
+        if let Some(reason) = validity::make_valid_blocked(db_pool, &source, mode).await? {
+            println!("   ⚠️  Preparation skipped: {}", reason);
+            continue;
         }
 
-        // 🔹 Jika index belum ada → buat GiST index pada geom_3857
-        if !has_index {
-            let sql = format!(
-                "CREATE INDEX CONCURRENTLY IF NOT EXISTS {} ON {}.{} USING GIST(geom_3857)",
-                idx_name, schema, table
+        if let Err(e) = prep::prepare(db_pool, &source, mode, config).await {
+            println!("   ❌ Failed to prepare {}: {}", table_name, e);
+            continue;
+        }
+        if validity::validate_enabled() && writes && !capabilities.can_write_prep_schema() {
+            println!(
+                "   ⚠️  Validity check skipped: role {} cannot create objects in schema {}",
+                capabilities.role, prep::prep_schema()
             );
-            sqlx::query(&sql).execute(db_pool).await?;
-            println!("   ✅ Created GiST index: {}.{}", schema, idx_name);
+        } else if validity::validate_enabled()
+            && let Err(e) = validity::check_layer(db_pool, &source, mode, config).await
+        {
+            println!("   ❌ Failed to check geometry validity of {}: {}", table_name, e);
         }
     }
 
@@ -250,3 +265,32 @@ pub async fn create_index(
 
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pg_identifier_keeps_short_names() {
+        let name = "a".repeat(MAX_IDENTIFIER_LEN);
+        assert_eq!(pg_identifier(&name), name);
+    }
+
+    #[test]
+    fn pg_identifier_hashes_long_names() {
+        let a = pg_identifier(&format!("sync_{}_a", "x".repeat(70)));
+        let b = pg_identifier(&format!("sync_{}_b", "x".repeat(70)));
+        assert_eq!(a.len(), MAX_IDENTIFIER_LEN);
+        assert_ne!(a, b);
+        assert!(a.starts_with("sync_xxx"));
+    }
+
+    #[test]
+    fn pg_identifier_respects_char_boundaries() {
+        let name = format!("{}é{}", "x".repeat(53), "y".repeat(20));
+        let short = pg_identifier(&name);
+        assert!(short.len() <= MAX_IDENTIFIER_LEN);
+        assert!(short.starts_with(&"x".repeat(53)));
+    }
+}
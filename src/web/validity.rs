@@ -0,0 +1,374 @@
+// Geometri invalid (lihat web::prep). ST_AsMVTGeom membuang atau merusak polygon invalid tanpa
+// pesan apa pun, sehingga tile berlubang. Setiap run persiapan memeriksa kolom sumber dengan
+// ST_IsValidDetail dan mencatat feature yang invalid di {PREP_SCHEMA}.invalid_geometries
+// (diganti per layer setiap run). Laporan: GET /layers/invalid dan /layers/{layer}/invalid.
+//
+// Dengan `make_valid` (layers.json, default env GEOM_MAKE_VALID=false) geom_3857 berisi hasil
+// ST_MakeValid; baris lama yang sudah tersimpan diperbarui di sini.
+//
+//   GEOM_VALIDATE   periksa validitas saat persiapan (default true)
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use super::feature_id::primary_key;
+use super::prep::{self, LayerSource, PrepMode, GEOM_3857};
+use super::privileges::can_select;
+use super::backfill::{Backfill, BackfillConfig};
+use super::utils::{create_schema_sql, quote_ident, quote_literal};
+use super::web_handler::{ensure_layers_loaded, get_layer_detail, json_error, layer_error};
+
+// Jumlah contoh key per layer di output persiapan
+const SAMPLE_KEYS: i64 = 5;
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+
+pub fn validate_enabled() -> bool {
+    std::env::var("GEOM_VALIDATE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(true)
+}
+
+
+pub(crate) fn report_table_name(prep_schema: &str) -> String {
+    format!("{}.invalid_geometries", quote_ident(prep_schema))
+}
+
+
+/// Backfill yang mencatat feature invalid layer ke tabel laporan, per batch key (lihat
+/// web::backfill). Feature yang sudah tercatat diperbarui, jadi run yang terputus bisa dilanjutkan.
+pub(crate) fn detect_backfill(source: &LayerSource, pk: &[String]) -> Backfill {
+    Backfill {
+        prep_schema: prep::prep_schema(),
+        mode: "validate",
+        verb: "Checked",
+        layer: source.id.clone(),
+        schema: source.schema.clone(),
+        table: source.table.clone(),
+        statement: format!(
+            "INSERT INTO {report} (layer, feature_key, reason, location, repaired) \
+             SELECT {layer}, {key}, d.reason, ST_AsText(d.location), {repaired} \
+             FROM {table} t CROSS JOIN LATERAL ST_IsValidDetail({geom}) d \
+             WHERE {{range}} AND NOT d.valid \
+             ON CONFLICT (layer, feature_key) DO UPDATE SET reason = EXCLUDED.reason, \
+             location = EXCLUDED.location, repaired = EXCLUDED.repaired, checked_at = now()",
+            report = report_table_name(&prep::prep_schema()),
+            layer = quote_literal(&source.id),
+            key = prep::row_key(pk),
+            repaired = source.make_valid(),
+            table = source.qualified(),
+            geom = source.linear_geom("t"),
+        ),
+    }
+}
+
+
+// Kolom geom_3857 bertipe spesifik (mis. geometry(Polygon,3857)) tidak bisa menampung hasil
+// ST_MakeValid yang berupa MULTIPOLYGON. None jika kolom sudah generik atau belum ada.
+async fn generic_type_sql(db_pool: &PgPool, relation: &str, column: &str) -> Result<Option<String>, sqlx::Error> {
+    let column_type: Option<String> = sqlx::query(
+        "SELECT format_type(a.atttypid, a.atttypmod) AS type FROM pg_attribute a \
+         WHERE a.attrelid = to_regclass($1) AND a.attname = $2 AND NOT a.attisdropped"
+    )
+    .bind(relation)
+    .bind(column)
+    .fetch_optional(db_pool)
+    .await?
+    .map(|row| row.try_get("type"))
+    .transpose()?;
+
+    Ok(match column_type {
+        Some(t) if t.starts_with("geometry(") && !t.starts_with("geometry(Geometry,") => Some(format!(
+            "ALTER TABLE {} ALTER COLUMN {} TYPE geometry(Geometry, 3857)",
+            relation,
+            quote_ident(column)
+        )),
+        _ => None,
+    })
+}
+
+
+/// Alasan layer dengan make_valid tidak bisa disiapkan: kolom tersimpan dibuat sebelum
+/// make_valid aktif dan bertipe spesifik. Kolom baru sudah generik (LayerSource::column_type);
+/// tipe kolom lama tidak pernah diubah otomatis karena menulis ulang seluruh tabel di bawah
+/// ACCESS EXCLUSIVE lock.
+pub(crate) async fn make_valid_blocked(db_pool: &PgPool, source: &LayerSource, mode: PrepMode) -> Result<Option<String>, sqlx::Error> {
+    if !source.make_valid() || source.geom_column == GEOM_3857 {
+        return Ok(None);
+    }
+    let (relation, column) = match mode {
+        PrepMode::Column => (source.qualified(), source.prepared_column.clone()),
+        PrepMode::Shadow => (source.shadow_table(), GEOM_3857.to_string()),
+        _ => return Ok(None),
+    };
+    Ok(generic_type_sql(db_pool, &relation, &column).await?.map(|sql| {
+        format!(
+            "make_valid needs {} to be geometry(Geometry, 3857); this rewrites the table, run it in a maintenance window: {}",
+            column, sql
+        )
+    }))
+}
+
+
+/// DDL tabel laporan (juga ditampilkan oleh `prepare --dry-run`)
+pub(crate) fn report_table_sql(prep_schema: &str) -> String {
+    format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS {} (
+            layer text NOT NULL,
+            feature_key text NOT NULL,
+            reason text,
+            location text,
+            repaired boolean NOT NULL DEFAULT false,
+            checked_at timestamptz NOT NULL DEFAULT now(),
+            PRIMARY KEY (layer, feature_key)
+        )
+        "#,
+        report_table_name(prep_schema)
+    )
+}
+
+
+/// Hapus hasil run sebelumnya satu layer ($1 = id layer) sebelum run baru
+pub(crate) fn clear_report_sql() -> String {
+    format!("DELETE FROM {} WHERE layer = $1", report_table_name(&prep::prep_schema()))
+}
+
+
+async fn ensure_report_table(db_pool: &PgPool) -> Result<(), sqlx::Error> {
+    let schema = prep::prep_schema();
+    sqlx::query(&create_schema_sql(&schema))
+        .execute(db_pool)
+        .await?;
+    sqlx::query(&report_table_sql(&schema))
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+
+/// Backfill yang menulis ulang geom_3857 tersimpan untuk baris invalid (data lama sebelum
+/// make_valid aktif), per batch key. None untuk mode tanpa data tersimpan.
+pub(crate) fn repair_backfill(source: &LayerSource, mode: PrepMode, pk: &[String]) -> Option<Backfill> {
+    let invalid = format!("NOT ST_IsValid({})", source.linear_geom("t"));
+    let statement = match mode {
+        PrepMode::Column => format!(
+            "UPDATE {table} t SET {col} = {geom} WHERE {{range}} AND {invalid} AND t.{col} IS DISTINCT FROM {geom}",
+            table = source.qualified(),
+            col = quote_ident(&source.prepared_column),
+            geom = source.transform("t"),
+        ),
+        PrepMode::Shadow if !pk.is_empty() => format!(
+            "UPDATE {shadow} s SET {g} = {geom} FROM {table} t \
+             WHERE {{range}} AND {pk_match} AND {invalid} AND s.{g} IS DISTINCT FROM {geom}",
+            shadow = source.shadow_table(),
+            g = GEOM_3857,
+            geom = source.transform("t"),
+            table = source.qualified(),
+            pk_match = prep::key_match(pk, "s", "t"),
+        ),
+        _ => return None,
+    };
+    Some(Backfill {
+        prep_schema: prep::prep_schema(),
+        mode: "repair",
+        verb: "Repaired",
+        layer: source.id.clone(),
+        schema: source.schema.clone(),
+        table: source.table.clone(),
+        statement,
+    })
+}
+
+
+/// Periksa validitas geometri satu layer setelah persiapan, catat hasilnya, dan perbaiki
+/// geom_3857 tersimpan jika make_valid aktif. Keduanya berjalan per batch seperti backfill.
+/// Mode none tidak menulis apa pun.
+pub async fn check_layer(db_pool: &PgPool, source: &LayerSource, mode: PrepMode, config: &BackfillConfig) -> Result<(), sqlx::Error> {
+    if mode == PrepMode::ReadOnly || source.geom_column == GEOM_3857 {
+        return Ok(());
+    }
+    ensure_report_table(db_pool).await?;
+    let pk = primary_key(db_pool, &source.schema, &source.table).await?;
+    let table = report_table_name(&prep::prep_schema());
+    let repaired = source.make_valid();
+
+    // Run baru (bukan lanjutan run yang terputus): hasil run sebelumnya dibuang dulu
+    let detect = detect_backfill(source, &pk);
+    if config.restart || !detect.is_pending(db_pool).await? {
+        sqlx::query(&clear_report_sql())
+            .bind(&source.id)
+            .execute(db_pool)
+            .await?;
+    }
+    detect.run(db_pool, config).await?;
+
+    let invalid: i64 = sqlx::query(&format!("SELECT count(*) AS invalid FROM {} WHERE layer = $1", table))
+        .bind(&source.id)
+        .fetch_one(db_pool)
+        .await?
+        .try_get("invalid")?;
+    if invalid == 0 {
+        println!("   ✅ All geometries are valid");
+        return Ok(());
+    }
+
+    let samples: Vec<String> = sqlx::query(&format!(
+        "SELECT feature_key FROM {} WHERE layer = $1 ORDER BY feature_key LIMIT {}",
+        table, SAMPLE_KEYS
+    ))
+    .bind(&source.id)
+    .fetch_all(db_pool)
+    .await?
+    .iter()
+    .map(|row| row.try_get("feature_key"))
+    .collect::<Result<_, _>>()?;
+    println!("   ⚠️  {} invalid geometries (e.g. {})", invalid, samples.join("; "));
+
+    if !repaired {
+        println!("      Set \"make_valid\": true for {} to repair them", source.id);
+    } else if let Some(repair) = repair_backfill(source, mode, &pk) {
+        let updated = repair.run(db_pool, config).await?;
+        println!("   ✅ Repaired with ST_MakeValid ({} stored rows updated)", updated);
+    }
+    Ok(())
+}
+
+
+// ---------- Laporan ----------
+
+#[derive(Serialize)]
+struct InvalidFeature {
+    key: String,
+    reason: Option<String>,
+    location: Option<String>,
+    repaired: bool,
+    checked_at: String,
+}
+
+
+#[derive(Deserialize)]
+pub struct ReportQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+
+async fn report_exists(db_pool: &PgPool) -> Result<bool, sqlx::Error> {
+    can_select(db_pool, &report_table_name(&prep::prep_schema())).await
+}
+
+
+/// GET /layers/invalid: jumlah geometri invalid per layer dari persiapan terakhir
+pub async fn invalid_summary(db_pool: web::Data<PgPool>) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let result = async {
+        if !report_exists(db_pool).await? {
+            return Ok(Vec::new());
+        }
+        sqlx::query(&format!(
+            "SELECT layer, count(*) AS invalid, bool_and(repaired) AS repaired, max(checked_at)::text AS checked_at \
+             FROM {} GROUP BY layer ORDER BY layer",
+            report_table_name(&prep::prep_schema())
+        ))
+        .fetch_all(db_pool)
+        .await?
+        .iter()
+        .map(|row| -> Result<_, sqlx::Error> {
+            Ok(json!({
+                "layer": row.try_get::<String, _>("layer")?,
+                "invalid": row.try_get::<i64, _>("invalid")?,
+                "repaired": row.try_get::<bool, _>("repaired")?,
+                "checked_at": row.try_get::<String, _>("checked_at")?,
+            }))
+        })
+        .collect::<Result<Vec<_>, _>>()
+    }
+    .await;
+
+    match result {
+        Ok(layers) => HttpResponse::Ok()
+            .insert_header(("Access-Control-Allow-Origin", "*"))
+            .json(json!({ "layers": layers })),
+        Err(e) => {
+            error!("Failed to read invalid geometry report: {:?}", e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read invalid geometry report")
+        }
+    }
+}
+
+
+/// GET /layers/{layer}/invalid?limit=&offset=: feature invalid satu layer, urut key
+pub async fn invalid_features(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    query: web::Query<ReportQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let table_name = path.into_inner();
+    let db_pool = db_pool.get_ref();
+    ensure_layers_loaded(db_pool, &req).await;
+    if get_layer_detail(table_name.clone()).await.is_none() {
+        let message = layer_error(&table_name)
+            .await
+            .unwrap_or_else(|| format!("Layer not found: {}", table_name));
+        return json_error(StatusCode::NOT_FOUND, message);
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let result = async {
+        if !report_exists(db_pool).await? {
+            return Ok((0, Vec::new()));
+        }
+        let table = report_table_name(&prep::prep_schema());
+        let total: i64 = sqlx::query(&format!("SELECT count(*) AS total FROM {} WHERE layer = $1", table))
+            .bind(&table_name)
+            .fetch_one(db_pool)
+            .await?
+            .try_get("total")?;
+        let features = sqlx::query(&format!(
+            "SELECT feature_key, reason, location, repaired, checked_at::text AS checked_at \
+             FROM {} WHERE layer = $1 ORDER BY feature_key LIMIT $2 OFFSET $3",
+            table
+        ))
+        .bind(&table_name)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(db_pool)
+        .await?
+        .iter()
+        .map(|row| -> Result<_, sqlx::Error> {
+            Ok(InvalidFeature {
+                key: row.try_get("feature_key")?,
+                reason: row.try_get("reason")?,
+                location: row.try_get("location")?,
+                repaired: row.try_get("repaired")?,
+                checked_at: row.try_get("checked_at")?,
+            })
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+        Ok::<_, sqlx::Error>((total, features))
+    }
+    .await;
+
+    match result {
+        Ok((total, features)) => HttpResponse::Ok()
+            .insert_header(("Access-Control-Allow-Origin", "*"))
+            .json(json!({
+                "layer": table_name,
+                "invalid": total,
+                "limit": limit,
+                "offset": offset,
+                "features": features,
+            })),
+        Err(e) => {
+            error!("Failed to read invalid geometry report: {:?}", e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read invalid geometry report")
+        }
+    }
+}
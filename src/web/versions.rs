@@ -0,0 +1,197 @@
+// Token versi per layer untuk cache key (memory + S3).
+//
+// Token = hash dari (epoch manual, counter perubahan data, fingerprint konfigurasi).
+// Begitu salah satu berubah, semua tile lama otomatis tidak terjangkau oleh key baru,
+// lalu prefix S3 versi lama dihapus di background.
+use std::collections::HashMap;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+use sqlx::{PgPool, Row};
+use sha2::{Digest, Sha256};
+use log::{info, warn, error};
+use super::cache::{S3Config, invalidate_layer_memory, list_layer_versions, purge_layer};
+use super::db::service_object;
+use super::tile_query::TILE_QUERY_FINGERPRINT;
+use super::config::layer_config;
+use super::prep::{layer_sources, prep_schema};
+use super::privileges::can_select;
+
+// Versi untuk layer yang belum pernah di-refresh
+pub const UNVERSIONED: &str = "0";
+
+static LAYER_VERSIONS: Lazy<RwLock<HashMap<String, String>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+
+pub async fn current_version(table_name: &str) -> String {
+    LAYER_VERSIONS
+        .read()
+        .await
+        .get(table_name)
+        .cloned()
+        .unwrap_or_else(|| UNVERSIONED.to_string())
+}
+
+
+fn version_token(epoch: i64, data_changes: i64, config_fingerprint: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(epoch.to_le_bytes());
+    hasher.update(data_changes.to_le_bytes());
+    hasher.update(config_fingerprint.as_bytes());
+    hex::encode(&hasher.finalize()[..6])
+}
+
+
+/// Hitung ulang versi semua layer. Mengembalikan layer yang versinya berubah
+/// beserta versi lamanya (None untuk layer yang baru pertama kali terlihat).
+pub async fn refresh_versions(pool: &PgPool) -> Result<Vec<(String, Option<String>, String)>, sqlx::Error> {
+    let sources = layer_sources(pool).await?;
+
+    // Counter n_tup_* bertambah setiap INSERT/UPDATE/DELETE pada tabel sumber,
+    // ditambah tabel bayangan untuk layer mode shadow (lihat web::prep)
+    let mut schemas: Vec<String> = sources.iter().map(|s| s.schema.clone()).collect();
+    schemas.push(prep_schema());
+    let data_changes: HashMap<(String, String), i64> = sqlx::query(
+        r#"
+        SELECT schemaname::text AS schema_name, relname::text AS table_name,
+            (n_tup_ins + n_tup_upd + n_tup_del)::bigint AS data_changes
+        FROM pg_stat_all_tables
+        WHERE schemaname = ANY($1)
+        "#
+    )
+    .bind(&schemas)
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|r| Ok(((r.try_get("schema_name")?, r.try_get("table_name")?), r.try_get("data_changes")?)))
+    .collect::<Result<_, sqlx::Error>>()?;
+
+    // Mode read-only: tabel epoch mungkin belum dimigrasi atau tidak di-GRANT; epoch dianggap 0
+    let versions_table = service_object("tile_layer_versions");
+    let epochs: HashMap<String, i64> = if can_select(pool, &versions_table).await? {
+        sqlx::query(&format!("SELECT layer, epoch FROM {}", versions_table))
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(|r| Ok((r.try_get("layer")?, r.try_get("epoch")?)))
+            .collect::<Result<_, sqlx::Error>>()?
+    } else {
+        HashMap::new()
+    };
+
+    let mut changed = Vec::new();
+    let mut versions = LAYER_VERSIONS.write().await;
+
+    for source in sources {
+        let changes = |schema: &str, table: &str| {
+            data_changes.get(&(schema.to_string(), table.to_string())).copied().unwrap_or(0)
+        };
+        let data_changes = changes(&source.schema, &source.table) + changes(&prep_schema(), &source.prep_name());
+        let epoch = epochs.get(&source.id).copied().unwrap_or(0);
+
+        let config_fingerprint = format!(
+            "{}|{}|{}|{}|{}|{}",
+            source.schema, source.id, source.geom_type, source.srid,
+            layer_config(&source.id).fingerprint(), *TILE_QUERY_FINGERPRINT
+        );
+        let token = version_token(epoch, data_changes, &config_fingerprint);
+
+        let previous = versions.insert(source.id.clone(), token.clone());
+        if previous.as_ref() != Some(&token) {
+            changed.push((source.id, previous, token));
+        }
+    }
+
+    Ok(changed)
+}
+
+
+/// Naikkan epoch manual layer (invalidasi global atas permintaan)
+pub async fn bump_version(pool: &PgPool, table_name: &str) -> Result<String, sqlx::Error> {
+    sqlx::query(&format!(
+        r#"
+        INSERT INTO {} (layer, epoch)
+        VALUES ($1, 1)
+        ON CONFLICT (layer)
+        DO UPDATE SET epoch = tile_layer_versions.epoch + 1, updated_at = now()
+        "#,
+        service_object("tile_layer_versions")
+    ))
+    .bind(table_name)
+    .execute(pool)
+    .await?;
+
+    for (table, previous, token) in refresh_versions(pool).await? {
+        on_version_changed(&table, previous.as_deref(), &token);
+    }
+
+    Ok(current_version(table_name).await)
+}
+
+
+fn on_version_changed(table_name: &str, previous: Option<&str>, token: &str) {
+    match previous {
+        Some(previous) => info!("Layer {} version changed: {} -> {}", table_name, previous, token),
+        None => info!("Layer {} version: {}", table_name, token),
+    }
+
+    invalidate_layer_memory(table_name, Some(token));
+
+    let gc_enabled = std::env::var("S3_GC_OLD_VERSIONS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(true);
+    if gc_enabled {
+        let table_name = table_name.to_string();
+        let token = token.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = collect_old_versions(&table_name, &token).await {
+                warn!("Failed to garbage-collect old versions of {}: {}", table_name, e);
+            }
+        });
+    }
+}
+
+
+// Hapus semua prefix versi S3 selain versi aktif
+async fn collect_old_versions(table_name: &str, keep_version: &str) -> Result<(), String> {
+    let config = S3Config::default();
+    let versions = list_layer_versions(&config, table_name)
+        .await
+        .map_err(|e| e.to_string())?;
+    for version in versions {
+        if version == keep_version {
+            continue;
+        }
+        let deleted = purge_layer(&config, table_name, Some(&version))
+            .await
+            .map_err(|e| e.to_string())?;
+        info!("✓ Garbage-collected {} objects of {}/{}", deleted, table_name, version);
+    }
+    Ok(())
+}
+
+
+/// Loop background: refresh versi setiap LAYER_VERSION_REFRESH_SECONDS
+pub fn spawn_refresh_task(pool: PgPool) {
+    let interval = std::env::var("LAYER_VERSION_REFRESH_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            match refresh_versions(&pool).await {
+                Ok(changed) => {
+                    for (table, previous, token) in changed {
+                        on_version_changed(&table, previous.as_deref(), &token);
+                    }
+                }
+                Err(e) => error!("Failed to refresh layer versions: {:?}", e),
+            }
+        }
+    });
+}
@@ -1,37 +1,83 @@
 use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use actix_web::http::StatusCode;
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 use sqlx::Row;
 use serde::{Serialize, Deserialize};
 use once_cell::sync::Lazy;
 use tokio::sync::RwLock;
-use log::{error, info};
-use super::utils; 
-use super::cache::{S3Config, get_from_memory_cache, get_from_s3, put_to_memory_cache, put_to_s3};
-
-// use vector_tile_services::utils::tile_to_bbox;
+use log::{error, info, warn};
+use super::versions;
+use super::tile_query;
+use super::config;
+use super::tms;
+use super::ogc;
+use super::prep::{self, LayerSource, PrepMode};
+use super::privileges;
+use super::feature_id::{self, FeatureId};
+use super::features::{Column, layer_columns};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use super::cache::{S3Config, TileCacheKey, CircuitState, get_from_memory_cache, get_from_s3, put_to_memory_cache, put_to_s3, s3_status, get_cache_report, flush_memory_cache};
 
 
 #[derive(Serialize, Clone)]
 pub struct Layer {
-    table_name: String,
-    geom_column: String,
-    geom_type: String,
-    srid: i32,
-    bbox: [f64; 4], // [minx, miny, maxx, maxy]
-    url: String,
+    pub(crate) schema: String,
+    pub(crate) table_name: String, // id layer: nama tabel, atau tabel.kolom (lihat web::prep::LayerSource)
+    pub(crate) source_table: String, // tabel sumber di database
+    pub(crate) group: String,
+    pub(crate) geom_column: String, // kolom 3857 di `relation` (selalu geom_3857)
+    pub(crate) source_column: String, // kolom geometri asli di tabel sumber
+    pub(crate) geom_type: String,
+    pub(crate) srid: i32,
+    pub(crate) bbox: [f64; 4], // [minx, miny, maxx, maxy]
+    pub(crate) minzoom: u32,
+    pub(crate) maxzoom: u32,
+    pub(crate) url: String,
+    pub(crate) prep: PrepMode, // mode persiapan geometri yang dipakai (lihat web::prep)
+    #[serde(skip)]
+    pub(crate) relation: String, // relasi SQL: kolom tabel sumber + geom_3857
+    #[serde(skip)]
+    pub(crate) has_ctid: bool, // relasi menyertakan ctid tabel sumber (bukan view / foreign table)
+    #[serde(skip)]
+    pub(crate) tms_bounds: HashMap<String, [f64; 4]>, // bounds layer per tile matrix set id
+    #[serde(skip)]
+    pub(crate) feature_id: FeatureId, // ekspresi id feature MVT
+    #[serde(skip)]
+    pub(crate) properties: Vec<Column>, // kolom atribut yang ikut ke tile
+    #[serde(skip)]
+    pub(crate) tile_queries: HashMap<i32, String>, // query tile per SRID tile matrix set (lihat web::tile_query)
 }
 
 impl Layer {
-    fn new(table_name: String, geom_column: String, geom_type: String, srid: i32, bbox: [f64; 4],base_url:String) -> Self {
+    fn new(source: &LayerSource, bbox: [f64; 4],base_url:String) -> Self {
+        let layer_config = config::layer_config(&source.id);
         let mut layer = Self {
-                table_name: table_name.to_string(), 
-                geom_column: geom_column.to_string(), 
-                geom_type: geom_type.to_string(), 
-                srid: srid, 
-                bbox: bbox,
+                group: layer_config.group.clone().unwrap_or_else(|| source.schema.clone()),
+                schema: source.schema.clone(),
+                table_name: source.id.clone(), 
+                source_table: source.table.clone(),
+                relation: source.qualified(),
+                has_ctid: source.has_ctid(),
+                prep: prep::layer_prep_mode(&source.id),
+                geom_column: prep::GEOM_3857.to_string(),
+                source_column: source.geom_column.clone(),
+                geom_type: source.tile_type(), 
+                srid: source.source_srid().unwrap_or(source.srid), 
+                bbox,
+                minzoom: 0,
+                maxzoom: 0,
                 url: String::new(), 
+                tms_bounds: HashMap::new(),
+                feature_id: FeatureId::default(),
+                properties: Vec::new(),
+                tile_queries: HashMap::new(),
             };
 
+        layer.minzoom = layer_config.minzoom();
+        layer.maxzoom = layer_config.maxzoom();
+
         layer.url = layer.generate_url(base_url);
         layer
 
@@ -53,13 +99,99 @@ pub struct TilePath {
 }
 
 
+#[derive(Deserialize)]
+pub struct TmsTilePath {
+    tms: String,
+    table_name: String,
+    z: u32,
+    x: u32,
+    y: u32,
+}
+
+
 pub static LAYERS_CACHE: Lazy<RwLock<Option<Vec<Layer>>>> =
     Lazy::new(|| RwLock::new(None));
 
 
+/// Tabel yang tidak bisa dipublikasikan pada load_layers terakhir, beserta alasannya
+#[derive(Serialize, Clone)]
+pub struct LayerError {
+    pub layer: String,
+    pub schema: String,
+    pub table: String,
+    pub geom_column: String,
+    pub geom_type: String,
+    pub srid: i32,
+    pub error: String,
+}
+
+static LAYER_ERRORS: Lazy<RwLock<Vec<LayerError>>> =
+    Lazy::new(|| RwLock::new(Vec::new()));
+
+
+// Alasan sebuah tabel geometri tidak dipublikasikan (None jika memang tidak ada)
+pub(crate) async fn layer_error(table_name: &str) -> Option<String> {
+    LAYER_ERRORS
+        .read()
+        .await
+        .iter()
+        .find(|e| e.layer == table_name)
+        .map(|e| format!("Layer {} cannot be published: {}", table_name, e.error))
+}
+
+
+// Response error dalam bentuk JSON: {"error": "...", "status": 400}
+pub fn json_error(status: StatusCode, message: impl Into<String>) -> HttpResponse {
+    HttpResponse::build(status)
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .json(serde_json::json!({
+            "error": message.into(),
+            "status": status.as_u16(),
+        }))
+}
+
+
+// Path tile yang tidak bisa di-parse (mis. z/x/y bukan angka) -> 400 JSON
+pub fn path_error_handler(err: actix_web::error::PathError, _req: &HttpRequest) -> actix_web::Error {
+    let message = format!("Invalid path: {}", err);
+    actix_web::error::InternalError::from_response(err, json_error(StatusCode::BAD_REQUEST, message)).into()
+}
+
+
+// Query string yang tidak bisa di-parse (mis. lon bukan angka) -> 400 JSON
+pub fn query_error_handler(err: actix_web::error::QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let message = format!("Invalid query: {}", err);
+    actix_web::error::InternalError::from_response(err, json_error(StatusCode::BAD_REQUEST, message)).into()
+}
+
+
+// Pastikan LAYERS_CACHE terisi (sebelumnya hanya diisi saat membuka / atau /layers)
+pub(crate) async fn ensure_layers_loaded(pool: &PgPool, req: &HttpRequest) {
+    if LAYERS_CACHE.read().await.is_some() {
+        return;
+    }
+
+    match load_layers(pool, req.clone()).await {
+        Ok(layers) => {
+            let mut cache = LAYERS_CACHE.write().await;
+            if cache.is_none() {
+                *cache = Some(layers);
+                info!("Layers cache loaded on first tile request");
+            }
+        }
+        Err(e) => error!("Failed to load layers cache: {:?}", e),
+    }
+}
+
+
 
 pub async fn index(pool: web::Data<PgPool>, req: HttpRequest) -> HttpResponse {
 
+    // Client OGC API (?f=json atau Accept: application/json) mendapat landing page JSON
+    if ogc::wants_json(&req) {
+        return ogc::landing_page(req).await;
+    }
+
     {
         let cache = LAYERS_CACHE.read().await;
         if cache.is_some() {
@@ -98,80 +230,216 @@ pub async fn layer_list() -> HttpResponse {
 }
 
 
+// Halaman preview interaktif satu layer; viewer MVT ikut ter-embed supaya jalan offline
+pub async fn preview(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let table_name = path.into_inner();
+    ensure_layers_loaded(db_pool.get_ref(), &req).await;
+    if get_layer_detail(table_name.clone()).await.is_none() {
+        return json_error(StatusCode::NOT_FOUND, format!("Layer not found: {}", table_name));
+    }
 
+    let config = serde_json::json!({
+        "layer": table_name,
+        "tilejson": format!("/tiles/{}.json", table_name),
+        "features": format!("/layers/{}/features/", table_name),
+    });
+    // `<` di-escape supaya nama layer tidak bisa menutup tag <script>
+    let config = config.to_string().replace('<', "\\u003c");
+    let title = table_name
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
 
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            include_str!("../../static/preview.html")
+                .replace("{{TITLE}}", &title)
+                .replace("{{CONFIG}}", &config),
+        )
+}
 
-pub async fn get_layers (db_pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
 
-     {
-        let cache = LAYERS_CACHE.read().await;
-        if let Some(layers) = &*cache {
-            return HttpResponse::Ok().json(layers);
-        }
+pub async fn health(db_pool: web::Data<PgPool>) -> HttpResponse {
+    let database_ok = sqlx::query("SELECT 1")
+        .execute(db_pool.get_ref())
+        .await
+        .is_ok();
+    let s3 = s3_status().await;
+
+    // S3 hanya cache: kalau breaker open service tetap jalan, tapi statusnya degraded
+    let status = if !database_ok {
+        "down"
+    } else if s3.enabled && s3.circuit != CircuitState::Closed {
+        "degraded"
+    } else {
+        "ok"
+    };
+
+    let body = serde_json::json!({
+        "status": status,
+        "database": if database_ok { "ok" } else { "unreachable" },
+        "s3": s3,
+    });
+
+    if database_ok {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
     }
+}
 
-    let pool: &PgPool = db_pool.get_ref();
 
-     let rows= sqlx::query(
-       r#"
-        SELECT f_table_schema, f_table_name, f_geometry_column, type, srid
-        FROM public.geometry_columns WHERE f_geometry_column = 'geom_3857'
-        "#
-    )
-    .fetch_all(pool)
-    .await;
+// Endpoint /admin/* butuh ADMIN_TOKEN (header Authorization: Bearer <token>). Tanpa ADMIN_TOKEN
+// endpoint admin nonaktif (404), bukan terbuka untuk siapa saja. None = boleh lanjut.
+fn admin_denied(req: &HttpRequest) -> Option<HttpResponse> {
+    let Some(token) = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()) else {
+        return Some(json_error(StatusCode::NOT_FOUND, "Admin endpoints are disabled: ADMIN_TOKEN is not set"));
+    };
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match given {
+        Some(given) if constant_time_eq(given, &token) => None,
+        _ => Some(HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Unauthorized" }))),
+    }
+}
 
-    let rows = match rows {
-        Ok(t) => t,
+
+// Token dibandingkan lewat hash-nya tanpa berhenti di byte pertama yang berbeda, jadi waktu
+// respons tidak membocorkan isi maupun panjang token
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a.as_bytes()), Sha256::digest(b.as_bytes()));
+    a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+
+pub async fn bump_layer_version(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(denied) = admin_denied(&req) {
+        return denied;
+    }
+
+    // Hanya layer yang dipublikasikan; id lain tidak boleh menambah baris versi
+    let table_name = path.into_inner();
+    ensure_layers_loaded(db_pool.get_ref(), &req).await;
+    if get_layer_detail(table_name.clone()).await.is_none() {
+        let message = layer_error(&table_name)
+            .await
+            .unwrap_or_else(|| format!("Layer not found: {}", table_name));
+        return json_error(StatusCode::NOT_FOUND, message);
+    }
+    match versions::bump_version(db_pool.get_ref(), &table_name).await {
+        Ok(version) => HttpResponse::Ok().json(serde_json::json!({
+            "layer": table_name,
+            "version": version,
+        })),
+        Err(e) if privileges::is_permission_error(&e) => {
+            warn!("Cannot bump version of {}: {}", table_name, e);
+            HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "Layer versions cannot be changed: the database role is read-only"
+            }))
+        }
         Err(e) => {
-            eprintln!("DB error: {:?}", e);
-            return HttpResponse::InternalServerError().body("Failed to fetch tables");
+            error!("Failed to bump version of {}: {:?}", table_name, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Failed to bump layer version" }))
         }
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct CacheFlushQuery {
+    layer: Option<String>,
+}
+
+
+pub async fn cache_stats(req: HttpRequest) -> HttpResponse {
+    if let Some(denied) = admin_denied(&req) {
+        return denied;
+    }
+    HttpResponse::Ok().json(get_cache_report().await)
+}
+
+
+pub async fn flush_cache(req: HttpRequest, query: web::Query<CacheFlushQuery>) -> HttpResponse {
+    if let Some(denied) = admin_denied(&req) {
+        return denied;
+    }
+
+    let layer = query.into_inner().layer;
+    flush_memory_cache(layer.as_deref()).await;
+    info!("Memory cache flushed: {}", layer.as_deref().unwrap_or("all layers"));
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "flushed": layer.as_deref().unwrap_or("all"),
+        "memory": get_cache_report().await.memory,
+    }))
+}
+
+
+pub async fn metrics() -> HttpResponse {
+    let report = get_cache_report().await;
+    let memory = report.memory;
+    let s3 = report.s3;
+    let circuit_open = match s3.circuit {
+        CircuitState::Closed => 0,
+        CircuitState::HalfOpen => 1,
+        CircuitState::Open => 2,
     };
 
-    let mut layers: Vec<Layer> = Vec::new();
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+    };
+    metric("tile_memory_entries", "gauge", "Tiles held in the memory cache", memory.entry_count);
+    metric("tile_memory_bytes", "gauge", "Weighted size of the memory cache in bytes", memory.memory_bytes);
+    metric("tile_memory_hits_total", "counter", "Memory cache hits", memory.hits);
+    metric("tile_memory_misses_total", "counter", "Memory cache misses", memory.misses);
+    metric("tile_memory_evictions_total", "counter", "Memory cache entries evicted by size or TTL", memory.evictions);
+    metric("tile_s3_enabled", "gauge", "Whether the S3 cache tier is configured", s3.enabled as u64);
+    metric("tile_s3_circuit_state", "gauge", "S3 circuit breaker state (0=closed, 1=half-open, 2=open)", circuit_open);
+    metric("tile_s3_circuit_trips_total", "counter", "Times the S3 circuit breaker opened", s3.circuit_trips);
+    metric("tile_s3_hits_total", "counter", "S3 cache hits", s3.hits);
+    metric("tile_s3_misses_total", "counter", "S3 cache misses", s3.misses);
+    metric("tile_s3_errors_total", "counter", "Failed S3 operations", s3.errors);
+    metric("tile_s3_timeouts_total", "counter", "Timed out S3 operations", s3.timeouts);
+    metric("tile_s3_bypassed_total", "counter", "S3 operations skipped while the circuit was open", s3.bypassed);
 
-      
-
-    for t in rows {
-        let schema: String = t.try_get("f_table_schema").unwrap();
-        let table: String = t.try_get("f_table_name").unwrap();
-        let geom_col: String = t.try_get("f_geometry_column").unwrap();
-        let geom_type: String = t.try_get("type").unwrap();
-        let srid: i32 = t.try_get("srid").unwrap();
-
-        // Dynamic ST_Extent
-        let sql = format!(
-            "SELECT \
-                ST_XMin(ST_Extent({geom})) AS minx, \
-                ST_YMin(ST_Extent({geom})) AS miny, \
-                ST_XMax(ST_Extent({geom})) AS maxx, \
-                ST_YMax(ST_Extent({geom})) AS maxy \
-             FROM {}.{}",
-            schema, table,
-            geom = geom_col
-        );
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(out)
+}
 
-        let row = sqlx::query(&sql)
-            .fetch_one(pool)
-            .await
-            .expect("Failed to get extent");
-
-        let bbox = [
-            row.try_get::<f64, _>("minx").unwrap_or(0.0),
-            row.try_get::<f64, _>("miny").unwrap_or(0.0),
-            row.try_get::<f64, _>("maxx").unwrap_or(0.0),
-            row.try_get::<f64, _>("maxy").unwrap_or(0.0),
-        ];
-
-        let base_url = {
-            let c = req.connection_info();
-            format!("{}://{}", c.scheme(), c.host())
-        };
 
-        layers.push(Layer::new(table, geom_col, geom_type, srid, bbox, base_url));
+
+
+
+pub async fn get_layers (db_pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+
+     {
+        let cache = LAYERS_CACHE.read().await;
+        if let Some(layers) = &*cache {
+            return HttpResponse::Ok().json(layers);
+        }
     }
 
+    let layers = match load_layers(db_pool.get_ref(), req).await {
+        Ok(layers) => layers,
+        Err(e) => {
+            error!("DB error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch tables");
+        }
+    };
 
     {
         let mut cache = LAYERS_CACHE.write().await;
@@ -185,68 +453,164 @@ pub async fn get_layers (db_pool: web::Data<PgPool>, req: HttpRequest) -> impl R
 
 
 pub async fn load_layers(db_pool: &PgPool, req: HttpRequest) -> Result<Vec<Layer>, sqlx::Error> {
-    let rows = sqlx::query(
-        r#"
-        SELECT f_table_schema, f_table_name, f_geometry_column, type, srid
-        FROM public.geometry_columns WHERE f_geometry_column = 'geom_3857'
-        "#
-    )
-    .fetch_all(db_pool)
-    .await?;
+    // Satu layer per (tabel, kolom geometri); geom_3857 dibaca lewat relasi layer (lihat web::prep)
+    let sources = prep::layer_sources(db_pool).await?;
 
     let mut layers: Vec<Layer> = Vec::new();
+    let mut errors: Vec<LayerError> = Vec::new();
     // utils::cleanup_all_geom_3857(&db_pool).await?;
 
-    for t in rows {
-        let schema: String = t.try_get("f_table_schema")?;
-        let table: String = t.try_get("f_table_name")?;
-        let geom_col: String = t.try_get("f_geometry_column")?;
-        let geom_type: String = t.try_get("type")?;
-        let srid: i32 = t.try_get("srid")?;
-
-
-        // Create Index if not Exist
-        
-        // utils::create_geom_3857_index(db_pool, &schema, &table, &geom_col, &geom_type).await?;
-        
-        
-
-
-        // Generate Min Max Bounds
-        let sql = format!(
-            "SELECT \
-                ST_XMin(ST_Extent({geom})) AS minx, \
-                ST_YMin(ST_Extent({geom})) AS miny, \
-                ST_XMax(ST_Extent({geom})) AS maxx, \
-                ST_YMax(ST_Extent({geom})) AS maxy \
-             FROM {}.{}",
-            schema, table,
-            geom = geom_col
-        );
+    let base_url = {
+        let c = req.connection_info();
+        format!("{}://{}", c.scheme(), c.host())
+    };
+
+    // Satu tabel yang bermasalah (SRID 0, tipe tidak didukung, query gagal) tidak menghentikan layer lain
+    for source in sources {
+        let result = match source.problem() {
+            Some(problem) => Err(problem),
+            None => load_layer(db_pool, &source, &base_url).await.map_err(|e| e.to_string()),
+        };
+        match result {
+            Ok(layer) => layers.push(layer),
+            Err(error) => {
+                warn!("Layer {} cannot be published: {}", source.id, error);
+                errors.push(LayerError {
+                    layer: source.id,
+                    schema: source.schema,
+                    table: source.table,
+                    geom_column: source.geom_column,
+                    geom_type: source.geom_type,
+                    srid: source.srid,
+                    error,
+                });
+            }
+        }
+    }
+    *LAYER_ERRORS.write().await = errors;
+    Ok(layers)
+}
+
 
-        let row = sqlx::query(&sql)
-            .fetch_one(db_pool)
-            .await?;
+async fn load_layer(db_pool: &PgPool, source: &LayerSource, base_url: &str) -> Result<Layer, sqlx::Error> {
+    let mut layer = Layer::new(source, [0.0; 4], base_url.to_string());
+    let columns = layer_columns(db_pool, &layer).await?;
+    (layer.prep, layer.relation) = prep::resolve(db_pool, source, layer.prep, &columns).await?;
+
+    // Generate Min Max Bounds (3857)
+    let row = sqlx::query(&prep::extent_sql(source, &layer))
+        .fetch_one(db_pool)
+        .await?;
+
+    let bbox = [
+        row.try_get::<f64, _>("minx").unwrap_or(0.0),
+        row.try_get::<f64, _>("miny").unwrap_or(0.0),
+        row.try_get::<f64, _>("maxx").unwrap_or(0.0),
+        row.try_get::<f64, _>("maxy").unwrap_or(0.0),
+    ];
+
+    layer.bbox = bbox;
+    layer.tms_bounds = layer_tms_bounds(db_pool, &bbox).await;
+    layer.feature_id = feature_id::resolve(db_pool, &layer, &columns).await?;
+    layer.properties = layer_tile_properties(&layer, &columns);
+    layer.tile_queries = tile_query::render_all(&layer);
+    Ok(layer)
+}
 
-        let bbox = [
-            row.try_get::<f64, _>("minx").unwrap_or(0.0),
-            row.try_get::<f64, _>("miny").unwrap_or(0.0),
-            row.try_get::<f64, _>("maxx").unwrap_or(0.0),
-            row.try_get::<f64, _>("maxy").unwrap_or(0.0),
-        ];
 
-        let base_url = {
-            let c = req.connection_info();
-            format!("{}://{}", c.scheme(), c.host())
-        };
+// Status publikasi semua tabel geometri: layer yang tampil dan yang gagal beserta alasannya
+pub async fn layer_status(db_pool: web::Data<PgPool>, req: HttpRequest) -> HttpResponse {
+    ensure_layers_loaded(db_pool.get_ref(), &req).await;
+    let published: Vec<String> = LAYERS_CACHE
+        .read()
+        .await
+        .iter()
+        .flatten()
+        .map(|l| l.table_name.clone())
+        .collect();
+    let failed = LAYER_ERRORS.read().await.clone();
+
+    HttpResponse::Ok()
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .json(serde_json::json!({
+            "published": published,
+            "failed": failed,
+            "database": privileges::current().map(|c| c.to_json()),
+        }))
+}
+
+
+// Kolom `properties` dari konfigurasi layer yang benar-benar ada; gid & geom dipakai query tile
+fn layer_tile_properties(layer: &Layer, columns: &[Column]) -> Vec<Column> {
+    let names = config::layer_config(&layer.table_name).properties.unwrap_or_default();
+    names
+        .iter()
+        .filter_map(|name| {
+            let column = columns
+                .iter()
+                .find(|c| &c.name == name && !c.is_geometry() && c.name != "gid" && c.name != "geom");
+            if column.is_none() {
+                warn!("Property {} of layer {} is not a usable column, skipped", name, layer.table_name);
+            }
+            column.cloned()
+        })
+        .collect()
+}
 
-        layers.push(Layer::new(table, geom_col, geom_type, srid, bbox, base_url));
+
+// Extent layer (3857) ditransform ke CRS setiap tile matrix set, untuk cek tile di luar bounds.
+// Sisi envelope dipecah dulu (64 segmen per sisi terpanjang): di proyeksi lain sisi lurus
+// bisa melengkung keluar dari empat titik sudut, sehingga bounds dari sudut saja terlalu sempit.
+// TMS yang gagal ditransform (mis. extent di luar area CRS) dilewati: tile-nya tidak dicek.
+async fn layer_tms_bounds(db_pool: &PgPool, bbox: &[f64; 4]) -> HashMap<String, [f64; 4]> {
+    let mut bounds = HashMap::new();
+
+    for tms in tms::tile_matrix_sets() {
+        let Some(srid) = tms.srid() else { continue };
+        if srid == 3857 {
+            bounds.insert(tms.id.clone(), *bbox);
+            continue;
+        }
+
+        let row = sqlx::query(
+            r#"
+            SELECT ST_XMin(b) AS minx, ST_YMin(b) AS miny, ST_XMax(b) AS maxx, ST_YMax(b) AS maxy
+            FROM (
+                SELECT ST_Transform(
+                    ST_Segmentize(ST_MakeEnvelope($1, $2, $3, $4, 3857), GREATEST($3 - $1, $4 - $2, 1.0) / 64),
+                    $5
+                ) AS b
+            ) t
+            "#
+        )
+        .bind(bbox[0])
+        .bind(bbox[1])
+        .bind(bbox[2])
+        .bind(bbox[3])
+        .bind(srid)
+        .fetch_one(db_pool)
+        .await;
+
+        match row {
+            Ok(row) => {
+                if let (Ok(minx), Ok(miny), Ok(maxx), Ok(maxy)) = (
+                    row.try_get::<f64, _>("minx"),
+                    row.try_get::<f64, _>("miny"),
+                    row.try_get::<f64, _>("maxx"),
+                    row.try_get::<f64, _>("maxy"),
+                ) {
+                    bounds.insert(tms.id.clone(), [minx, miny, maxx, maxy]);
+                }
+            }
+            Err(e) => warn!("Failed to compute bounds in {}: {}", tms.id, e),
+        }
     }
-    Ok(layers)
+
+    bounds
 }
 
 
-async fn get_layer_detail(table_name: String) -> Option<Layer> {
+pub(crate) async fn get_layer_detail(table_name: String) -> Option<Layer> {
     // 1️⃣ Ambil guard terlebih dahulu
     let cache_guard = LAYERS_CACHE.read().await;
     // 2️⃣ Ambil reference ke Vec<Layer>
@@ -270,11 +634,24 @@ async fn get_layer_detail(table_name: String) -> Option<Layer> {
 }
 
 
+// Semua layer dari LAYERS_CACHE (dimuat dulu jika belum)
+pub(crate) async fn all_layers(pool: &PgPool, req: &HttpRequest) -> Vec<Layer> {
+    ensure_layers_loaded(pool, req).await;
+    LAYERS_CACHE.read().await.clone().unwrap_or_default()
+}
+
+
+pub(crate) fn base_url(req: &HttpRequest) -> String {
+    let c = req.connection_info();
+    format!("{}://{}", c.scheme(), c.host())
+}
+
+
 pub async fn get_layer_detail_from_db(db_pool: &PgPool, table_name: String, base_url: &str) -> Option<Layer> {
     // Query ke database langsung, ambil hanya table_name & geom_column
     let row = match sqlx::query(
         r#"
-        SELECT f_table_name, f_geometry_column
+        SELECT f_table_schema, f_table_name, f_geometry_column
         FROM public.geometry_columns
         WHERE f_table_name = $1
         "#
@@ -290,18 +667,24 @@ pub async fn get_layer_detail_from_db(db_pool: &PgPool, table_name: String, base
         }
     };
 
+    let schema: String = row.try_get("f_table_schema").ok()?;
     let table_name: String = row.try_get("f_table_name").ok()?;
     let geom_column: String = row.try_get("f_geometry_column").ok()?;
 
     // Untuk field lain, bisa pakai default / placeholder
-    let layer = Layer::new(
-        table_name,
+    let source = LayerSource {
+        id: table_name.clone(),
+        schema,
+        table: table_name,
         geom_column,
-        "UNKNOWN".to_string(), // placeholder
-        0 as i32,                          // placeholder
-        [0.0, 0.0, 0.0, 0.0],      // placeholder
-        base_url.to_string()
-    );
+        geom_type: "UNKNOWN".to_string(), // placeholder
+        srid: 0_i32,                      // placeholder
+        relkind: None,
+        geography: false,
+        prepared_column: prep::GEOM_3857.to_string(),
+        selectable: true,
+    };
+    let layer = Layer::new(&source, [0.0, 0.0, 0.0, 0.0], base_url.to_string());
 
     Some(layer)
 }
@@ -311,16 +694,86 @@ pub async fn get_layer_detail_from_db(db_pool: &PgPool, table_name: String, base
 pub async fn get_vector_tile(
     db_pool: web::Data<PgPool>,
     path: web::Path<TilePath>,
-) -> impl Responder {
+    req: HttpRequest,
+) -> HttpResponse {
+    let params = path.into_inner();
+    serve_tile(db_pool.get_ref(), &req, tms::WEB_MERCATOR_QUAD, &params.table_name, params.z, params.x, params.y).await
+}
+
+
+pub async fn get_tms_vector_tile(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<TmsTilePath>,
+    req: HttpRequest,
+) -> HttpResponse {
     let params = path.into_inner();
+    serve_tile(db_pool.get_ref(), &req, &params.tms, &params.table_name, params.z, params.x, params.y).await
+}
+
+
+pub async fn serve_tile(
+    db_pool: &PgPool,
+    req: &HttpRequest,
+    tms_id: &str,
+    table_name: &str,
+    z: u32,
+    x: u32,
+    y: u32,
+) -> HttpResponse {
     let s3_config = S3Config::default();
 
+    let Some(tile_matrix_set) = tms::get_tile_matrix_set(tms_id) else {
+        return json_error(StatusCode::NOT_FOUND, format!("Tile matrix set not found: {}", tms_id));
+    };
+    let Some(srid) = tile_matrix_set.srid() else {
+        return json_error(StatusCode::NOT_FOUND, format!("Tile matrix set has no supported CRS: {}", tms_id));
+    };
+
+    // VALIDASI sebelum menyentuh cache maupun database
+    if let Err(e) = tile_matrix_set.validate_tile(z, x, y) {
+        return json_error(StatusCode::BAD_REQUEST, e.to_string());
+    }
+
+    ensure_layers_loaded(db_pool, req).await;
+    let layer = match get_layer_detail(table_name.to_string()).await {
+        Some(l) => l,
+        None => {
+            let message = layer_error(table_name)
+                .await
+                .unwrap_or_else(|| format!("Layer not found: {}", table_name));
+            return json_error(StatusCode::NOT_FOUND, message);
+        }
+    };
+
+    if z < layer.minzoom || z > layer.maxzoom {
+        return json_error(
+            StatusCode::NOT_FOUND,
+            format!(
+                "Zoom {} is outside the zoom range of layer {} ({}-{})",
+                z, layer.table_name, layer.minzoom, layer.maxzoom
+            ),
+        );
+    }
+
+    // Tile di luar extent layer (plus buffer MVT 256/4096) pasti kosong
+    let Some(tile_bbox) = tile_matrix_set.tile_bbox(z, x, y) else {
+        return json_error(StatusCode::BAD_REQUEST, format!("Invalid tile {}/{}/{}", z, x, y));
+    };
+    if let Some(bounds) = layer.tms_bounds.get(tms_id)
+        && !tile_bbox.expand(256.0 / 4096.0).intersects(bounds)
+    {
+        return HttpResponse::NoContent()
+            .insert_header(("Access-Control-Allow-Origin", "*"))
+            .finish();
+    }
 
+    let version = versions::current_version(table_name).await;
+    let cache_key = TileCacheKey::new(table_name, &version, tms_id, z, x, y);
 
-    info!("Tile request: {}/{}/{}/{}", params.table_name, params.z, params.x, params.y);
+    info!("Tile request: {} (v{})", cache_key, version);
 
     // GET DATA FROM CACHE FIRST
-    if let Some(cached_tile) = get_from_memory_cache(&params.table_name, params.z, params.x, params.y).await {
+    if let Some(cached_tile) = get_from_memory_cache(&cache_key).await {
         if cached_tile.is_empty() {
             return HttpResponse::NoContent()
                 .insert_header(("Access-Control-Allow-Origin", "*"))
@@ -338,11 +791,11 @@ pub async fn get_vector_tile(
 
 
     // GET DATA FROM S3
-    match get_from_s3(&s3_config, &params.table_name, params.z, params.x, params.y).await {
+    match get_from_s3(&s3_config, &cache_key).await {
         Ok(Some(s3_tile)) => {
             info!("✓ S3 cache hit: {} bytes", s3_tile.len());
             
-            put_to_memory_cache(&params.table_name, params.z, params.x, params.y, s3_tile.clone()).await;
+            put_to_memory_cache(&cache_key, s3_tile.clone()).await;
             
             if s3_tile.is_empty() {
                 return HttpResponse::NoContent()
@@ -362,42 +815,23 @@ pub async fn get_vector_tile(
             info!("✗ S3 cache miss");
         }
         Err(e) => {
-            error!("✗ S3 error: {:?}", e);
+            warn!("✗ S3 error: {}", e);
         }
     }
 
 
-
-
-
-
-
-
-
-
-
-    let layer = match get_layer_detail(params.table_name.clone()).await {
-        Some(l) => l,
-        None => {
-            error!("Layer not found:");
-            return HttpResponse::NotFound().body("Layer not found");
-        }
+    // Query disiapkan saat layer dimuat; SRID tile matrix set yang tidak dikenal saat itu dirender di sini
+    let query = match layer.tile_queries.get(&srid) {
+        Some(query) => Cow::Borrowed(query.as_str()),
+        None => Cow::Owned(tile_query::render(&layer, srid)),
     };
-
-    let tile_bbox = utils::tile_to_bbox(params.z, params.x, params.y);
-
-    match sqlx::query_scalar::<_, Vec<u8>>(
-        "SELECT public.get_tile($1, $2, $3, $4, $5, $6, $7, $8)"
-    )
-    .bind(&layer.table_name)
-    .bind(params.z as i32)
-    .bind(params.x as i32)
-    .bind(params.y as i32)
+    match sqlx::query_scalar::<_, Vec<u8>>(&query)
     .bind(tile_bbox.minx)
     .bind(tile_bbox.miny)
     .bind(tile_bbox.maxx)
     .bind(tile_bbox.maxy)
-    .fetch_one(db_pool.get_ref())
+    .bind(z as i32)
+    .fetch_one(db_pool)
     .await
     {
         Ok(tile) => {
@@ -405,19 +839,16 @@ pub async fn get_vector_tile(
 
             //SAVE TILE TO CACHE
             
-            put_to_memory_cache(params.table_name.as_str(), params.z, params.x, params.y, tile.clone()).await;
+            put_to_memory_cache(&cache_key, tile.clone()).await;
+
 
 
-            let table_name_clone = params.table_name.clone();
             let s3_config_clone = s3_config.clone();
             let tile_clone = tile.clone();
             tokio::spawn(async move {
                 if let Err(e) = put_to_s3(
                     &s3_config_clone,
-                    &table_name_clone,
-                    params.z,
-                    params.x,
-                    params.y,
+                    &cache_key,
                     tile_clone,
                 ).await {
                     error!("Failed to save to S3: {:?}", e);
@@ -440,9 +871,7 @@ pub async fn get_vector_tile(
         }
         Err(e) => {
             error!("✗ Database error: {:?}", e);
-            HttpResponse::InternalServerError()
-                .content_type("text/plain")
-                .body(format!("Database error: {}", e))
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,327 @@
+// OGC WMTS 1.0.0 untuk client lama, di atas katalog layer & tile matrix set yang sama.
+//
+//   /wmts?SERVICE=WMTS&REQUEST=GetCapabilities      capabilities (KVP)
+//   /wmts/1.0.0/WMTSCapabilities.xml                capabilities (RESTful)
+//   /wmts?SERVICE=WMTS&REQUEST=GetTile&LAYER=..&TILEMATRIXSET=..&TILEMATRIX=..&TILEROW=..&TILECOL=..
+//
+// GetTile diteruskan ke serve_tile, jadi cache & validasi sama dengan /tiles.
+// ResourceURL menunjuk ke endpoint tile OGC API (/collections/{layer}/tiles/...).
+use actix_web::{web, HttpResponse, HttpRequest};
+use actix_web::http::StatusCode;
+use sqlx::PgPool;
+use super::ogc::{layer_bbox_crs84, tile_matrix_limits};
+use super::tms::{self, TileMatrixSet};
+use super::web_handler::{Layer, all_layers, base_url, serve_tile};
+
+const MVT_MEDIA_TYPE: &str = "application/vnd.mapbox-vector-tile";
+
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+
+// WMTS 1.0.0 memakai URN: .../def/crs/EPSG/0/3857 -> urn:ogc:def:crs:EPSG::3857
+fn crs_urn(uri: &str) -> String {
+    let parts: Vec<&str> = uri.trim_end_matches('/').rsplit('/').take(3).collect();
+    match parts.as_slice() {
+        [code, version, authority] if uri.contains("/def/crs/") => {
+            let version = if *version == "0" { "" } else { version };
+            format!("urn:ogc:def:crs:{}:{}:{}", authority, version, code)
+        }
+        _ => uri.to_string(),
+    }
+}
+
+
+// OWS ExceptionReport, format error yang dipahami client WMTS
+fn exception(status: StatusCode, code: &str, locator: &str, message: &str) -> HttpResponse {
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<ows:ExceptionReport xmlns:ows="http://www.opengis.net/ows/1.1" version="2.0.0" xml:lang="en">
+  <ows:Exception exceptionCode="{}" locator="{}">
+    <ows:ExceptionText>{}</ows:ExceptionText>
+  </ows:Exception>
+</ows:ExceptionReport>
+"#,
+        code,
+        xml_escape(locator),
+        xml_escape(message)
+    );
+    HttpResponse::build(status)
+        .content_type("application/xml")
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .body(body)
+}
+
+
+// WMTS hanya mendukung grid dengan origin kiri atas
+fn wmts_compatible(tms: &TileMatrixSet) -> bool {
+    tms.tile_matrices.iter().all(|m| m.corner_of_origin != "bottomLeft")
+}
+
+
+fn layer_xml(layer: &Layer, base: &str) -> String {
+    let id = xml_escape(&layer.table_name);
+    let bbox = layer_bbox_crs84(layer);
+
+    let mut links = String::new();
+    for tms in tms::tile_matrix_sets().filter(|t| wmts_compatible(t)) {
+        let limits: String = tile_matrix_limits(layer, tms)
+            .iter()
+            .map(|l| format!(
+                "\n          <TileMatrixLimits><TileMatrix>{}</TileMatrix><MinTileRow>{}</MinTileRow><MaxTileRow>{}</MaxTileRow><MinTileCol>{}</MinTileCol><MaxTileCol>{}</MaxTileCol></TileMatrixLimits>",
+                xml_escape(&l.tile_matrix), l.min_row, l.max_row, l.min_col, l.max_col
+            ))
+            .collect();
+        links.push_str(&format!(
+            "\n      <TileMatrixSetLink>\n        <TileMatrixSet>{}</TileMatrixSet>\n        <TileMatrixSetLimits>{}\n        </TileMatrixSetLimits>\n      </TileMatrixSetLink>",
+            xml_escape(&tms.id),
+            limits
+        ));
+    }
+
+    format!(
+        r#"
+    <Layer>
+      <ows:Title>{id}</ows:Title>
+      <ows:WGS84BoundingBox>
+        <ows:LowerCorner>{} {}</ows:LowerCorner>
+        <ows:UpperCorner>{} {}</ows:UpperCorner>
+      </ows:WGS84BoundingBox>
+      <ows:Identifier>{id}</ows:Identifier>
+      <ows:BoundingBox crs="urn:ogc:def:crs:EPSG::3857">
+        <ows:LowerCorner>{} {}</ows:LowerCorner>
+        <ows:UpperCorner>{} {}</ows:UpperCorner>
+      </ows:BoundingBox>
+      <Style isDefault="true">
+        <ows:Identifier>default</ows:Identifier>
+      </Style>
+      <Format>{mvt}</Format>{links}
+      <ResourceURL format="{mvt}" resourceType="tile" template="{base}/collections/{id}/tiles/{{TileMatrixSet}}/{{TileMatrix}}/{{TileRow}}/{{TileCol}}"/>
+    </Layer>"#,
+        bbox[0], bbox[1], bbox[2], bbox[3],
+        layer.bbox[0], layer.bbox[1], layer.bbox[2], layer.bbox[3],
+        id = id,
+        mvt = MVT_MEDIA_TYPE,
+        links = links,
+        base = xml_escape(base),
+    )
+}
+
+
+fn tile_matrix_set_xml(tms: &TileMatrixSet) -> String {
+    let well_known = if tms.id == tms::WEB_MERCATOR_QUAD {
+        "\n      <WellKnownScaleSet>urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible</WellKnownScaleSet>"
+    } else {
+        ""
+    };
+
+    // TopLeftCorner mengikuti urutan axis CRS, sama seperti pointOfOrigin di TMS 2.0
+    let matrices: String = tms
+        .tile_matrices
+        .iter()
+        .map(|m| format!(
+            r#"
+      <TileMatrix>
+        <ows:Identifier>{}</ows:Identifier>
+        <ScaleDenominator>{}</ScaleDenominator>
+        <TopLeftCorner>{} {}</TopLeftCorner>
+        <TileWidth>{}</TileWidth>
+        <TileHeight>{}</TileHeight>
+        <MatrixWidth>{}</MatrixWidth>
+        <MatrixHeight>{}</MatrixHeight>
+      </TileMatrix>"#,
+            xml_escape(&m.id),
+            m.scale_denominator,
+            m.point_of_origin[0],
+            m.point_of_origin[1],
+            m.tile_width,
+            m.tile_height,
+            m.matrix_width,
+            m.matrix_height
+        ))
+        .collect();
+
+    format!(
+        r#"
+    <TileMatrixSet>
+      <ows:Identifier>{}</ows:Identifier>
+      <ows:SupportedCRS>{}</ows:SupportedCRS>{}{}
+    </TileMatrixSet>"#,
+        xml_escape(&tms.id),
+        xml_escape(&crs_urn(tms.crs_uri())),
+        well_known,
+        matrices
+    )
+}
+
+
+fn capabilities_xml(layers: &[Layer], base: &str) -> String {
+    let kvp = xml_escape(&format!("{}/wmts?", base));
+    let operation = |name: &str| format!(
+        r#"
+    <ows:Operation name="{}">
+      <ows:DCP>
+        <ows:HTTP>
+          <ows:Get xlink:href="{}">
+            <ows:Constraint name="GetEncoding">
+              <ows:AllowedValues><ows:Value>KVP</ows:Value></ows:AllowedValues>
+            </ows:Constraint>
+          </ows:Get>
+        </ows:HTTP>
+      </ows:DCP>
+    </ows:Operation>"#,
+        name, kvp
+    );
+
+    let layers_xml: String = layers.iter().map(|l| layer_xml(l, base)).collect();
+    let sets_xml: String = tms::tile_matrix_sets()
+        .filter(|t| wmts_compatible(t))
+        .map(tile_matrix_set_xml)
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Capabilities xmlns="http://www.opengis.net/wmts/1.0" xmlns:ows="http://www.opengis.net/ows/1.1" xmlns:xlink="http://www.w3.org/1999/xlink" version="1.0.0">
+  <ows:ServiceIdentification>
+    <ows:Title>Vector Tiles Service</ows:Title>
+    <ows:ServiceType>OGC WMTS</ows:ServiceType>
+    <ows:ServiceTypeVersion>1.0.0</ows:ServiceTypeVersion>
+  </ows:ServiceIdentification>
+  <ows:OperationsMetadata>{}{}
+  </ows:OperationsMetadata>
+  <Contents>{}{}
+  </Contents>
+  <ServiceMetadataURL xlink:href="{}/wmts/1.0.0/WMTSCapabilities.xml"/>
+</Capabilities>
+"#,
+        operation("GetCapabilities"),
+        operation("GetTile"),
+        layers_xml,
+        sets_xml,
+        xml_escape(base)
+    )
+}
+
+
+async fn capabilities_response(db_pool: &PgPool, req: &HttpRequest) -> HttpResponse {
+    let layers = all_layers(db_pool, req).await;
+    HttpResponse::Ok()
+        .content_type("application/xml")
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .body(capabilities_xml(&layers, &base_url(req)))
+}
+
+
+pub async fn capabilities(db_pool: web::Data<PgPool>, req: HttpRequest) -> HttpResponse {
+    capabilities_response(db_pool.get_ref(), &req).await
+}
+
+
+// Nama parameter KVP tidak case-sensitive
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+
+pub async fn kvp(
+    db_pool: web::Data<PgPool>,
+    query: web::Query<Vec<(String, String)>>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let params = query.into_inner();
+
+    if let Some(service) = param(&params, "service")
+        && !service.eq_ignore_ascii_case("WMTS")
+    {
+        return exception(StatusCode::BAD_REQUEST, "InvalidParameterValue", "service", "SERVICE must be WMTS");
+    }
+
+    let Some(request) = param(&params, "request") else {
+        return exception(StatusCode::BAD_REQUEST, "MissingParameterValue", "request", "Missing REQUEST parameter");
+    };
+
+    if request.eq_ignore_ascii_case("GetCapabilities") {
+        return capabilities_response(db_pool.get_ref(), &req).await;
+    }
+    if !request.eq_ignore_ascii_case("GetTile") {
+        return exception(
+            StatusCode::NOT_IMPLEMENTED,
+            "OperationNotSupported",
+            "request",
+            &format!("Operation {} is not supported", request),
+        );
+    }
+
+    match tile_request(&params) {
+        Ok((layer, tms, z, col, row)) => serve_tile(db_pool.get_ref(), &req, &tms.id, layer, z, col, row).await,
+        Err(resp) => resp,
+    }
+}
+
+
+fn required<'a>(params: &'a [(String, String)], name: &str) -> Result<&'a str, HttpResponse> {
+    param(params, name).ok_or_else(|| exception(
+        StatusCode::BAD_REQUEST,
+        "MissingParameterValue",
+        name,
+        &format!("Missing {} parameter", name.to_uppercase()),
+    ))
+}
+
+
+// Parameter GetTile -> (layer, tile matrix set, z, col, row)
+fn tile_request(params: &[(String, String)]) -> Result<(&str, &'static TileMatrixSet, u32, u32, u32), HttpResponse> {
+    let layer = required(params, "layer")?;
+    let tms_id = required(params, "tilematrixset")?;
+    let tile_matrix = required(params, "tilematrix")?;
+    let tile_row = required(params, "tilerow")?;
+    let tile_col = required(params, "tilecol")?;
+
+    if let Some(format) = param(params, "format")
+        && format != MVT_MEDIA_TYPE
+    {
+        return Err(exception(
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+            "format",
+            &format!("Only {} is supported", MVT_MEDIA_TYPE),
+        ));
+    }
+
+    let Some(tms) = tms::get_tile_matrix_set(tms_id).filter(|t| wmts_compatible(t)) else {
+        return Err(exception(
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+            "tilematrixset",
+            &format!("Unknown tile matrix set {}", tms_id),
+        ));
+    };
+    let Some(z) = tms.matrix_index(tile_matrix) else {
+        return Err(exception(
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+            "tilematrix",
+            &format!("Tile matrix {} does not exist in {}", tile_matrix, tms.id),
+        ));
+    };
+    let (Ok(row), Ok(col)) = (tile_row.parse::<u32>(), tile_col.parse::<u32>()) else {
+        return Err(exception(
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+            "tilerow",
+            "TILEROW and TILECOL must be non-negative integers",
+        ));
+    };
+
+    Ok((layer, tms, z, col, row))
+}